@@ -0,0 +1,200 @@
+/* C API of perceptual-hashing, generated by cbindgen from src/ffi.rs - do not edit */
+
+#ifndef PERCEPTUAL_HASHING_H
+#define PERCEPTUAL_HASHING_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Returned by `ph_hash_algorithm` for a null hash.
+#define PH_NO_ALGORITHM UINT32_MAX
+
+// Result of a call; the values are stable.
+typedef enum PhStatus {
+  PH_STATUS_OK = 0,
+  // A null pointer, an unknown algorithm or a malformed hex string.
+  PH_STATUS_INVALID_ARGUMENT = 1,
+  PH_STATUS_IO = 2,
+  // The image could not be decoded.
+  PH_STATUS_DECODE = 3,
+  // The hashes come from different algorithms or settings.
+  PH_STATUS_INCOMPATIBLE = 4,
+  PH_STATUS_NOT_FOUND = 5,
+  // A bug in the library; the message says where.
+  PH_STATUS_INTERNAL = 6,
+} PhStatus;
+
+// Algorithms, as passed to the functions taking an `algorithm`; the values are stable.
+typedef enum PhAlgorithm {
+  PH_ALGORITHM_DHASH = 0,
+  PH_ALGORITHM_PHASH = 1,
+  PH_ALGORITHM_BLOCKHASH = 2,
+  PH_ALGORITHM_PDQ = 3,
+  PH_ALGORITHM_RADIAL = 4,
+  PH_ALGORITHM_MARR_HILDRETH = 5,
+  PH_ALGORITHM_COLOR_MOMENTS = 6,
+  PH_ALGORITHM_SEGMENTS = 7,
+} PhAlgorithm;
+
+// A hash of one algorithm.
+typedef struct PhHash PhHash;
+
+// An index file in memory.
+typedef struct PhIndex PhIndex;
+
+// A file of an index close to a query.
+typedef struct PhMatch {
+  // The path as it was inserted, the raw bytes on Unix; null if it holds a nul byte.
+  char *path;
+  uint32_t distance;
+} PhMatch;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Message of the last failed call on this thread, or null. Valid until the next failing call.
+const char *ph_last_error(void);
+
+// Largest distance between two hashes of `algorithm` with the default settings, 0 for unknown algorithms.
+uint32_t ph_max_distance(uint32_t algorithm);
+
+// Hashes the image at `path` (the first frame of animations) with the default settings.
+//
+// # Safety
+// `path` is a null-terminated string and `out` points to writable memory.
+enum PhStatus ph_hash_file(const char *path, uint32_t algorithm, struct PhHash **out);
+
+// Like `ph_hash_file`, for an encoded image in memory.
+//
+// # Safety
+// `data` points to `len` readable bytes and `out` points to writable memory.
+enum PhStatus ph_hash_buffer(const uint8_t *data,
+                             size_t len,
+                             uint32_t algorithm,
+                             struct PhHash **out);
+
+// Parses a hex string as `ph_hash_to_hex` writes it.
+//
+// # Safety
+// `hex` is a null-terminated string and `out` points to writable memory.
+enum PhStatus ph_hash_from_hex(uint32_t algorithm, const char *hex, struct PhHash **out);
+
+// The hash as hex, to be released with `ph_string_free`; null when `hash` is null.
+//
+// # Safety
+// `hash` is null or was returned by this library and not freed.
+char *ph_hash_to_hex(const struct PhHash *hash);
+
+// The `PhAlgorithm` of the hash, `PH_NO_ALGORITHM` when `hash` is null.
+//
+// # Safety
+// `hash` is null or was returned by this library and not freed.
+uint32_t ph_hash_algorithm(const struct PhHash *hash);
+
+// Distance between two hashes of the same algorithm: the Hamming distance for bit hashes, 0 meaning identical.
+//
+// # Safety
+// `a` and `b` were returned by this library and not freed, `out` points to writable memory.
+enum PhStatus ph_distance(const struct PhHash *a,
+                          const struct PhHash *b,
+                          uint32_t *out);
+
+// # Safety
+// `hash` is null or was returned by this library and not freed.
+void ph_hash_free(struct PhHash *hash);
+
+// # Safety
+// `text` is null or was returned by this library and not freed.
+void ph_string_free(char *text);
+
+// An empty index with the default settings.
+struct PhIndex *ph_index_new(void);
+
+// Reads an index file, e.g. one written by `perceptual-hashing index`.
+//
+// # Safety
+// `path` is a null-terminated string and `out` points to writable memory.
+enum PhStatus ph_index_load(const char *path, struct PhIndex **out);
+
+// # Safety
+// `index` was returned by this library and not freed, `path` is a null-terminated string.
+enum PhStatus ph_index_save(const struct PhIndex *index, const char *path);
+
+// Number of files in the index, 0 when `index` is null.
+//
+// # Safety
+// `index` is null or was returned by this library and not freed.
+size_t ph_index_len(const struct PhIndex *index);
+
+// Like `ph_hash_file`, with the settings of the index, so that the hash can be queried in an index
+// file written with other settings than the defaults.
+//
+// # Safety
+// `index` was returned by this library and not freed, `path` is a null-terminated string and
+// `out` points to writable memory.
+enum PhStatus ph_index_hash_file(const struct PhIndex *index,
+                                 const char *path,
+                                 uint32_t algorithm,
+                                 struct PhHash **out);
+
+// Like `ph_hash_buffer`, with the settings of the index.
+//
+// # Safety
+// `index` was returned by this library and not freed, `data` points to `len` readable bytes and
+// `out` points to writable memory.
+enum PhStatus ph_index_hash_buffer(const struct PhIndex *index,
+                                   const uint8_t *data,
+                                   size_t len,
+                                   uint32_t algorithm,
+                                   struct PhHash **out);
+
+// Hashes the image at `path` with every algorithm and the settings of the index, replacing its entry.
+//
+// # Safety
+// `index` was returned by this library and not freed, `path` is a null-terminated string.
+enum PhStatus ph_index_insert_file(struct PhIndex *index,
+                                   const char *path);
+
+// Like `ph_index_insert_file`, for an encoded image in memory stored under `name`.
+//
+// # Safety
+// `index` was returned by this library and not freed, `name` is a null-terminated string and
+// `data` points to `len` readable bytes.
+enum PhStatus ph_index_insert_buffer(struct PhIndex *index,
+                                     const char *name,
+                                     const uint8_t *data,
+                                     size_t len);
+
+// # Safety
+// `index` was returned by this library and not freed, `path` is a null-terminated string.
+enum PhStatus ph_index_remove(struct PhIndex *index, const char *path);
+
+// Files of the index within `max_distance` of `query`, closest first, in an array of `*out_len`
+// matches to be released with `ph_matches_free`. The query has to be computed with the settings of
+// the index, which are the defaults unless the index file says otherwise; `ph_index_hash_file` uses them.
+//
+// # Safety
+// `index` and `query` were returned by this library and not freed, `out` and `out_len` point to writable memory.
+enum PhStatus ph_index_query(const struct PhIndex *index,
+                             const struct PhHash *query,
+                             uint32_t max_distance,
+                             struct PhMatch **out,
+                             size_t *out_len);
+
+// # Safety
+// `matches` and `len` are those returned by `ph_index_query`, not freed before.
+void ph_matches_free(struct PhMatch *matches, size_t len);
+
+// # Safety
+// `index` is null or was returned by this library and not freed.
+void ph_index_free(struct PhIndex *index);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* PERCEPTUAL_HASHING_H */
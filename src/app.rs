@@ -2,8 +2,14 @@ use std::time::{Duration, Instant};
 use std::thread::sleep;
 use std::path::PathBuf;
 
-use crate::handler::handle::HashingType;
-use crate::handler::similarity_analyzer::SimilarityAnalyzer;
+use perceptual_hashing::handler::handle::{HashingType, MatchOptions};
+use perceptual_hashing::handler::composite::CompositeRule;
+use perceptual_hashing::handler::score::ScoreOptions;
+use perceptual_hashing::handler::similarity_analyzer::SimilarityAnalyzer;
+use perceptual_hashing::preprocessing::preprocessor::PreprocessOptions;
+use perceptual_hashing::hashing::HashingOptions;
+use crate::config::Settings;
+use crate::logging::LogBuffer;
 use crate::ui::UI;
 use color_eyre::{eyre::Ok, Result};
 use ratatui::{DefaultTerminal};
@@ -12,40 +18,64 @@ const DURATION: Duration = Duration::from_millis(17);
 
 // logic and handling of the app
 
+#[derive(Clone, Copy, PartialEq)]
 pub enum CurrentScreen {
     FolderChoose,
     ChooseAnAlgorithm,
     Calculating,
-    Main
+    Main,
+    // the latest log events, over any of the screens above
+    Log
 }
 
 pub struct App {
     pub current_screen: CurrentScreen,
     pub dir_path: Option<PathBuf>,
     pub hashing_type: Option<HashingType>,
+    pub preprocess_options: PreprocessOptions,
+    pub hashing_options: HashingOptions,
+    // PDQ quality limit and how animations are compared
+    pub match_options: MatchOptions,
+    // the configuration; the options above start from it and can be changed on the screens
+    pub settings: Settings,
+    // shown next to every distance on the results screen
+    pub composite_rule: CompositeRule,
+    pub score_options: ScoreOptions,
+    // short message for the results screen, e.g. where the export went
+    pub status: Option<String>,
     pub similarity_analyzer: Option<SimilarityAnalyzer>,
     pub time_start: Option<Instant>,
     pub time_elapsed: String,
     pub items_list: Option<Vec<PathBuf>>,
+    // what the log screen shows
+    pub log: LogBuffer,
     pub exit: bool
 }
 
 impl App {
-    pub fn new() -> Self {
+    pub fn new(settings: Settings, log: LogBuffer) -> Self {
         App {
             current_screen: CurrentScreen::FolderChoose,
             dir_path: None,
             hashing_type: None,
+            preprocess_options: settings.preprocess_options,
+            hashing_options: settings.hashing_options,
+            match_options: settings.match_options,
+            composite_rule: settings.composite_rule.clone(),
+            score_options: ScoreOptions { filter: settings.filter, ..Default::default() },
+            settings,
+            status: None,
             similarity_analyzer: None,
             time_start: None,
             time_elapsed: String::new(),
             items_list: None,
+            log,
             exit: false
         }
     }
 
     pub fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
-        let mut ui = UI::new()?;
+        let mut ui = UI::new(self)?;
 
         while !self.exit {
             let mut ui_result = Ok(());
@@ -0,0 +1,282 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::mpsc::channel;
+use std::thread;
+use clap::{Parser, Subcommand};
+use color_eyre::{Result, eyre::eyre};
+use perceptual_hashing::handler::handle::{HashingType, ScanOptions, calculate_record, calculate_records, find_matches, imagehash_strings};
+use perceptual_hashing::handler::index::Index;
+use perceptual_hashing::handler::progress::CancellationToken;
+use perceptual_hashing::handler::tags::{self, TagStore};
+use perceptual_hashing::handler::watch::{self, LiveIndex};
+#[cfg(feature = "server")]
+use perceptual_hashing::handler::server::{self, Service};
+use perceptual_hashing::hashing::Encoding;
+use perceptual_hashing::preprocessing::formats;
+use perceptual_hashing::preprocessing::preprocessor::load_preview;
+use crate::config::Settings;
+
+// command line of the application; without arguments the TUI starts
+#[derive(Parser)]
+#[command(version, about = "Find similar images in a folder with perceptual hashes", after_help = formats::describe())]
+pub struct Cli {
+    /// List the image formats this build can read and exit
+    #[arg(long)]
+    pub formats: bool,
+    /// Take hashes from xattr (extended attributes) or xmp (sidecars) when current, and store them there otherwise
+    #[arg(long, global = true, value_parser = parse_tag_store)]
+    pub tags: Option<TagStore>,
+    /// Read this configuration file after the system, user and project ones
+    #[arg(long, global = true, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+    /// Override a configuration key, e.g. --set general.algorithm=dhash or --set hashing.blockhash.bits=8
+    #[arg(long = "set", global = true, value_name = "KEY=VALUE")]
+    pub overrides: Vec<String>,
+    /// Also log to stderr when running a command: -v for info, -vv for the time every file took, -vvv for everything
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Hash every image in a folder and write the hashes to an index file
+    Index {
+        dir: PathBuf,
+        output: PathBuf,
+    },
+    /// Print the hashes of an image, one algorithm per line
+    Hash {
+        image: PathBuf,
+        /// native, or imagehash for the strings of Python's imagehash (dHash and pHash only)
+        #[arg(short, long, default_value = "native", value_parser = parse_encoding)]
+        encoding: Encoding,
+    },
+    /// List the files of an index that are similar to an image, or to a hash string
+    Query {
+        index: PathBuf,
+        #[arg(required_unless_present = "hash")]
+        image: Option<PathBuf>,
+        /// Hex string to look for instead of the hash of an image
+        #[arg(long, conflicts_with = "image")]
+        hash: Option<String>,
+        /// Convention of --hash: native, or imagehash for the strings of Python's imagehash
+        #[arg(short, long, default_value = "native", value_parser = parse_encoding)]
+        encoding: Encoding,
+        /// dhash, phash, blockhash, pdq, radial, marr-hildreth, color-moments or segments [default: general.algorithm of the configuration, or phash]
+        #[arg(short, long, value_parser = parse_hashing_type)]
+        algorithm: Option<HashingType>,
+        /// Largest distance listed [default: thresholds.near-duplicate of the configuration, or a tenth of the largest possible distance]
+        #[arg(short = 'd', long)]
+        max_distance: Option<u32>,
+    },
+    /// Combine index files built with the same settings; the newer entry of a file wins
+    Merge {
+        output: PathBuf,
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+    },
+    /// Write an index file as CSV, one row per file with the hex hashes of every algorithm
+    ExportCsv {
+        index: PathBuf,
+        output: PathBuf,
+    },
+    /// Read a CSV written by export-csv back into an index file
+    ImportCsv {
+        csv: PathBuf,
+        output: PathBuf,
+    },
+    /// Keep an index of a folder up to date as files change, printing one JSON event per line
+    Watch {
+        dir: PathBuf,
+        /// Index file read at start and rewritten after changes [default: <DIR>.phix]
+        #[arg(short, long)]
+        index: Option<PathBuf>,
+        /// Algorithm near-duplicates are reported for [default: general.algorithm of the configuration, or phash]
+        #[arg(short, long, value_parser = parse_hashing_type)]
+        algorithm: Option<HashingType>,
+        /// Largest distance reported as a near-duplicate [default: thresholds.near-duplicate of the configuration, or a tenth of the largest possible distance]
+        #[arg(short = 'd', long)]
+        max_distance: Option<u32>,
+    },
+    /// Remove the hashes stored with --tags from the files of a folder, or from one file
+    StripTags {
+        path: PathBuf,
+        /// xattr or xmp
+        #[arg(value_parser = parse_tag_store)]
+        store: TagStore,
+    },
+    /// Print the effective configuration and the files it was read from
+    Config,
+    /// Answer hash and index queries over HTTP with JSON; the index is created if missing and rewritten after changes
+    #[cfg(feature = "server")]
+    Serve {
+        index: PathBuf,
+        /// Address and port to listen on
+        #[arg(short, long, default_value = server::DEFAULT_BIND)]
+        bind: String,
+        /// Folder whose files and archives PUT /entries may read from disk; without it images have to be uploaded
+        #[arg(long)]
+        root: Option<PathBuf>,
+        /// Requests answered at the same time [default: number of CPUs]
+        #[arg(short, long)]
+        workers: Option<usize>,
+    },
+}
+
+fn parse_encoding(name: &str) -> std::result::Result<Encoding, String> {
+    Encoding::from_name(name).ok_or_else(|| format!("unknown encoding {name}"))
+}
+
+fn parse_tag_store(name: &str) -> std::result::Result<TagStore, String> {
+    TagStore::from_name(name).ok_or_else(|| format!("unknown tag store {name}, expected xattr or xmp"))
+}
+
+fn parse_hashing_type(id: &str) -> std::result::Result<HashingType, String> {
+    HashingType::from_id(id).ok_or_else(|| format!("unknown algorithm {id}"))
+}
+
+pub fn run(command: Command, settings: &Settings) -> Result<()> {
+    let (options, hashing_options) = (settings.preprocess_options, settings.hashing_options);
+    // new indexes start with the configured settings, existing ones keep theirs
+    let load_or_new = |path: &std::path::Path| -> Result<Index> {
+        Ok(if path.exists() { Index::load(path)? } else { Index::new(Default::default(), options, hashing_options) })
+    };
+    match command {
+        Command::Index { dir, output } => {
+            let scan = ScanOptions { options, hashing_options, tags: settings.tags, filter: settings.scan_filter.clone() };
+            let records = calculate_records(&dir, &scan, None, &CancellationToken::new())?;
+            let index = Index::new(records, options, hashing_options);
+            index.save(&output)?;
+            println!("Indexed {} files into {}", index.entries.len(), output.display());
+        }
+        // computed from the image as imagehash sees it, whatever the settings and stored tags
+        Command::Hash { image, encoding: Encoding::Imagehash } => {
+            for (hashing_type, hex) in imagehash_strings(&load_preview(&image)?) {
+                println!("{}\t{hex}", hashing_type.id());
+            }
+        }
+        Command::Hash { image, encoding } => {
+            let record = match settings.tags {
+                Some(store) => tags::tagged_record(&image, store, &options, &hashing_options)?,
+                None => calculate_record(&image, &options, &hashing_options)?,
+            };
+            for hashing_type in HashingType::ALL {
+                if let Some(hex) = record.hashes.get(&hashing_type).and_then(|hash| hashing_type.format_hash(hash, encoding)) {
+                    println!("{}\t{hex}", hashing_type.id());
+                }
+            }
+        }
+        Command::Query { index, image, hash, encoding, algorithm, max_distance } => {
+            let index = Index::load(&index)?;
+            let algorithm = algorithm.or(settings.algorithm).unwrap_or(HashingType::PHash);
+            let query = match (image, hash) {
+                // the image is hashed with the settings of the index, so the hashes are comparable
+                (Some(image), _) => {
+                    let record = calculate_record(&image, &index.options, &index.hashing_options)?;
+                    record.hashes.get(&algorithm).cloned().ok_or_else(|| eyre!("no {algorithm} hash for {}", image.display()))?
+                }
+                (None, Some(hex)) => algorithm
+                    .parse_hash(&hex, encoding, &index.hashing_options)
+                    .ok_or_else(|| eyre!("{hex} is not a {algorithm} hash in the {encoding} encoding for the settings of the index"))?,
+                (None, None) => unreachable!("clap requires an image or a hash"),
+            };
+            let max_distance = max_distance.unwrap_or(settings.near_duplicate_distance(algorithm, &index.hashing_options));
+            for (path, distance) in find_matches(&index.records(), algorithm, &query, max_distance, None) {
+                println!("{distance}\t{}", path.display());
+            }
+        }
+        Command::Merge { output, inputs } => {
+            let mut inputs = inputs.iter();
+            let first = inputs.next().expect("at least one input");
+            let mut merged = Index::load(first)?;
+            for input in inputs {
+                merged.merge(Index::load(input)?).map_err(|err| eyre!("{}: {err}", input.display()))?;
+            }
+            merged.save(&output)?;
+            println!("Merged {} files into {}", merged.entries.len(), output.display());
+        }
+        Command::ExportCsv { index, output } => {
+            let mut writer = BufWriter::new(File::create(output)?);
+            Index::load(&index)?.export_csv(&mut writer)?;
+            writer.flush()?;
+        }
+        Command::ImportCsv { csv, output } => {
+            Index::import_csv(BufReader::new(File::open(csv)?))?.save(&output)?;
+        }
+        Command::StripTags { path, store } => {
+            let files: Vec<PathBuf> = if path.is_dir() {
+                std::fs::read_dir(&path)?.flatten().map(|entry| entry.path()).filter(|file| file.is_file()).collect()
+            } else {
+                vec![path]
+            };
+            let mut stripped = 0;
+            for file in &files {
+                // sidecars themselves are files of the folder too
+                if store == TagStore::Xmp && file.extension().is_some_and(|extension| extension == "xmp") {
+                    continue;
+                }
+                if tags::strip(file, store).map_err(|err| eyre!("{}: {err}", file.display()))? {
+                    stripped += 1;
+                }
+            }
+            println!("Removed the hashes of {stripped} files from {store}");
+        }
+        Command::Config => unreachable!("printed before the configuration is checked"),
+        Command::Watch { dir, index, algorithm, max_distance } => {
+            let path = match index {
+                Some(index) => index,
+                None => watch::default_index_path(&dir).map_err(|err| eyre!("{}: {err}", dir.display()))?,
+            };
+            let index = load_or_new(&path)?;
+            let algorithm = algorithm.or(settings.algorithm).unwrap_or(HashingType::PHash);
+            let max_distance = max_distance.unwrap_or(settings.near_duplicate_distance(algorithm, &index.hashing_options));
+
+            // watching starts before the sync so that nothing changing in between is missed
+            let (tx, rx) = channel();
+            let (options, hashing_options, watched, filter) = (index.options, index.hashing_options, dir.clone(), settings.scan_filter.clone());
+            let watcher = thread::spawn(move || watch::watch(&watched, &options, &hashing_options, &filter, &tx, &CancellationToken::new()));
+            let mut live = LiveIndex::new(index, algorithm, settings.match_options, max_distance);
+            let changes = watch::sync(&dir, &live.index, &settings.scan_filter);
+            apply_changes(&mut live, changes, &path)?;
+
+            // runs until interrupted, or until the watcher fails
+            while let Ok(change) = rx.recv() {
+                let changes = std::iter::once(change).chain(rx.try_iter()).collect();
+                apply_changes(&mut live, changes, &path)?;
+            }
+            watcher.join().map_err(|_| eyre!("the watcher panicked"))??;
+        }
+        #[cfg(feature = "server")]
+        Command::Serve { index, bind, root, workers } => {
+            let loaded = load_or_new(&index)?;
+            let server = server::bind(&bind)?;
+            let workers = workers.unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |count| count.get()));
+            eprintln!("Serving {} with {} files on http://{}", index.display(), loaded.entries.len(), server.server_addr());
+            let cancel = CancellationToken::new();
+            let interrupted = cancel.clone();
+            ctrlc::set_handler(move || interrupted.cancel())?;
+            server::serve(&server, &Service::new(loaded, Some(index), root), workers, &cancel)?;
+        }
+    }
+    Ok(())
+}
+
+// prints the events of the changes as NDJSON and saves the index when anything changed
+fn apply_changes(live: &mut LiveIndex, changes: Vec<watch::Change>, path: &std::path::Path) -> Result<()> {
+    let mut stdout = std::io::stdout().lock();
+    let mut changed = false;
+    for change in changes {
+        for event in live.apply(change) {
+            writeln!(stdout, "{}", serde_json::to_string(&event)?)?;
+            changed = true;
+        }
+    }
+    stdout.flush()?;
+    if changed {
+        live.index.save(path)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,472 @@
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use color_eyre::{Result, eyre::eyre};
+use serde::{Deserialize, Serialize};
+use toml::{Table, Value};
+use ratatui::{style::Stylize, text::Span};
+use perceptual_hashing::handler::composite::{self, Combinator, CompositeRule};
+use perceptual_hashing::handler::frames::FrameMatch;
+use perceptual_hashing::handler::handle::{HashingType, MatchOptions};
+use perceptual_hashing::handler::index::{self, IndexError};
+use perceptual_hashing::handler::scan::ScanFilter;
+use perceptual_hashing::handler::score::ScoreFilter;
+use perceptual_hashing::handler::tags::TagStore;
+use perceptual_hashing::hashing::{HashingOptions, pdq_hash::MIN_QUALITY};
+use perceptual_hashing::preprocessing::preprocessor::PreprocessOptions;
+use tracing::level_filters::LevelFilter;
+use tracing_appender::rolling::Rotation;
+use crate::keys::{self, Action, Keymap};
+use crate::logging::LogSettings;
+use crate::theme::{self, Theme};
+
+const APP_DIR: &str = "perceptual-hashing";
+const FILE_NAME: &str = "config.toml";
+/// Looked for in the current folder and its parents.
+pub const PROJECT_FILE_NAME: &str = ".perceptual-hashing.toml";
+
+// the configuration file; every section and key is optional, later layers override single keys
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    pub general: General,
+    /// The settings of index files: alpha, decode, frames, color, blockhash.bits, ...
+    pub hashing: Table,
+    pub thresholds: Thresholds,
+    pub composite: Composite,
+    pub scan: Scan,
+    pub log: Log,
+    /// Action name to key.
+    pub keys: BTreeMap<String, String>,
+    /// Colour role to colour.
+    pub theme: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct General {
+    /// Folder the explorer starts in.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_dir: Option<String>,
+    /// Preselected on the algorithm screen and the default of query and watch.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub algorithm: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Thresholds {
+    /// PDQ hashes of a lower quality are left out, 0 keeps every one.
+    pub min_quality: u8,
+    /// best, fraction:<percent> or aligned:<percent>
+    pub frame_match: String,
+    /// Filter the results start with: none, distance or similarity.
+    pub filter: String,
+    /// Limits a newly enabled filter starts with.
+    pub max_distance: u32,
+    pub min_similarity: f64,
+    /// Algorithm to the largest distance of a near-duplicate, for query, watch and the watch mode of the TUI.
+    pub near_duplicate: BTreeMap<String, u32>,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Thresholds {
+            min_quality: MIN_QUALITY,
+            frame_match: "best".to_string(),
+            filter: "none".to_string(),
+            max_distance: 10,
+            min_similarity: 80.0,
+            near_duplicate: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Composite {
+    /// all, any or weighted
+    pub combinator: String,
+    pub conditions: Vec<Condition>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Condition {
+    pub algorithm: String,
+    pub max_distance: u32,
+    /// Only used by the weighted combinator.
+    #[serde(default = "default_weight")]
+    pub weight: f64,
+}
+
+fn default_weight() -> f64 {
+    1.0
+}
+
+impl Default for Composite {
+    fn default() -> Self {
+        let rule = CompositeRule::default();
+        Composite {
+            combinator: rule.combinator.id().to_string(),
+            conditions: rule.conditions.iter()
+                .map(|condition| Condition {
+                    algorithm: condition.hashing_type.id().to_string(),
+                    max_distance: condition.max_distance,
+                    weight: condition.weight,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Scan {
+    pub extensions: Vec<String>,
+    pub exclude: Vec<String>,
+    pub skip_hidden: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_size_mb: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Log {
+    /// off, error, warn, info, debug or trace; debug adds the time every file took.
+    pub level: String,
+    /// Folder of the log files, by default in the state folder of the user.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dir: Option<String>,
+    /// A new file every minute, hour or day, or never.
+    pub rotation: String,
+    pub max_files: usize,
+}
+
+impl Default for Log {
+    fn default() -> Self {
+        Log { level: "info".to_string(), dir: None, rotation: "daily".to_string(), max_files: 7 }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let mut hashing = Table::new();
+        for (key, value) in index::settings(&PreprocessOptions::default(), &HashingOptions::default()) {
+            if key != "algorithms" {
+                insert(&mut hashing, &key, typed(value));
+            }
+        }
+        Config {
+            general: General::default(),
+            hashing,
+            thresholds: Thresholds::default(),
+            composite: Composite::default(),
+            scan: Scan::default(),
+            log: Log::default(),
+            keys: keys::defaults(),
+            theme: theme::defaults(),
+        }
+    }
+}
+
+// numbers and booleans of the index settings as such, so that the printed configuration reads naturally
+fn typed(value: String) -> Value {
+    value.parse().map(Value::Integer)
+        .or_else(|_| value.parse().map(Value::Float))
+        .or_else(|_| value.parse().map(Value::Boolean))
+        .unwrap_or(Value::String(value))
+}
+
+// sets a dotted key like `hashing.blockhash.bits`, creating the tables on the way
+fn insert(table: &mut Table, key: &str, value: Value) {
+    match key.split_once('.') {
+        Some((head, rest)) => {
+            let entry = table.entry(head).or_insert_with(|| Value::Table(Table::new()));
+            if !entry.is_table() {
+                *entry = Value::Table(Table::new());
+            }
+            insert(entry.as_table_mut().expect("just made a table"), rest, value);
+        }
+        None => {
+            table.insert(key.to_string(), value);
+        }
+    }
+}
+
+// tables are merged key by key, anything else is replaced
+fn merge(base: &mut Table, layer: Table) {
+    for (key, value) in layer {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base)), Value::Table(layer)) => merge(base, layer),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+fn home_dir() -> Option<PathBuf> {
+    env::var_os(if cfg!(windows) { "USERPROFILE" } else { "HOME" }).map(PathBuf::from)
+}
+
+fn expand_home(dir: &str) -> PathBuf {
+    match (dir.strip_prefix("~/"), home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(dir),
+    }
+}
+
+// where the log files go unless configured
+fn state_dir() -> Option<PathBuf> {
+    if cfg!(windows) {
+        env::var_os("LOCALAPPDATA").map(|dir| PathBuf::from(dir).join(APP_DIR).join("logs"))
+    } else {
+        env::var_os("XDG_STATE_HOME").map(PathBuf::from)
+            .or_else(|| home_dir().map(|home| home.join(".local").join("state")))
+            .map(|dir| dir.join(APP_DIR))
+    }
+}
+
+/// A layer of the configuration, from the lowest to the highest priority.
+#[derive(Debug, Clone)]
+pub struct Source {
+    pub name: &'static str,
+    pub path: Option<PathBuf>,
+    pub found: bool,
+}
+
+// system, user and project files; the project file is the closest one up from the current folder
+fn layer_paths() -> Vec<(&'static str, Option<PathBuf>)> {
+    let system = if cfg!(windows) {
+        env::var_os("PROGRAMDATA").map(|dir| PathBuf::from(dir).join(APP_DIR).join(FILE_NAME))
+    } else {
+        Some(Path::new("/etc").join(APP_DIR).join(FILE_NAME))
+    };
+    let user = if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        env::var_os("XDG_CONFIG_HOME").map(PathBuf::from).or_else(|| home_dir().map(|home| home.join(".config")))
+    };
+    let cwd = env::current_dir().ok();
+    let project = cwd.as_ref().and_then(|cwd| cwd.ancestors().map(|dir| dir.join(PROJECT_FILE_NAME)).find(|path| path.is_file()))
+        .or_else(|| cwd.map(|cwd| cwd.join(PROJECT_FILE_NAME)));
+    vec![("system", system), ("user", user.map(|dir| dir.join(APP_DIR).join(FILE_NAME))), ("project", project)]
+}
+
+/// The merged configuration and where it came from.
+pub struct Loaded {
+    pub config: Config,
+    pub sources: Vec<Source>,
+    pub overrides: Vec<String>,
+}
+
+fn read_layer(path: &Path) -> Result<Table> {
+    let text = fs::read_to_string(path).map_err(|err| eyre!("{}: {err}", path.display()))?;
+    let table: Table = toml::from_str(&text).map_err(|err| eyre!("{}: {err}", path.display()))?;
+    // checked on its own so that mistakes are reported with their file
+    Config::deserialize(Value::Table(table.clone())).map_err(|err| eyre!("{}: {err}", path.display()))?;
+    Ok(table)
+}
+
+/// Merges the defaults, the system, user and project files, `file` and the `key=value` `overrides`, in that order.
+pub fn load(file: Option<&Path>, overrides: &[String]) -> Result<Loaded> {
+    let mut merged = Table::try_from(Config::default())?;
+    let mut sources = Vec::new();
+    let layers = layer_paths().into_iter().map(|(name, path)| (name, path, false))
+        .chain(file.map(|file| ("--config", Some(file.to_path_buf()), true)));
+    for (name, path, required) in layers {
+        let found = path.as_ref().is_some_and(|path| path.is_file());
+        if let Some(path) = path.as_ref().filter(|_| found || required) {
+            merge(&mut merged, read_layer(path)?);
+        }
+        sources.push(Source { name, path, found });
+    }
+    for assignment in overrides {
+        let (key, value) = assignment.split_once('=').ok_or_else(|| eyre!("--set {assignment}: expected KEY=VALUE"))?;
+        // TOML values like 8, true or ["jpg", "png"], anything else is a string
+        let value = toml::from_str::<Table>(&format!("value = {value}")).ok()
+            .and_then(|mut table| table.remove("value"))
+            .unwrap_or_else(|| Value::String(value.to_string()));
+        let mut layer = Table::new();
+        insert(&mut layer, key.trim(), value);
+        Config::deserialize(Value::Table(layer.clone())).map_err(|err| eyre!("--set {assignment}: {err}"))?;
+        merge(&mut merged, layer);
+    }
+    let config = Config::deserialize(Value::Table(merged))?;
+    Ok(Loaded { config, sources, overrides: overrides.to_vec() })
+}
+
+impl Loaded {
+    /// The effective configuration as TOML, after comments naming its layers.
+    pub fn describe(&self) -> Result<String> {
+        let mut text = String::from("# defaults\n");
+        for source in &self.sources {
+            let path = source.path.as_ref().map_or("-".into(), |path| path.display().to_string());
+            let found = if source.found { "" } else { " (not found)" };
+            text.push_str(&format!("# {}: {path}{found}\n", source.name));
+        }
+        for assignment in &self.overrides {
+            text.push_str(&format!("# --set {assignment}\n"));
+        }
+        text.push('\n');
+        text.push_str(&toml::to_string(&self.config)?);
+        Ok(text)
+    }
+}
+
+/// The configuration checked and turned into what the application works with.
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub start_dir: Option<PathBuf>,
+    pub algorithm: Option<HashingType>,
+    pub tags: Option<TagStore>,
+    pub preprocess_options: PreprocessOptions,
+    pub hashing_options: HashingOptions,
+    pub match_options: MatchOptions,
+    pub filter: Option<ScoreFilter>,
+    pub max_distance: u32,
+    pub min_similarity: f64,
+    near_duplicate: HashMap<HashingType, u32>,
+    pub composite_rule: CompositeRule,
+    pub scan_filter: ScanFilter,
+    pub keys: Keymap,
+    pub theme: Theme,
+    pub log: LogSettings,
+}
+
+impl Settings {
+    /// Largest distance of a near-duplicate: the configured one, or a tenth of the largest possible distance.
+    pub fn near_duplicate_distance(&self, hashing_type: HashingType, hashing_options: &HashingOptions) -> u32 {
+        self.near_duplicate.get(&hashing_type).copied().unwrap_or_else(|| hashing_type.near_duplicate_distance(hashing_options))
+    }
+
+    /// The key of `action` for the hints, in the colour of keys.
+    pub fn hint(&self, action: Action) -> Span<'static> {
+        Span::from(self.keys.label(action)).fg(self.theme.keys).bold()
+    }
+}
+
+fn hashing_type(key: &str, id: &str) -> Result<HashingType> {
+    HashingType::from_id(id).ok_or_else(|| eyre!("{key}: unknown algorithm {id}"))
+}
+
+// the [hashing] table as index settings, e.g. ("blockhash.bits", "16")
+fn flatten(prefix: &str, table: &Table, settings: &mut Vec<(String, String)>) -> Result<()> {
+    for (key, value) in table {
+        let key = if prefix.is_empty() { key.clone() } else { format!("{prefix}.{key}") };
+        match value {
+            Value::Table(table) => flatten(&key, table, settings)?,
+            Value::String(text) => settings.push((key, text.clone())),
+            Value::Integer(_) | Value::Float(_) | Value::Boolean(_) => settings.push((key, value.to_string())),
+            _ => return Err(eyre!("hashing.{key}: expected a string, a number or a boolean")),
+        }
+    }
+    Ok(())
+}
+
+impl Config {
+    pub fn settings(&self) -> Result<Settings> {
+        let general = &self.general;
+        let start_dir = general.start_dir.as_deref().map(expand_home);
+        let algorithm = general.algorithm.as_deref().map(|id| hashing_type("general.algorithm", id)).transpose()?;
+        let tags = general.tags.as_deref()
+            .map(|name| TagStore::from_name(name).ok_or_else(|| eyre!("general.tags: unknown tag store {name}, expected xattr or xmp")))
+            .transpose()?;
+
+        let mut settings = index::settings(&PreprocessOptions::default(), &HashingOptions::default());
+        settings.retain(|(key, _)| key != "algorithms");
+        let mut configured = Vec::new();
+        flatten("", &self.hashing, &mut configured)?;
+        for (key, value) in configured {
+            let setting = settings.iter_mut().find(|(known, _)| *known == key).ok_or_else(|| eyre!("hashing.{key}: unknown setting"))?;
+            setting.1 = value;
+        }
+        let (preprocess_options, hashing_options) = index::parse_settings(&settings).map_err(|err| match err {
+            IndexError::Invalid(message) => eyre!("hashing: {message}"),
+            err => eyre!("hashing: {err}"),
+        })?;
+
+        let thresholds = &self.thresholds;
+        let percent = |text: &str| text.parse::<f64>().ok().filter(|percent| (0.0..=100.0).contains(percent));
+        let frames = match thresholds.frame_match.split_once(':') {
+            None if thresholds.frame_match == "best" => Some(FrameMatch::BestFrame),
+            Some(("fraction", min_similarity)) => percent(min_similarity).map(|min_similarity| FrameMatch::Fraction { min_similarity }),
+            Some(("aligned", min_similarity)) => percent(min_similarity).map(|min_similarity| FrameMatch::Aligned { min_similarity }),
+            _ => None,
+        }.ok_or_else(|| eyre!("thresholds.frame-match: expected best, fraction:<percent> or aligned:<percent>"))?;
+        let match_options = MatchOptions { min_quality: (thresholds.min_quality > 0).then_some(thresholds.min_quality), frames };
+        let filter = match thresholds.filter.as_str() {
+            "none" => None,
+            "distance" => Some(ScoreFilter::MaxDistance(thresholds.max_distance)),
+            "similarity" => Some(ScoreFilter::MinSimilarity(thresholds.min_similarity)),
+            other => return Err(eyre!("thresholds.filter: unknown filter {other}, expected none, distance or similarity")),
+        };
+        let near_duplicate = thresholds.near_duplicate
+            .iter()
+            .map(|(id, distance)| Ok((hashing_type("thresholds.near-duplicate", id)?, *distance)))
+            .collect::<Result<_>>()?;
+
+        let composite = &self.composite;
+        let combinator = Combinator::from_id(&composite.combinator)
+            .ok_or_else(|| eyre!("composite.combinator: unknown combinator {}, expected all, any or weighted", composite.combinator))?;
+        if composite.conditions.is_empty() {
+            return Err(eyre!("composite.conditions: expected at least one condition"));
+        }
+        let conditions = composite.conditions
+            .iter()
+            .map(|condition| {
+                if !condition.weight.is_finite() || condition.weight < 0.0 {
+                    return Err(eyre!("composite.conditions: weight of {}: expected a number of at least 0", condition.algorithm));
+                }
+                let hashing_type = hashing_type("composite.conditions", &condition.algorithm)?;
+                Ok(composite::Condition { hashing_type, max_distance: condition.max_distance, weight: condition.weight })
+            })
+            .collect::<Result<_>>()?;
+        let composite_rule = CompositeRule { combinator, conditions };
+
+        let scan = &self.scan;
+        let scan_filter = ScanFilter {
+            extensions: scan.extensions.iter().map(|extension| extension.trim_start_matches('.').to_string()).collect(),
+            exclude: scan.exclude.clone(),
+            skip_hidden: scan.skip_hidden,
+            max_size: scan.max_size_mb.map(|megabytes| (megabytes * 1024.0 * 1024.0) as u64),
+        };
+
+        let log = &self.log;
+        let level = log.level.parse::<LevelFilter>()
+            .map_err(|_| eyre!("log.level: unknown level {}, expected off, error, warn, info, debug or trace", log.level))?;
+        let rotation = match log.rotation.as_str() {
+            "minutely" => Rotation::MINUTELY,
+            "hourly" => Rotation::HOURLY,
+            "daily" => Rotation::DAILY,
+            "never" => Rotation::NEVER,
+            other => return Err(eyre!("log.rotation: unknown rotation {other}, expected minutely, hourly, daily or never")),
+        };
+        if log.max_files == 0 {
+            return Err(eyre!("log.max-files: expected a positive number"));
+        }
+        let log = LogSettings { level, dir: log.dir.as_deref().map(expand_home).or_else(state_dir), rotation, max_files: log.max_files };
+
+        Ok(Settings {
+            start_dir,
+            algorithm,
+            tags,
+            preprocess_options,
+            hashing_options,
+            match_options,
+            filter,
+            max_distance: thresholds.max_distance,
+            min_similarity: thresholds.min_similarity,
+            near_duplicate,
+            composite_rule,
+            scan_filter,
+            keys: Keymap::new(&self.keys)?,
+            theme: Theme::new(&self.theme)?,
+            log,
+        })
+    }
+}
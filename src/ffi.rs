@@ -0,0 +1,544 @@
+//! C API of the hashers and index files, built into the `cdylib`. The header is
+//! `include/perceptual_hashing.h`, generated from this file by cbindgen (see `cbindgen.toml`).
+//!
+//! Every function returning a `PhStatus` leaves a message for `ph_last_error` when it fails.
+//! Objects returned through pointers belong to the caller and are released with the matching
+//! `_free` function. Paths and hex strings are UTF-8, or bytes of the platform encoding on Unix.
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString, c_char};
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::path::{Path, PathBuf};
+use std::ptr;
+
+use crate::handler::handle::{HashingType, calculate_hash, calculate_record, find_matches, hash_buffer, hash_bytes};
+use crate::handler::index::{Index, IndexEntry, stamp};
+use crate::hashing::{Encoding, Hash, HashingOptions};
+use crate::preprocessing::preprocessor::PreprocessOptions;
+
+/// Algorithms, as passed to the functions taking an `algorithm`; the values are stable.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PhAlgorithm {
+    Dhash = 0,
+    Phash = 1,
+    Blockhash = 2,
+    Pdq = 3,
+    Radial = 4,
+    MarrHildreth = 5,
+    ColorMoments = 6,
+    Segments = 7,
+}
+
+/// Result of a call; the values are stable.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PhStatus {
+    Ok = 0,
+    /// A null pointer, an unknown algorithm or a malformed hex string.
+    InvalidArgument = 1,
+    Io = 2,
+    /// The image could not be decoded.
+    Decode = 3,
+    /// The hashes come from different algorithms or settings.
+    Incompatible = 4,
+    NotFound = 5,
+    /// A bug in the library; the message says where.
+    Internal = 6,
+}
+
+/// A hash of one algorithm.
+pub struct PhHash {
+    hashing_type: HashingType,
+    hash: Hash,
+}
+
+/// An index file in memory.
+pub struct PhIndex {
+    index: Index,
+}
+
+/// Returned by `ph_hash_algorithm` for a null hash.
+pub const PH_NO_ALGORITHM: u32 = u32::MAX;
+
+/// A file of an index close to a query.
+#[repr(C)]
+pub struct PhMatch {
+    /// The path as it was inserted, the raw bytes on Unix; null if it holds a nul byte.
+    pub path: *mut c_char,
+    pub distance: u32,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+type Failure = (PhStatus, String);
+
+fn invalid(message: &str) -> Failure {
+    (PhStatus::InvalidArgument, message.to_string())
+}
+
+// runs `f`, turning its error or panic into a status and the message of ph_last_error
+fn guard(f: impl FnOnce() -> Result<(), Failure>) -> PhStatus {
+    catch(f).err().unwrap_or(PhStatus::Ok)
+}
+
+// like `guard`, for functions that return a value rather than a status: `fallback` when `f` fails
+fn guard_or<T>(fallback: T, f: impl FnOnce() -> Result<T, Failure>) -> T {
+    catch(f).unwrap_or(fallback)
+}
+
+fn catch<T>(f: impl FnOnce() -> Result<T, Failure>) -> Result<T, PhStatus> {
+    let (status, message) = match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(value)) => return Ok(value),
+        Ok(Err(failure)) => failure,
+        Err(panic) => {
+            let message = panic.downcast_ref::<&str>().map(|s| s.to_string()).or_else(|| panic.downcast_ref::<String>().cloned());
+            (PhStatus::Internal, message.unwrap_or_else(|| "panic".to_string()))
+        }
+    };
+    LAST_ERROR.with(|last| *last.borrow_mut() = CString::new(message.replace('\0', " ")).ok());
+    Err(status)
+}
+
+fn algorithm(algorithm: u32) -> Result<HashingType, Failure> {
+    HashingType::from_index(algorithm as usize).ok_or_else(|| invalid(&format!("unknown algorithm {algorithm}")))
+}
+
+unsafe fn string<'a>(text: *const c_char, name: &str) -> Result<&'a CStr, Failure> {
+    if text.is_null() {
+        return Err(invalid(&format!("{name} is null")));
+    }
+    Ok(unsafe { CStr::from_ptr(text) })
+}
+
+unsafe fn path(text: *const c_char) -> Result<PathBuf, Failure> {
+    let text = unsafe { string(text, "path") }?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        Ok(PathBuf::from(std::ffi::OsStr::from_bytes(text.to_bytes())))
+    }
+    #[cfg(not(unix))]
+    text.to_str().map(PathBuf::from).map_err(|_| invalid("path is not UTF-8"))
+}
+
+// the inverse of `path`
+fn c_path(path: &Path) -> *mut c_char {
+    #[cfg(unix)]
+    let bytes = {
+        use std::os::unix::ffi::OsStrExt;
+        path.as_os_str().as_bytes().to_vec()
+    };
+    #[cfg(not(unix))]
+    let bytes = path.to_string_lossy().into_owned().into_bytes();
+    CString::new(bytes).map_or(ptr::null_mut(), CString::into_raw)
+}
+
+unsafe fn bytes<'a>(data: *const u8, len: usize) -> Result<&'a [u8], Failure> {
+    match (data.is_null(), len) {
+        (_, 0) => Err(invalid("the buffer is empty")),
+        (true, _) => Err(invalid("data is null")),
+        (false, _) => Ok(unsafe { std::slice::from_raw_parts(data, len) }),
+    }
+}
+
+unsafe fn output<'a, T>(out: *mut T) -> Result<&'a mut T, Failure> {
+    unsafe { out.as_mut() }.ok_or_else(|| invalid("the output pointer is null"))
+}
+
+fn decode_error(err: image::ImageError) -> Failure {
+    match err {
+        image::ImageError::IoError(err) => (PhStatus::Io, err.to_string()),
+        err => (PhStatus::Decode, err.to_string()),
+    }
+}
+
+fn new_hash(hashing_type: HashingType, hash: Hash) -> *mut PhHash {
+    Box::into_raw(Box::new(PhHash { hashing_type, hash }))
+}
+
+/// Message of the last failed call on this thread, or null. Valid until the next failing call.
+#[unsafe(no_mangle)]
+pub extern "C" fn ph_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |message| message.as_ptr()))
+}
+
+/// Largest distance between two hashes of `algorithm` with the default settings, 0 for unknown algorithms.
+#[unsafe(no_mangle)]
+pub extern "C" fn ph_max_distance(algorithm: u32) -> u32 {
+    HashingType::from_index(algorithm as usize).map_or(0, |hashing_type| hashing_type.max_distance(&HashingOptions::default()))
+}
+
+/// Hashes the image at `path` (the first frame of animations) with the default settings.
+///
+/// # Safety
+/// `path` is a null-terminated string and `out` points to writable memory.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ph_hash_file(path: *const c_char, algorithm: u32, out: *mut *mut PhHash) -> PhStatus {
+    guard(|| {
+        let (path, hashing_type, out) = (unsafe { self::path(path) }?, self::algorithm(algorithm)?, unsafe { output(out) }?);
+        let hash = calculate_hash(&path, hashing_type, &PreprocessOptions::default(), &HashingOptions::default()).map_err(decode_error)?;
+        *out = new_hash(hashing_type, hash);
+        Ok(())
+    })
+}
+
+/// Like `ph_hash_file`, for an encoded image in memory.
+///
+/// # Safety
+/// `data` points to `len` readable bytes and `out` points to writable memory.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ph_hash_buffer(data: *const u8, len: usize, algorithm: u32, out: *mut *mut PhHash) -> PhStatus {
+    guard(|| {
+        let (bytes, hashing_type, out) = (unsafe { bytes(data, len) }?, self::algorithm(algorithm)?, unsafe { output(out) }?);
+        let hash = hash_buffer(bytes, hashing_type, &PreprocessOptions::default(), &HashingOptions::default()).map_err(decode_error)?;
+        *out = new_hash(hashing_type, hash);
+        Ok(())
+    })
+}
+
+/// Parses a hex string as `ph_hash_to_hex` writes it.
+///
+/// # Safety
+/// `hex` is a null-terminated string and `out` points to writable memory.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ph_hash_from_hex(algorithm: u32, hex: *const c_char, out: *mut *mut PhHash) -> PhStatus {
+    guard(|| {
+        let (hashing_type, out) = (self::algorithm(algorithm)?, unsafe { output(out) }?);
+        let hex = unsafe { string(hex, "hex") }?.to_str().map_err(|_| invalid("hex is not UTF-8"))?;
+        let hash = hashing_type
+            .parse_hash(hex, Encoding::Native, &HashingOptions::default())
+            .ok_or_else(|| invalid(&format!("{hex} is not a {hashing_type} hash")))?;
+        *out = new_hash(hashing_type, hash);
+        Ok(())
+    })
+}
+
+/// The hash as hex, to be released with `ph_string_free`; null when `hash` is null.
+///
+/// # Safety
+/// `hash` is null or was returned by this library and not freed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ph_hash_to_hex(hash: *const PhHash) -> *mut c_char {
+    match unsafe { hash.as_ref() } {
+        Some(hash) => CString::new(hash.hash.to_hex()).map_or(ptr::null_mut(), CString::into_raw),
+        None => ptr::null_mut(),
+    }
+}
+
+/// The `PhAlgorithm` of the hash, `PH_NO_ALGORITHM` when `hash` is null.
+///
+/// # Safety
+/// `hash` is null or was returned by this library and not freed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ph_hash_algorithm(hash: *const PhHash) -> u32 {
+    guard_or(PH_NO_ALGORITHM, || {
+        let hash = unsafe { hash.as_ref() }.ok_or_else(|| invalid("hash is null"))?;
+        Ok(HashingType::ALL.iter().position(|&hashing_type| hashing_type == hash.hashing_type).expect("listed algorithm") as u32)
+    })
+}
+
+/// Distance between two hashes of the same algorithm: the Hamming distance for bit hashes, 0 meaning identical.
+///
+/// # Safety
+/// `a` and `b` were returned by this library and not freed, `out` points to writable memory.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ph_distance(a: *const PhHash, b: *const PhHash, out: *mut u32) -> PhStatus {
+    guard(|| {
+        let (a, b) = unsafe { (a.as_ref(), b.as_ref()) };
+        let (a, b) = a.zip(b).ok_or_else(|| invalid("a hash is null"))?;
+        let out = unsafe { output(out) }?;
+        if a.hashing_type != b.hashing_type {
+            return Err((PhStatus::Incompatible, format!("a {} hash cannot be compared with a {} hash", a.hashing_type, b.hashing_type)));
+        }
+        *out = a.hash.distance(&b.hash).ok_or_else(|| (PhStatus::Incompatible, "the hashes were computed with different settings".to_string()))?;
+        Ok(())
+    })
+}
+
+/// # Safety
+/// `hash` is null or was returned by this library and not freed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ph_hash_free(hash: *mut PhHash) {
+    if !hash.is_null() {
+        drop(unsafe { Box::from_raw(hash) });
+    }
+}
+
+/// # Safety
+/// `text` is null or was returned by this library and not freed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ph_string_free(text: *mut c_char) {
+    if !text.is_null() {
+        drop(unsafe { CString::from_raw(text) });
+    }
+}
+
+/// An empty index with the default settings.
+#[unsafe(no_mangle)]
+pub extern "C" fn ph_index_new() -> *mut PhIndex {
+    let index = Index::new(Default::default(), PreprocessOptions::default(), HashingOptions::default());
+    Box::into_raw(Box::new(PhIndex { index }))
+}
+
+/// Reads an index file, e.g. one written by `perceptual-hashing index`.
+///
+/// # Safety
+/// `path` is a null-terminated string and `out` points to writable memory.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ph_index_load(path: *const c_char, out: *mut *mut PhIndex) -> PhStatus {
+    guard(|| {
+        let (path, out) = (unsafe { self::path(path) }?, unsafe { output(out) }?);
+        let index = Index::load(&path).map_err(|err| (PhStatus::Io, format!("{}: {err}", path.display())))?;
+        *out = Box::into_raw(Box::new(PhIndex { index }));
+        Ok(())
+    })
+}
+
+/// # Safety
+/// `index` was returned by this library and not freed, `path` is a null-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ph_index_save(index: *const PhIndex, path: *const c_char) -> PhStatus {
+    guard(|| {
+        let index = unsafe { index.as_ref() }.ok_or_else(|| invalid("index is null"))?;
+        let path = unsafe { self::path(path) }?;
+        index.index.save(&path).map_err(|err| (PhStatus::Io, format!("{}: {err}", path.display())))
+    })
+}
+
+/// Number of files in the index, 0 when `index` is null.
+///
+/// # Safety
+/// `index` is null or was returned by this library and not freed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ph_index_len(index: *const PhIndex) -> usize {
+    guard_or(0, || Ok(unsafe { index.as_ref() }.ok_or_else(|| invalid("index is null"))?.index.entries.len()))
+}
+
+/// Like `ph_hash_file`, with the settings of the index, so that the hash can be queried in an index
+/// file written with other settings than the defaults.
+///
+/// # Safety
+/// `index` was returned by this library and not freed, `path` is a null-terminated string and
+/// `out` points to writable memory.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ph_index_hash_file(index: *const PhIndex, path: *const c_char, algorithm: u32, out: *mut *mut PhHash) -> PhStatus {
+    guard(|| {
+        let index = &unsafe { index.as_ref() }.ok_or_else(|| invalid("index is null"))?.index;
+        let (path, hashing_type, out) = (unsafe { self::path(path) }?, self::algorithm(algorithm)?, unsafe { output(out) }?);
+        let hash = calculate_hash(&path, hashing_type, &index.options, &index.hashing_options).map_err(decode_error)?;
+        *out = new_hash(hashing_type, hash);
+        Ok(())
+    })
+}
+
+/// Like `ph_hash_buffer`, with the settings of the index.
+///
+/// # Safety
+/// `index` was returned by this library and not freed, `data` points to `len` readable bytes and
+/// `out` points to writable memory.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ph_index_hash_buffer(index: *const PhIndex, data: *const u8, len: usize, algorithm: u32, out: *mut *mut PhHash) -> PhStatus {
+    guard(|| {
+        let index = &unsafe { index.as_ref() }.ok_or_else(|| invalid("index is null"))?.index;
+        let (bytes, hashing_type, out) = (unsafe { bytes(data, len) }?, self::algorithm(algorithm)?, unsafe { output(out) }?);
+        let hash = hash_buffer(bytes, hashing_type, &index.options, &index.hashing_options).map_err(decode_error)?;
+        *out = new_hash(hashing_type, hash);
+        Ok(())
+    })
+}
+
+/// Hashes the image at `path` with every algorithm and the settings of the index, replacing its entry.
+///
+/// # Safety
+/// `index` was returned by this library and not freed, `path` is a null-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ph_index_insert_file(index: *mut PhIndex, path: *const c_char) -> PhStatus {
+    guard(|| {
+        let index = &mut unsafe { index.as_mut() }.ok_or_else(|| invalid("index is null"))?.index;
+        let path = unsafe { self::path(path) }?;
+        let record = calculate_record(&path, &index.options, &index.hashing_options).map_err(decode_error)?;
+        let (size, modified) = stamp(&path);
+        index.insert(IndexEntry { path, size, modified, record });
+        Ok(())
+    })
+}
+
+/// Like `ph_index_insert_file`, for an encoded image in memory stored under `name`.
+///
+/// # Safety
+/// `index` was returned by this library and not freed, `name` is a null-terminated string and
+/// `data` points to `len` readable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ph_index_insert_buffer(index: *mut PhIndex, name: *const c_char, data: *const u8, len: usize) -> PhStatus {
+    guard(|| {
+        let index = &mut unsafe { index.as_mut() }.ok_or_else(|| invalid("index is null"))?.index;
+        let (path, bytes) = (unsafe { self::path(name) }?, unsafe { self::bytes(data, len) }?);
+        let record = hash_bytes(bytes, &index.options, &index.hashing_options).map_err(decode_error)?;
+        index.insert(IndexEntry { path, size: len as u64, modified: 0, record });
+        Ok(())
+    })
+}
+
+/// # Safety
+/// `index` was returned by this library and not freed, `path` is a null-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ph_index_remove(index: *mut PhIndex, path: *const c_char) -> PhStatus {
+    guard(|| {
+        let index = &mut unsafe { index.as_mut() }.ok_or_else(|| invalid("index is null"))?.index;
+        let path = unsafe { self::path(path) }?;
+        match index.remove(&path) {
+            Some(_) => Ok(()),
+            None => Err((PhStatus::NotFound, format!("{} is not in the index", path.display()))),
+        }
+    })
+}
+
+/// Files of the index within `max_distance` of `query`, closest first, in an array of `*out_len`
+/// matches to be released with `ph_matches_free`. The query has to be computed with the settings of
+/// the index, which are the defaults unless the index file says otherwise; `ph_index_hash_file` uses them.
+///
+/// # Safety
+/// `index` and `query` were returned by this library and not freed, `out` and `out_len` point to writable memory.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ph_index_query(
+    index: *const PhIndex,
+    query: *const PhHash,
+    max_distance: u32,
+    out: *mut *mut PhMatch,
+    out_len: *mut usize
+) -> PhStatus {
+    guard(|| {
+        let index = &unsafe { index.as_ref() }.ok_or_else(|| invalid("index is null"))?.index;
+        let query = unsafe { query.as_ref() }.ok_or_else(|| invalid("query is null"))?;
+        let (out, out_len) = (unsafe { output(out) }?, unsafe { output(out_len) }?);
+        let matches: Box<[PhMatch]> = find_matches(&index.records(), query.hashing_type, &query.hash, max_distance, None)
+            .into_iter()
+            .map(|(path, distance)| PhMatch { path: c_path(&path), distance })
+            .collect();
+        *out_len = matches.len();
+        *out = Box::into_raw(matches) as *mut PhMatch;
+        Ok(())
+    })
+}
+
+/// # Safety
+/// `matches` and `len` are those returned by `ph_index_query`, not freed before.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ph_matches_free(matches: *mut PhMatch, len: usize) {
+    if matches.is_null() {
+        return;
+    }
+    let matches = unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(matches, len)) };
+    for found in matches.iter() {
+        unsafe { ph_string_free(found.path) };
+    }
+}
+
+/// # Safety
+/// `index` is null or was returned by this library and not freed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ph_index_free(index: *mut PhIndex) {
+    if !index.is_null() {
+        drop(unsafe { Box::from_raw(index) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use super::*;
+
+    const HEADER: &str = "include/perceptual_hashing.h";
+
+    // run with UPDATE_HEADER=1 to write the header after changing the API
+    #[test]
+    fn header_is_up_to_date() {
+        let config = cbindgen::Config::from_file("cbindgen.toml").unwrap();
+        let bindings = cbindgen::Builder::new().with_config(config).with_src("src/ffi.rs").generate().unwrap();
+        let mut generated = Vec::new();
+        bindings.write(&mut generated);
+        if std::env::var_os("UPDATE_HEADER").is_some() {
+            fs::write(HEADER, &generated).unwrap();
+        }
+        assert!(fs::read(HEADER).unwrap() == generated, "{HEADER} is out of date, run the tests with UPDATE_HEADER=1");
+    }
+
+    #[test]
+    fn algorithms_keep_their_values() {
+        for (i, hashing_type) in HashingType::ALL.into_iter().enumerate() {
+            assert_eq!(algorithm(i as u32), Ok(hashing_type));
+        }
+        assert_eq!(PhAlgorithm::Segments as usize, HashingType::ALL.len() - 1);
+        assert_eq!(algorithm(8).unwrap_err().0, PhStatus::InvalidArgument);
+    }
+
+    #[test]
+    fn failures_leave_a_message() {
+        let mut hash = ptr::null_mut();
+        let status = unsafe { ph_hash_file(c"test_images/missing.png".as_ptr(), PhAlgorithm::Dhash as u32, &mut hash) };
+        assert_eq!(status, PhStatus::Io);
+        assert!(hash.is_null());
+        assert!(!ph_last_error().is_null());
+
+        let status = unsafe { ph_hash_from_hex(PhAlgorithm::Phash as u32, c"xyz".as_ptr(), &mut hash) };
+        assert_eq!(status, PhStatus::InvalidArgument);
+        assert_eq!(unsafe { CStr::from_ptr(ph_last_error()) }.to_str(), Ok("xyz is not a pHash hash"));
+    }
+
+    #[test]
+    fn null_objects_give_sentinels() {
+        assert_eq!(unsafe { ph_hash_algorithm(ptr::null()) }, PH_NO_ALGORITHM);
+        assert_eq!(unsafe { CStr::from_ptr(ph_last_error()) }.to_str(), Ok("hash is null"));
+        assert_eq!(unsafe { ph_index_len(ptr::null()) }, 0);
+        assert_eq!(unsafe { CStr::from_ptr(ph_last_error()) }.to_str(), Ok("index is null"));
+    }
+
+    #[test]
+    fn hashes_can_use_the_settings_of_an_index() {
+        let path = c"test_images/test1.png";
+        let mut index = PhIndex { index: Index::new(Default::default(), PreprocessOptions::default(), HashingOptions::default()) };
+        index.index.hashing_options.blockhash.bits = 8;
+        assert_eq!(unsafe { ph_index_insert_file(&mut index, path.as_ptr()) }, PhStatus::Ok);
+        let entry = index.index.get(Path::new("test_images/test1.png")).unwrap();
+        assert_eq!(entry.size, fs::metadata("test_images/test1.png").unwrap().len());
+        assert_ne!(entry.modified, 0);
+
+        let (mut default, mut own) = (ptr::null_mut(), ptr::null_mut());
+        assert_eq!(unsafe { ph_hash_file(path.as_ptr(), PhAlgorithm::Blockhash as u32, &mut default) }, PhStatus::Ok);
+        assert_eq!(unsafe { ph_index_hash_file(&index, path.as_ptr(), PhAlgorithm::Blockhash as u32, &mut own) }, PhStatus::Ok);
+        let mut distance = 0;
+        assert_eq!(unsafe { ph_distance(default, own, &mut distance) }, PhStatus::Incompatible);
+
+        let (mut matches, mut count) = (ptr::null_mut(), 0);
+        assert_eq!(unsafe { ph_index_query(&index, own, 0, &mut matches, &mut count) }, PhStatus::Ok);
+        assert_eq!(count, 1);
+        unsafe {
+            ph_matches_free(matches, count);
+            ph_hash_free(default);
+            ph_hash_free(own);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn matches_keep_the_bytes_of_their_paths() {
+        let name = CString::new(&b"photos/caf\xe9.png"[..]).unwrap();
+        let bytes = fs::read("test_images/test1.png").unwrap();
+        let mut index = PhIndex { index: Index::new(Default::default(), PreprocessOptions::default(), HashingOptions::default()) };
+        assert_eq!(unsafe { ph_index_insert_buffer(&mut index, name.as_ptr(), bytes.as_ptr(), bytes.len()) }, PhStatus::Ok);
+
+        let mut query = ptr::null_mut();
+        assert_eq!(unsafe { ph_hash_buffer(bytes.as_ptr(), bytes.len(), PhAlgorithm::Dhash as u32, &mut query) }, PhStatus::Ok);
+        let (mut matches, mut count) = (ptr::null_mut(), 0);
+        assert_eq!(unsafe { ph_index_query(&index, query, 0, &mut matches, &mut count) }, PhStatus::Ok);
+        assert_eq!(count, 1);
+        unsafe {
+            assert_eq!(CStr::from_ptr((*matches).path), name.as_c_str());
+            ph_matches_free(matches, count);
+            ph_hash_free(query);
+        }
+    }
+}
@@ -0,0 +1,217 @@
+use super::handle::{HashingType, HashRecord};
+
+/// How the conditions of a `CompositeRule` are combined.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Combinator {
+    /// Every condition has to hold.
+    #[default]
+    All,
+    /// One condition holding is enough.
+    Any,
+    /// The weighted mean of `distance / max_distance` over the conditions has to be at most 1.
+    Weighted,
+}
+
+impl Combinator {
+    pub const ALL: [Combinator; 3] = [Combinator::All, Combinator::Any, Combinator::Weighted];
+
+    /// Name in the configuration.
+    pub fn id(self) -> &'static str {
+        match self {
+            Combinator::All => "all",
+            Combinator::Any => "any",
+            Combinator::Weighted => "weighted",
+        }
+    }
+
+    pub fn from_id(id: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|combinator| combinator.id() == id)
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            Combinator::All => Combinator::Any,
+            Combinator::Any => Combinator::Weighted,
+            Combinator::Weighted => Combinator::All,
+        }
+    }
+}
+
+impl std::fmt::Display for Combinator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Combinator::All => write!(f, "AND"),
+            Combinator::Any => write!(f, "OR"),
+            Combinator::Weighted => write!(f, "weighted"),
+        }
+    }
+}
+
+/// `hashing_type` distance of at most `max_distance`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Condition {
+    pub hashing_type: HashingType,
+    pub max_distance: u32,
+    /// Only used by `Combinator::Weighted`.
+    pub weight: f64,
+}
+
+impl Condition {
+    pub fn new(hashing_type: HashingType, max_distance: u32) -> Self {
+        Condition { hashing_type, max_distance, weight: 1.0 }
+    }
+}
+
+/// Match decision combining the distances of several algorithms.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompositeRule {
+    pub combinator: Combinator,
+    pub conditions: Vec<Condition>,
+}
+
+impl Default for CompositeRule {
+    fn default() -> Self {
+        CompositeRule {
+            combinator: Combinator::All,
+            conditions: vec![Condition::new(HashingType::DHash, 10), Condition::new(HashingType::PHash, 8)],
+        }
+    }
+}
+
+/// Outcome of a `CompositeRule` for one pair of files.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompositeResult {
+    /// Distance per condition, `None` when the hashes could not be compared.
+    pub distances: Vec<(HashingType, Option<u32>)>,
+    /// Weighted mean of the relative distances, only for `Combinator::Weighted`.
+    pub score: Option<f64>,
+    pub is_match: bool,
+}
+
+impl CompositeRule {
+    pub fn evaluate(&self, a: &HashRecord, b: &HashRecord) -> CompositeResult {
+        let distances: Vec<(HashingType, Option<u32>)> = self
+            .conditions
+            .iter()
+            .map(|condition| {
+                let distance = a.hashes
+                    .get(&condition.hashing_type)
+                    .zip(b.hashes.get(&condition.hashing_type))
+                    .and_then(|(a, b)| a.distance(b));
+                (condition.hashing_type, distance)
+            })
+            .collect();
+
+        let holds = |(condition, (_, distance)): (&Condition, &(HashingType, Option<u32>))| {
+            distance.is_some_and(|distance| distance <= condition.max_distance)
+        };
+        let mut score = None;
+        let is_match = match self.combinator {
+            Combinator::All => !self.conditions.is_empty() && self.conditions.iter().zip(&distances).all(holds),
+            Combinator::Any => self.conditions.iter().zip(&distances).any(holds),
+            Combinator::Weighted => {
+                score = self.weighted_score(&distances);
+                score.is_some_and(|score| score <= 1.0)
+            }
+        };
+
+        CompositeResult { distances, score, is_match }
+    }
+
+    // a missing distance makes the whole score unknown
+    fn weighted_score(&self, distances: &[(HashingType, Option<u32>)]) -> Option<f64> {
+        let total_weight: f64 = self.conditions.iter().map(|c| c.weight).sum();
+        if total_weight <= 0.0 {
+            return None;
+        }
+        let mut sum = 0.0;
+        for (condition, (_, distance)) in self.conditions.iter().zip(distances) {
+            sum += condition.weight * (*distance)? as f64 / condition.max_distance.max(1) as f64;
+        }
+        Some(sum / total_weight)
+    }
+}
+
+impl std::fmt::Display for CompositeResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, (hashing_type, distance)) in self.distances.iter().enumerate() {
+            if i > 0 {
+                write!(f, " · ")?;
+            }
+            match distance {
+                Some(distance) => write!(f, "{hashing_type} {distance}")?,
+                None => write!(f, "{hashing_type} -")?,
+            }
+        }
+        if let Some(score) = self.score {
+            write!(f, " = {score:.2}")?;
+        }
+        write!(f, " {}", if self.is_match { "✓" } else { "✗" })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hashing::{BitHash, Hash};
+    use super::*;
+
+    // dHash and pHash hashes with the given number of bits set
+    fn record(d_bits: u32, p_bits: u32) -> HashRecord {
+        let mut record = HashRecord::default();
+        let ones = |n: u32| Hash::Bits(BitHash::from_u64(((1u128 << n) - 1) as u64));
+        record.hashes.insert(HashingType::DHash, ones(d_bits));
+        record.hashes.insert(HashingType::PHash, ones(p_bits));
+        record
+    }
+
+    #[test]
+    fn and_or_require_all_or_any_condition() {
+        let (a, b) = (record(0, 0), record(4, 12));
+        let mut rule = CompositeRule::default();
+
+        let and = rule.evaluate(&a, &b);
+        assert_eq!(and.distances, vec![(HashingType::DHash, Some(4)), (HashingType::PHash, Some(12))]);
+        assert!(!and.is_match);
+        assert_eq!(and.to_string(), "dHash 4 · pHash 12 ✗");
+
+        rule.combinator = Combinator::Any;
+        assert!(rule.evaluate(&a, &b).is_match);
+    }
+
+    #[test]
+    fn weighted_rule_averages_relative_distances() {
+        let (a, b) = (record(0, 0), record(5, 12));
+        let mut rule = CompositeRule { combinator: Combinator::Weighted, ..Default::default() };
+
+        // (5 / 10 + 12 / 8) / 2 = 1.0
+        let result = rule.evaluate(&a, &b);
+        assert_eq!(result.score, Some(1.0));
+        assert!(result.is_match);
+
+        // pHash counting three times as much: (0.5 + 3 * 1.5) / 4 = 1.25
+        rule.conditions[1].weight = 3.0;
+        let result = rule.evaluate(&a, &b);
+        assert_eq!(result.score, Some(1.25));
+        assert!(!result.is_match);
+    }
+
+    #[test]
+    fn combinators_are_found_by_id() {
+        for combinator in Combinator::ALL {
+            assert_eq!(Combinator::from_id(combinator.id()), Some(combinator));
+        }
+        assert_eq!(Combinator::from_id("and"), None);
+    }
+
+    #[test]
+    fn missing_hashes_never_match() {
+        let a = record(0, 0);
+        let rule = CompositeRule {
+            combinator: Combinator::All,
+            conditions: vec![Condition::new(HashingType::Pdq, 30)],
+        };
+        let result = rule.evaluate(&a, &a);
+        assert_eq!(result.distances, vec![(HashingType::Pdq, None)]);
+        assert!(!result.is_match);
+    }
+}
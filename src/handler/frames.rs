@@ -0,0 +1,168 @@
+use crate::hashing::Hash;
+use super::score::similarity;
+
+/// How files with several hashed frames (animations) are compared.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum FrameMatch {
+    /// Distance of the closest pair of frames.
+    #[default]
+    BestFrame,
+    /// Share of the frames that have a counterpart at least `min_similarity` percent similar,
+    /// the smaller one of both files, so that a still is not a full match of a long animation.
+    /// The share is reported as a distance, `max_distance * (1 - share)`, so the similarity
+    /// percentage of the result is the percentage of matching frames.
+    Fraction { min_similarity: f64 },
+    /// Like `Fraction`, but frames are compared in order at the best offset, as suits videos
+    /// sampled at a fixed rate; the share is taken of the shorter sequence.
+    Aligned { min_similarity: f64 },
+}
+
+impl FrameMatch {
+    pub fn next(self) -> Self {
+        match self {
+            FrameMatch::BestFrame => FrameMatch::Fraction { min_similarity: 90.0 },
+            FrameMatch::Fraction { min_similarity } => FrameMatch::Aligned { min_similarity },
+            FrameMatch::Aligned { .. } => FrameMatch::BestFrame,
+        }
+    }
+}
+
+impl std::fmt::Display for FrameMatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FrameMatch::BestFrame => write!(f, "best frame"),
+            FrameMatch::Fraction { min_similarity } => write!(f, "frames ≥ {min_similarity:.0}%"),
+            FrameMatch::Aligned { min_similarity } => write!(f, "aligned ≥ {min_similarity:.0}%"),
+        }
+    }
+}
+
+/// Distance between two sequences of frame hashes; stills are sequences of one frame.
+/// `None` when no pair of frames can be compared.
+pub fn frames_distance(a: &[&Hash], b: &[&Hash], max_distance: u32, mode: FrameMatch) -> Option<u32> {
+    match mode {
+        FrameMatch::BestFrame => best_frame(a, b),
+        FrameMatch::Fraction { min_similarity } => {
+            // without a comparable pair there is no share, not a share of 0
+            best_frame(a, b)?;
+            let share = matching_share(a, b, max_distance, min_similarity).min(matching_share(b, a, max_distance, min_similarity));
+            Some((max_distance as f64 * (1.0 - share)).round() as u32)
+        }
+        FrameMatch::Aligned { min_similarity } => {
+            let alignment = align(a, b, max_distance, min_similarity)?;
+            Some((max_distance as f64 * (1.0 - alignment.match_ratio)).round() as u32)
+        }
+    }
+}
+
+// distance of the closest pair of frames
+fn best_frame(a: &[&Hash], b: &[&Hash]) -> Option<u32> {
+    a.iter().flat_map(|frame| b.iter().filter_map(|other| frame.distance(other))).min()
+}
+
+// share of the frames of `a` with a counterpart in `b` at least `min_similarity` percent similar
+fn matching_share(a: &[&Hash], b: &[&Hash], max_distance: u32, min_similarity: f64) -> f64 {
+    let matching = a
+        .iter()
+        .filter(|frame| b.iter().filter_map(|other| frame.distance(other)).any(|distance| similarity(distance, max_distance) >= min_similarity))
+        .count();
+    matching as f64 / a.len() as f64
+}
+
+/// Best overlap of two frame sequences sampled at the same rate.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Alignment {
+    /// Frame `i` of the first sequence lines up with frame `i + offset` of the second one.
+    pub offset: i64,
+    /// Share of the frames of the shorter sequence that match at this offset.
+    pub match_ratio: f64,
+}
+
+/// Slides `b` along `a` and keeps the offset at which most frames are at least `min_similarity`
+/// percent similar, the smallest shift winning ties. `None` when no pair of frames can be compared.
+pub fn align(a: &[&Hash], b: &[&Hash], max_distance: u32, min_similarity: f64) -> Option<Alignment> {
+    let mut best: Option<(usize, i64)> = None;
+    for offset in -(a.len() as i64 - 1)..b.len() as i64 {
+        let mut compared = false;
+        let mut matching = 0;
+        for (i, frame) in a.iter().enumerate() {
+            let Some(other) = usize::try_from(i as i64 + offset).ok().and_then(|j| b.get(j)) else {
+                continue;
+            };
+            if let Some(distance) = frame.distance(other) {
+                compared = true;
+                if similarity(distance, max_distance) >= min_similarity {
+                    matching += 1;
+                }
+            }
+        }
+        let better = best.is_none_or(|(best_matching, best_offset)| {
+            matching > best_matching || (matching == best_matching && offset.abs() < best_offset.abs())
+        });
+        if compared && better {
+            best = Some((matching, offset));
+        }
+    }
+
+    let (matching, offset) = best?;
+    Some(Alignment { offset, match_ratio: matching as f64 / a.len().min(b.len()) as f64 })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hashing::BitHash;
+    use super::*;
+
+    fn hash(value: u64) -> Hash {
+        Hash::Bits(BitHash::from_u64(value))
+    }
+
+    #[test]
+    fn best_frame_finds_a_still_inside_an_animation() {
+        let animation = [hash(0xff00), hash(0x0f0f), hash(0xffff_0000)];
+        let still = [hash(0x0f0f)];
+        let frames: Vec<&Hash> = animation.iter().collect();
+        let stills: Vec<&Hash> = still.iter().collect();
+        assert_eq!(frames_distance(&stills, &frames, 64, FrameMatch::BestFrame), Some(0));
+        assert_eq!(frames_distance(&frames, &stills, 64, FrameMatch::BestFrame), Some(0));
+    }
+
+    #[test]
+    fn fraction_counts_matching_frames() {
+        let a = [hash(0), hash(0xff), hash(u64::MAX)];
+        let b = [hash(0), hash(0xfe)];
+        let (a, b): (Vec<&Hash>, Vec<&Hash>) = (a.iter().collect(), b.iter().collect());
+        let mode = FrameMatch::Fraction { min_similarity: 90.0 };
+
+        // the first two frames of `a` have close counterparts, the inverted one has not
+        let distance = frames_distance(&a, &b, 64, mode).unwrap();
+        assert_eq!(distance, 21);
+        assert!((similarity(distance, 64) - 200.0 / 3.0).abs() < 1.0);
+        // both frames of `b` match, but the smaller share counts either way round
+        assert_eq!(frames_distance(&b, &a, 64, mode), Some(21));
+    }
+
+    #[test]
+    fn a_still_matches_a_fraction_of_an_animation() {
+        let animation = [hash(0xff00), hash(0x0f0f), hash(0xffff_0000), hash(u64::MAX)];
+        let still = [hash(0x0f0f)];
+        let (frames, stills): (Vec<&Hash>, Vec<&Hash>) = (animation.iter().collect(), still.iter().collect());
+        let mode = FrameMatch::Fraction { min_similarity: 90.0 };
+        assert_eq!(frames_distance(&stills, &frames, 64, mode), Some(48));
+        assert_eq!(frames_distance(&frames, &stills, 64, mode), Some(48));
+    }
+
+    #[test]
+    fn a_cut_clip_is_aligned_at_its_offset() {
+        let video: Vec<Hash> = (0..10).map(|i| hash(0x0123_4567_89ab_cdef_u64.rotate_left(i * 7))).collect();
+        // seconds 3 to 6 of the video, with one frame damaged by the re-encode
+        let mut clip: Vec<Hash> = video[3..7].to_vec();
+        clip[1] = hash(!0x0123_4567_89ab_cdef_u64.rotate_left(4 * 7));
+        let (video, clip): (Vec<&Hash>, Vec<&Hash>) = (video.iter().collect(), clip.iter().collect());
+
+        let alignment = align(&clip, &video, 64, 90.0).unwrap();
+        assert_eq!(alignment, Alignment { offset: 3, match_ratio: 0.75 });
+        assert_eq!(align(&video, &clip, 64, 90.0).unwrap().offset, -3);
+        assert_eq!(frames_distance(&video, &clip, 64, FrameMatch::Aligned { min_similarity: 90.0 }), Some(16));
+    }
+}
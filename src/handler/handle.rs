@@ -1,23 +1,216 @@
 use std::collections::HashMap;
 use std::fs;
+use std::io::{BufRead, Cursor, Seek};
+use std::ops::ControlFlow;
 use std::path::{Path, PathBuf};
-use image::ImageError;
+use std::sync::mpsc::Sender;
+use std::time::Instant;
+use image::{DynamicImage, GrayImage, ImageError};
 use rayon::prelude::*;
+use tracing::{debug, info, warn};
 
-use crate::hashing;
+use crate::hashing::{self, BitHash, Encoding, Hash, HashingOptions, encoding};
+use crate::hashing::color::{ColorMode, ColorMoments};
+use crate::hashing::radial_hash::RadialDigest;
+use crate::hashing::segments::SegmentHashes;
+use crate::preprocessing::archive::{ArchiveKind, count_entries, locator, visit_entries};
+use crate::preprocessing::preprocessor::{PreprocessOptions, flatten_alpha, load_frames_and_originals, load_frames_and_originals_reader};
+use super::progress::{Cancelled, CancellationToken, ProgressEvent};
+use super::frames::{FrameMatch, frames_distance};
+use super::tags::{TagStore, tagged_record};
+use super::scan::ScanFilter;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum HashingType {
     DHash,
     PHash,
+    Blockhash,
+    Pdq,
+    Radial,
+    MarrHildreth,
+    ColorMoments,
+    Segments,
+}
+
+/// Hashes of a single file, one per `HashingType`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HashRecord {
+    pub hashes: HashMap<HashingType, Hash>,
+    /// Quality of the image between 0 and 100, as reported by PDQ.
+    pub quality: Option<u8>,
+    /// Hashes of the sampled frames of an animation, the first frame included; empty for still images.
+    pub frames: Vec<HashMap<HashingType, Hash>>,
+}
+
+impl HashRecord {
+    // every frame of an animation, or the only hash of a still image
+    pub fn frame_hashes(&self, hashing_type: HashingType) -> Vec<&Hash> {
+        if self.frames.is_empty() {
+            self.hashes.get(&hashing_type).into_iter().collect()
+        } else {
+            self.frames.iter().filter_map(|frame| frame.get(&hashing_type)).collect()
+        }
+    }
+}
+
+/// What `calculate_records` hashes in a folder, how, and where it keeps the hashes of files.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScanOptions {
+    pub options: PreprocessOptions,
+    pub hashing_options: HashingOptions,
+    /// Hashes of files are taken from this store when current, and stored there otherwise.
+    pub tags: Option<TagStore>,
+    pub filter: ScanFilter,
+}
+
+/// Comparison settings that can change without hashing the files again.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MatchOptions {
+    /// PDQ hashes of images below this quality are left out, as they tend to produce false matches.
+    pub min_quality: Option<u8>,
+    pub frames: FrameMatch,
 }
 
 impl HashingType {
+    pub const ALL: [HashingType; 8] = [HashingType::DHash, HashingType::PHash, HashingType::Blockhash, HashingType::Pdq, HashingType::Radial, HashingType::MarrHildreth, HashingType::ColorMoments, HashingType::Segments];
+
     pub fn from_index(i: usize) -> Option<Self> {
-        match i {
-            0 => Some(Self::DHash),
-            1 => Some(Self::PHash),
-            _ => None
+        Self::ALL.get(i).copied()
+    }
+
+    /// Stable lower-case name, used in index files, CSV headers and on the command line.
+    pub fn id(self) -> &'static str {
+        match self {
+            HashingType::DHash => "dhash",
+            HashingType::PHash => "phash",
+            HashingType::Blockhash => "blockhash",
+            HashingType::Pdq => "pdq",
+            HashingType::Radial => "radial",
+            HashingType::MarrHildreth => "marr-hildreth",
+            HashingType::ColorMoments => "color-moments",
+            HashingType::Segments => "segments",
+        }
+    }
+
+    pub fn from_id(id: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|t| t.id() == id)
+    }
+
+    /// `hash` as a string of `encoding`; `None` when the other library has no such hash. Imagehash
+    /// strings converted from the hashes of this crate can be a few bits off the ones imagehash prints
+    /// for the same image, `imagehash_strings` computes those.
+    pub fn format_hash(self, hash: &Hash, encoding: Encoding) -> Option<String> {
+        match encoding {
+            Encoding::Native => Some(hash.to_hex()),
+            Encoding::Imagehash => {
+                let Hash::Bits(bits) = hash else {
+                    return None;
+                };
+                let value = bits.to_u64()?;
+                match self {
+                    HashingType::DHash => Some(encoding::imagehash_hex(encoding::d_hash_to_imagehash(value))),
+                    HashingType::PHash => Some(encoding::imagehash_hex(encoding::p_hash_to_imagehash(value))),
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    /// Inverse of `format_hash` for hashes of this algorithm computed with `options`;
+    /// `None` when `hex` is not such a hash. Imagehash strings give an approximation of the hash
+    /// of this crate, close enough to look up near-duplicates.
+    pub fn parse_hash(self, hex: &str, encoding: Encoding, options: &HashingOptions) -> Option<Hash> {
+        match (encoding, self) {
+            (Encoding::Native, HashingType::Radial) => RadialDigest::from_hex(hex).map(Hash::Radial),
+            (Encoding::Native, HashingType::ColorMoments) => ColorMoments::from_hex(hex).map(Hash::Moments),
+            // at least as many tiles along the longer side as along the shorter one
+            (Encoding::Native, HashingType::Segments) => SegmentHashes::from_hex(hex)
+                .filter(|segments| {
+                    let (count, per_side) = (segments.segments().len(), options.segments.tiles_per_side());
+                    count.is_multiple_of(per_side) && count >= per_side * per_side
+                })
+                .map(Hash::Segments),
+            // the bit hashes have one bit per unit of distance
+            (Encoding::Native, _) => BitHash::from_hex(hex, self.max_distance(options) as usize).map(Hash::Bits),
+            // imagehash hashes the luminance only
+            (Encoding::Imagehash, HashingType::DHash) if options.color == ColorMode::Grayscale => {
+                encoding::parse_imagehash_hex(hex).map(|value| Hash::Bits(BitHash::from_u64(encoding::d_hash_from_imagehash(value))))
+            }
+            (Encoding::Imagehash, HashingType::PHash) if options.color == ColorMode::Grayscale => {
+                encoding::parse_imagehash_hex(hex).map(|value| Hash::Bits(BitHash::from_u64(encoding::p_hash_from_imagehash(value))))
+            }
+            (Encoding::Imagehash, _) => None,
+        }
+    }
+
+    // longest side of the image the algorithm works on
+    pub fn input_size(self, options: &HashingOptions) -> u32 {
+        match self {
+            HashingType::DHash => hashing::d_hash::IMG_WIDTH as u32,
+            HashingType::PHash => hashing::p_hash::IMG_SIZE,
+            // blockhash works on the original image, see `needs_original`; one pixel per block is the least it needs
+            HashingType::Blockhash => options.blockhash.bits,
+            // PDQ filters the original image down to its buffer itself, see `needs_original`
+            HashingType::Pdq => hashing::pdq_hash::BUFFER_SIZE as u32,
+            // radial lines need some resolution to sample the image from
+            HashingType::Radial => 256,
+            HashingType::MarrHildreth => hashing::marr_hildreth::IMG_SIZE,
+            HashingType::ColorMoments => hashing::color::IMG_SIZE,
+            HashingType::Segments => hashing::segments::IMG_SIZE,
+        }
+    }
+
+    /// Whether the algorithm is defined on the original pixels, so that it has to see the image
+    /// decoded in full even with `DecodeMode::Fast`; the others resample it to `input_size` anyway.
+    pub fn needs_original(self) -> bool {
+        matches!(self, HashingType::Blockhash | HashingType::Pdq)
+    }
+
+    // largest possible distance between two hashes, for similarity percentages
+    pub fn max_distance(self, options: &HashingOptions) -> u32 {
+        match self {
+            HashingType::DHash | HashingType::PHash => match options.color {
+                ColorMode::Grayscale => 64,
+                ColorMode::PerChannel => 3 * 64,
+            },
+            HashingType::Blockhash => options.blockhash.bits * options.blockhash.bits,
+            HashingType::Pdq => 256,
+            HashingType::Radial => hashing::radial_hash::MAX_DISTANCE,
+            HashingType::MarrHildreth => hashing::marr_hildreth::BITS,
+            HashingType::ColorMoments => hashing::color::MAX_DISTANCE,
+            HashingType::Segments => hashing::segments::MAX_DISTANCE,
+        }
+    }
+
+    /// Distance up to which watch mode and queries report files by default, i.e. 90% similarity;
+    /// for segments the distance up to which `SegmentHashes::is_match` holds.
+    pub fn near_duplicate_distance(self, options: &HashingOptions) -> u32 {
+        match self {
+            HashingType::Segments => hashing::segments::MATCH_DISTANCE,
+            _ => self.max_distance(options) / 10,
+        }
+    }
+
+    // the hash and its quality, for algorithms that provide one
+    pub fn hash(self, img: &DynamicImage, options: &HashingOptions) -> (Hash, Option<u8>) {
+        match self {
+            HashingType::DHash | HashingType::PHash => {
+                let hash = if self == HashingType::DHash { hashing::d_hash_image } else { hashing::p_hash_image };
+                let bits = match options.color {
+                    ColorMode::Grayscale => BitHash::from_u64(hash(img)),
+                    ColorMode::PerChannel => hashing::color::per_channel(img, hash),
+                };
+                (Hash::Bits(bits), None)
+            }
+            HashingType::Blockhash => (Hash::Bits(hashing::block_hash(img, &options.blockhash)), None),
+            HashingType::Pdq => {
+                let pdq = hashing::pdq_hash(img);
+                (Hash::Bits(pdq.hash), Some(pdq.quality))
+            }
+            HashingType::Radial => (Hash::Radial(hashing::radial_hash(img)), None),
+            HashingType::MarrHildreth => (Hash::Bits(hashing::marr_hildreth_hash(img, &options.marr_hildreth)), None),
+            HashingType::ColorMoments => (Hash::Moments(hashing::color_moment_hash(img)), None),
+            HashingType::Segments => (Hash::Segments(hashing::segment_hash(img, &options.segments)), None),
         }
     }
 }
@@ -27,53 +220,517 @@ impl std::fmt::Display for HashingType {
         match self {
             HashingType::DHash => write!(f, "dHash"),
             HashingType::PHash => write!(f, "pHash"),
+            HashingType::Blockhash => write!(f, "blockhash"),
+            HashingType::Pdq => write!(f, "PDQ"),
+            HashingType::Radial => write!(f, "Radial"),
+            HashingType::MarrHildreth => write!(f, "Marr-Hildreth"),
+            HashingType::ColorMoments => write!(f, "Color moments"),
+            HashingType::Segments => write!(f, "Segments"),
         }
     }
 }
 
-fn calculate_hashes(path: &Path, hashing_func: fn(path: &Path) -> Result<u64, ImageError>) -> HashMap<PathBuf, u64> {
-    if let Ok(paths) = fs::read_dir(path) {
-        let file_paths: Vec<PathBuf> = paths
-            .flatten()
-            .map(|entry| entry.path())
-            .filter(|path| path.is_file())
-            .collect();
+/// Hashes the file at `path`, or the archive entry a locator points at, with every algorithm;
+/// the image is decoded once and every hasher runs over it, unless a fast decode reduced it and
+/// the algorithms that need the original get a full decode of their own.
+pub fn calculate_record(path: &Path, options: &PreprocessOptions, hashing_options: &HashingOptions) -> Result<HashRecord, ImageError> {
+    let (frames, originals) = load_frames_and_originals(path, min_size(hashing_options), options)?;
+    Ok(hash_frames(&frames, originals.as_deref(), hashing_options))
+}
+
+/// Hashes an image that is streamed, e.g. an upload, with every algorithm; like for files,
+/// animations are sampled as `options` say.
+pub fn hash_reader<R: BufRead + Seek>(reader: R, options: &PreprocessOptions, hashing_options: &HashingOptions) -> Result<HashRecord, ImageError> {
+    let (frames, originals) = load_frames_and_originals_reader(reader, min_size(hashing_options), options)?;
+    Ok(hash_frames(&frames, originals.as_deref(), hashing_options))
+}
+
+/// `hash_reader` over a byte buffer.
+pub fn hash_bytes(bytes: &[u8], options: &PreprocessOptions, hashing_options: &HashingOptions) -> Result<HashRecord, ImageError> {
+    hash_reader(Cursor::new(bytes), options, hashing_options)
+}
+
+/// Hashes the file at `path` with one algorithm, decoded the way `calculate_record` decodes it, so that
+/// the hash is the one an index with the same settings holds for the file; animations give their first frame.
+pub fn calculate_hash(path: &Path, hashing_type: HashingType, options: &PreprocessOptions, hashing_options: &HashingOptions) -> Result<Hash, ImageError> {
+    let (frames, originals) = load_frames_and_originals(path, min_size(hashing_options), options)?;
+    Ok(hash_first_frame(hashing_type, &frames, originals.as_deref(), hashing_options))
+}
+
+/// `calculate_hash` for an encoded image in memory, like `hash_bytes`.
+pub fn hash_buffer(bytes: &[u8], hashing_type: HashingType, options: &PreprocessOptions, hashing_options: &HashingOptions) -> Result<Hash, ImageError> {
+    let (frames, originals) = load_frames_and_originals_reader(Cursor::new(bytes), min_size(hashing_options), options)?;
+    Ok(hash_first_frame(hashing_type, &frames, originals.as_deref(), hashing_options))
+}
+
+/// Hashes an already decoded image with every algorithm; its alpha channel is flattened as `options` say.
+pub fn hash_image(img: &DynamicImage, options: &PreprocessOptions, hashing_options: &HashingOptions) -> HashRecord {
+    hash_frames(&[flatten_alpha(img.clone(), options.alpha)], None, hashing_options)
+}
+
+/// Hashes an already decoded grayscale image with every algorithm; colour based hashes see it as grey.
+pub fn hash_gray(img: &GrayImage, hashing_options: &HashingOptions) -> HashRecord {
+    hash_frames(&[DynamicImage::ImageLuma8(img.clone())], None, hashing_options)
+}
+
+/// The strings Python's imagehash prints for `img` by algorithm, for dHash and pHash, computed like
+/// it computes them through Pillow (see `hashing::imagehash`) rather than converted from the hashes
+/// of this crate. `img` should be the image as stored, decoded in full like `load_preview` does.
+pub fn imagehash_strings(img: &DynamicImage) -> Vec<(HashingType, String)> {
+    vec![
+        (HashingType::DHash, encoding::imagehash_hex(hashing::imagehash::d_hash(img))),
+        (HashingType::PHash, encoding::imagehash_hex(hashing::imagehash::p_hash(img))),
+    ]
+}
+
+// largest hash input, the size images are decoded at
+fn min_size(hashing_options: &HashingOptions) -> u32 {
+    HashingType::ALL.iter().map(|t| t.input_size(hashing_options)).max().unwrap_or(0)
+}
+
+// the hash `hash_frames` gives the file, without running the other algorithms
+fn hash_first_frame(hashing_type: HashingType, frames: &[DynamicImage], originals: Option<&[DynamicImage]>, hashing_options: &HashingOptions) -> Hash {
+    let img = frames.first().expect("at least one frame");
+    let original = originals.and_then(|originals| originals.first()).unwrap_or(img);
+    hashing_type.hash(if hashing_type.needs_original() { original } else { img }, hashing_options).0
+}
+
+// `originals` are the same frames at full resolution, when `frames` are not
+fn hash_frames(frames: &[DynamicImage], originals: Option<&[DynamicImage]>, hashing_options: &HashingOptions) -> HashRecord {
+    let mut record = HashRecord::default();
+    for (i, img) in frames.iter().enumerate() {
+        let original = originals.and_then(|originals| originals.get(i)).unwrap_or(img);
+        let mut hashes = HashMap::new();
+        for hashing_type in HashingType::ALL {
+            let input = if hashing_type.needs_original() { original } else { img };
+            let (hash, quality) = hashing_type.hash(input, hashing_options);
+            hashes.insert(hashing_type, hash);
+            record.quality = record.quality.or(quality);
+        }
+        // the first frame stands for the whole file where frames are not looked at
+        if i == 0 {
+            record.hashes = hashes.clone();
+        }
+        if frames.len() > 1 {
+            record.frames.push(hashes);
+        }
+    }
+    record
+}
+
+// archive entries hashed in parallel at a time
+const ARCHIVE_BATCH: usize = 32;
+
+/// Hashes every file in `path` that the filter of `scan` accepts, and every file in the ZIP and TAR archives there, reporting to `progress`
+/// (if given) as it goes. Files that are not images are skipped; `Err(Cancelled)` is returned once `cancel` is triggered. With tags, the hashes of
+/// files (not archive entries) are taken from there when current and stored there otherwise.
+pub fn calculate_records(
+    path: &Path,
+    scan: &ScanOptions,
+    progress: Option<&Sender<ProgressEvent>>,
+    cancel: &CancellationToken
+) -> Result<HashMap<PathBuf, HashRecord>, Cancelled> {
+    let ScanOptions { options, hashing_options, tags, filter } = scan;
+    // the receiver may be gone already, which is not an error for the hashing itself
+    let report = |event| {
+        if let Some(tx) = progress {
+            tx.send(event).ok();
+        }
+    };
+
+    let paths = match fs::read_dir(path) {
+        Ok(paths) => paths,
+        Err(err) => {
+            warn!(dir = %path.display(), error = %err, "reading the folder failed");
+            report(ProgressEvent::Discovered(0));
+            return Ok(HashMap::new());
+        }
+    };
+    let started = Instant::now();
+
+    // archives are virtual directories, their entries are hashed without being extracted
+    let (archives, file_paths): (Vec<PathBuf>, Vec<PathBuf>) = paths
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && filter.accepts(path))
+        // sidecars written along the images are not images themselves
+        .filter(|path| *tags != Some(TagStore::Xmp) || path.extension().is_none_or(|extension| extension != "xmp"))
+        .partition(|path| ArchiveKind::of(path).is_some());
+    // an unreadable archive counts as one failed file
+    let entries: usize = archives.iter().map(|archive| count_entries(archive).unwrap_or(1)).sum();
+    report(ProgressEvent::Discovered(file_paths.len() + entries));
+    info!(dir = %path.display(), files = file_paths.len(), archives = archives.len(), entries, "scan started");
+
+    let track = |cur_path: &PathBuf, file_started: Instant, record: Result<HashRecord, ImageError>| match record {
+        Ok(record) => {
+            debug!(path = %cur_path.display(), elapsed = ?file_started.elapsed(), "hashed");
+            report(ProgressEvent::Hashed(cur_path.clone()));
+            Some((cur_path.clone(), record))
+        }
+        Err(err) => { // fault tolerance
+            log_failure(cur_path, &err);
+            report(ProgressEvent::Failed(cur_path.clone()));
+            None
+        }
+    };
+
+    let mut records: HashMap<PathBuf, HashRecord> = file_paths
+        .par_iter()
+        .filter_map(|cur_path| {
+            if cancel.is_cancelled() {
+                return None;
+            }
+            report(ProgressEvent::Started(cur_path.clone()));
+            let file_started = Instant::now();
+            let record = match tags {
+                Some(store) => tagged_record(cur_path, *store, options, hashing_options),
+                None => calculate_record(cur_path, options, hashing_options),
+            };
+            track(cur_path, file_started, record)
+        })
+        .collect();
 
-        file_paths
+    // entries are read in order and hashed ARCHIVE_BATCH at a time, so only a batch is held in memory
+    let hash_batch = |batch: Vec<(PathBuf, Vec<u8>)>| -> Vec<(PathBuf, HashRecord)> {
+        batch
             .par_iter()
-            .filter_map(|cur_path| {
-                hashing_func(cur_path)
-                    .ok() // fault tolerance
-                    .map(|cur_hash| (cur_path.clone(), cur_hash))
+            .filter_map(|(cur_path, bytes)| {
+                if cancel.is_cancelled() {
+                    return None;
+                }
+                report(ProgressEvent::Started(cur_path.clone()));
+                track(cur_path, Instant::now(), hash_bytes(bytes, options, hashing_options))
             })
             .collect()
+    };
+    for archive in &archives {
+        let mut batch = Vec::with_capacity(ARCHIVE_BATCH);
+        let visited = visit_entries(archive, |name, bytes| {
+            batch.push((locator(archive, &name), bytes));
+            if batch.len() == ARCHIVE_BATCH {
+                records.extend(hash_batch(std::mem::take(&mut batch)));
+            }
+            if cancel.is_cancelled() { ControlFlow::Break(()) } else { ControlFlow::Continue(()) }
+        });
+        records.extend(hash_batch(batch));
+        if let Err(err) = visited {
+            warn!(archive = %archive.display(), error = %err, "reading the archive failed");
+            report(ProgressEvent::Failed(archive.clone()));
+        }
+    }
+
+    if cancel.is_cancelled() {
+        info!(dir = %path.display(), hashed = records.len(), elapsed = ?started.elapsed(), "scan cancelled");
+        Err(Cancelled)
     } else {
-        HashMap::new()
+        info!(dir = %path.display(), hashed = records.len(), elapsed = ?started.elapsed(), "scan finished");
+        Ok(records)
     }
 }
 
-pub fn calculate_similarity(path: &Path, hashing_type: HashingType) -> HashMap<PathBuf, Vec<(PathBuf, u32)>> {
-    let hashes_map = match hashing_type {
-        HashingType::PHash => calculate_hashes(path, hashing::p_hash),
-        HashingType::DHash => calculate_hashes(path, hashing::d_hash),
-    };
+// files that are not images are expected in a folder, images that cannot be decoded are not
+fn log_failure(path: &Path, err: &ImageError) {
+    match err {
+        ImageError::Unsupported(_) => debug!(path = %path.display(), error = %err, "skipped"),
+        _ => warn!(path = %path.display(), error = %err, "hashing failed"),
+    }
+}
 
-    if hashes_map.len() < 2 {
+/// Distances between every pair of files; animations are compared frame by frame.
+pub fn calculate_similarity(
+    records: &HashMap<PathBuf, HashRecord>,
+    hashing_type: HashingType,
+    hashing_options: &HashingOptions,
+    match_options: &MatchOptions
+) -> HashMap<PathBuf, Vec<(PathBuf, u32)>> {
+    let image_entries: Vec<(&PathBuf, Vec<&Hash>)> = records
+        .iter()
+        .filter(|(_, record)| is_reliable(record, hashing_type, match_options.min_quality))
+        .map(|(path, record)| (path, record.frame_hashes(hashing_type)))
+        .collect();
+    let max_distance = hashing_type.max_distance(hashing_options);
+
+    if image_entries.len() < 2 {
         return HashMap::new();
     }
 
-    let image_entries: Vec<(&PathBuf, &u64)> = hashes_map.iter().collect();
-    
     image_entries
         .par_iter()
         .map(|(path1, hash1)| {
             let mut distances = Vec::new();
             for (path2, hash2) in image_entries.iter() {
-                let distance = (**hash1 ^ **hash2).count_ones();
-                distances.push(((*path2).clone(), distance));
+                if let Some(distance) = frames_distance(hash1, hash2, max_distance, match_options.frames) {
+                    distances.push(((*path2).clone(), distance));
+                }
             }
             distances.sort_by_key(|&(_, distance)| distance);
             ((*path1).clone(), distances)
         })
         .collect()
-}
\ No newline at end of file
+}
+
+/// Distance between two files as `calculate_similarity` computes it, for updating its result one
+/// file at a time; `None` when either file is left out for its PDQ quality or the hashes do not compare.
+pub fn record_distance(
+    a: &HashRecord,
+    b: &HashRecord,
+    hashing_type: HashingType,
+    hashing_options: &HashingOptions,
+    match_options: &MatchOptions
+) -> Option<u32> {
+    if !is_reliable(a, hashing_type, match_options.min_quality) || !is_reliable(b, hashing_type, match_options.min_quality) {
+        return None;
+    }
+    frames_distance(&a.frame_hashes(hashing_type), &b.frame_hashes(hashing_type), hashing_type.max_distance(hashing_options), match_options.frames)
+}
+
+fn is_reliable(record: &HashRecord, hashing_type: HashingType, min_quality: Option<u8>) -> bool {
+    match (hashing_type, min_quality, record.quality) {
+        (HashingType::Pdq, Some(min_quality), Some(quality)) => quality >= min_quality,
+        _ => true,
+    }
+}
+
+/// Files whose `hashing_type` hash is within `max_distance` of `query`, closest first; for
+/// animations the closest frame counts. Lets externally published hashes (e.g. parsed with
+/// `BitHash::from_hex`) be matched against a scan.
+pub fn find_matches(
+    records: &HashMap<PathBuf, HashRecord>,
+    hashing_type: HashingType,
+    query: &Hash,
+    max_distance: u32,
+    min_quality: Option<u8>
+) -> Vec<(PathBuf, u32)> {
+    let mut matches: Vec<(PathBuf, u32)> = records
+        .iter()
+        .filter(|(_, record)| is_reliable(record, hashing_type, min_quality))
+        .filter_map(|(path, record)| {
+            // hashes computed with other parameters cannot be compared
+            let distance = record.frame_hashes(hashing_type).iter().filter_map(|hash| hash.distance(query)).min()?;
+            (distance <= max_distance).then(|| (path.clone(), distance))
+        })
+        .collect();
+    matches.sort_by(|(path1, distance1), (path2, distance2)| distance1.cmp(distance2).then_with(|| path1.cmp(path2)));
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use crate::preprocessing::animation::FrameSampling;
+    use crate::test_support::write_archive;
+    use crate::preprocessing::decoder::DecodeMode;
+    use crate::preprocessing::preprocessor::{load, load_preview};
+    use super::*;
+
+    #[test]
+    fn one_decode_serves_every_algorithm() {
+        let scan = ScanOptions::default();
+        let (options, hashing_options) = (scan.options, scan.hashing_options);
+        let records = calculate_records(Path::new("test_images"), &scan, None, &CancellationToken::new())
+            .expect("not cancelled");
+        assert_eq!(records.len(), 4);
+
+        for (path, record) in &records {
+            for hashing_type in HashingType::ALL {
+                let img = load(path, hashing_type.input_size(&hashing_options), &options).expect("load failed");
+                assert_eq!(record.hashes[&hashing_type], hashing_type.hash(&img, &hashing_options).0, "{hashing_type} differs for {path:?}");
+            }
+        }
+
+        // every file is compared with every other one, itself included
+        let similarity = calculate_similarity(&records, HashingType::PHash, &hashing_options, &MatchOptions::default());
+        for distances in similarity.values() {
+            assert_eq!(distances.len(), 4);
+            assert_eq!(distances[0].1, 0);
+        }
+    }
+
+    #[test]
+    fn progress_is_reported_and_cancellation_stops_the_job() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let scan = ScanOptions::default();
+        calculate_records(Path::new("test_images"), &scan, Some(&tx), &CancellationToken::new())
+            .expect("not cancelled");
+        drop(tx);
+
+        let events: Vec<ProgressEvent> = rx.iter().collect();
+        assert_eq!(events[0], ProgressEvent::Discovered(4));
+        assert_eq!(events.iter().filter(|e| matches!(e, ProgressEvent::Hashed(_))).count(), 4);
+
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        assert_eq!(calculate_records(Path::new("test_images"), &scan, None, &cancel), Err(Cancelled));
+    }
+
+    #[test]
+    fn published_blockhash_is_matched_by_hex() {
+        let scan = ScanOptions::default();
+        let records = calculate_records(Path::new("test_images"), &scan, None, &CancellationToken::new())
+            .expect("not cancelled");
+
+        let target = Path::new("test_images").join("test3.png");
+        let published = records[&target].hashes[&HashingType::Blockhash].to_hex();
+        let query = Hash::Bits(BitHash::from_hex(&published, 256).expect("valid hex"));
+
+        let matches = find_matches(&records, HashingType::Blockhash, &query, 0, None);
+        assert_eq!(matches, vec![(target, 0)]);
+    }
+
+    #[test]
+    fn hashes_round_trip_through_both_encodings() {
+        let scan = ScanOptions::default();
+        let hashing_options = scan.hashing_options;
+        let records = calculate_records(Path::new("test_images"), &scan, None, &CancellationToken::new())
+            .expect("not cancelled");
+
+        for record in records.values() {
+            for hashing_type in HashingType::ALL {
+                let hash = &record.hashes[&hashing_type];
+                let native = hashing_type.format_hash(hash, Encoding::Native).unwrap();
+                let parsed = hashing_type.parse_hash(&native, Encoding::Native, &hashing_options).unwrap();
+                // colour moments are printed with 16 bits each
+                assert_eq!(parsed.distance(hash), Some(0), "{hashing_type}");
+
+                match hashing_type.format_hash(hash, Encoding::Imagehash) {
+                    Some(hex) => {
+                        assert!(matches!(hashing_type, HashingType::DHash | HashingType::PHash));
+                        assert_eq!(hashing_type.parse_hash(&hex, Encoding::Imagehash, &hashing_options).as_ref(), Some(hash));
+                    }
+                    None => assert!(!matches!(hashing_type, HashingType::DHash | HashingType::PHash)),
+                }
+            }
+        }
+
+        // imagehash has no per channel hashes
+        let per_channel = HashingOptions { color: ColorMode::PerChannel, ..Default::default() };
+        assert_eq!(HashingType::PHash.parse_hash("ad91c392346f49f4", Encoding::Imagehash, &per_channel), None);
+    }
+
+    #[test]
+    fn low_quality_pdq_hashes_are_filtered_out() {
+        let mut records = HashMap::new();
+        for (name, quality) in [("a", 80), ("b", 90), ("flat", 0)] {
+            let mut record = HashRecord { quality: Some(quality), ..Default::default() };
+            record.hashes.insert(HashingType::Pdq, Hash::Bits(BitHash::from_bits([false; 256])));
+            records.insert(PathBuf::from(name), record);
+        }
+
+        let hashing_options = HashingOptions::default();
+        let all = calculate_similarity(&records, HashingType::Pdq, &hashing_options, &MatchOptions::default());
+        assert_eq!(all.len(), 3);
+
+        let match_options = MatchOptions { min_quality: Some(hashing::pdq_hash::MIN_QUALITY), ..Default::default() };
+        let filtered = calculate_similarity(&records, HashingType::Pdq, &hashing_options, &match_options);
+        assert_eq!(filtered.len(), 2);
+        assert!(!filtered.contains_key(Path::new("flat")));
+    }
+
+    #[test]
+    fn a_still_matches_the_animation_containing_it() {
+        let animation = crate::test_support::write_gif("contains_still", &[0, 60, 120, 180]);
+        let options = PreprocessOptions { frames: FrameSampling::EveryNth(1), ..Default::default() };
+        let hashing_options = HashingOptions::default();
+
+        let mut records = HashMap::new();
+        records.insert(animation.clone(), calculate_record(&animation, &options, &hashing_options).unwrap());
+        assert_eq!(records[&animation].frames.len(), 4);
+
+        // the third frame on its own, as a still
+        let still = HashRecord { hashes: records[&animation].frames[2].clone(), ..Default::default() };
+        records.insert(PathBuf::from("still.png"), still);
+
+        let similarity = calculate_similarity(&records, HashingType::DHash, &hashing_options, &MatchOptions::default());
+        assert!(similarity[Path::new("still.png")].contains(&(animation.clone(), 0)));
+
+        let fraction = MatchOptions { frames: FrameMatch::Fraction { min_similarity: 90.0 }, ..Default::default() };
+        let similarity = calculate_similarity(&records, HashingType::DHash, &hashing_options, &fraction);
+        // the only frame of the still has a counterpart
+        assert!(similarity[Path::new("still.png")].contains(&(animation.clone(), 0)));
+        std::fs::remove_file(animation).ok();
+    }
+
+    #[test]
+    fn memory_and_decoded_images_hash_like_files() {
+        let options = PreprocessOptions::default();
+        let hashing_options = HashingOptions::default();
+        let path = Path::new("pictures/cats/robust/cats_scale_300.jpg");
+        let from_file = calculate_record(path, &options, &hashing_options).unwrap();
+
+        let bytes = fs::read(path).unwrap();
+        assert_eq!(hash_bytes(&bytes, &options, &hashing_options).unwrap(), from_file);
+        for hashing_type in HashingType::ALL {
+            assert_eq!(calculate_hash(path, hashing_type, &options, &hashing_options).unwrap(), from_file.hashes[&hashing_type]);
+        }
+
+        let img = load(path, min_size(&hashing_options), &options).unwrap();
+        assert_eq!(hash_image(&img, &options, &hashing_options), from_file);
+
+        // dHash works on luma only, a grayscale copy differs by rounding at most
+        let record = hash_gray(&img.to_luma8(), &hashing_options);
+        let distance = record.hashes[&HashingType::DHash].distance(&from_file.hashes[&HashingType::DHash]).unwrap();
+        assert!(distance <= 4, "distance {distance}");
+    }
+
+    #[test]
+    fn fast_decode_keeps_the_original_for_blockhash_and_pdq() {
+        let hashing_options = HashingOptions::default();
+        let fast = PreprocessOptions { decode: DecodeMode::Fast, ..Default::default() };
+        // big enough to be decoded at half its size
+        let path = Path::new("pictures/cats/robust/cats_scale_300.jpg");
+        assert!(load(path, min_size(&hashing_options), &fast).unwrap().width() < 9792);
+
+        let full_record = calculate_record(path, &PreprocessOptions::default(), &hashing_options).unwrap();
+        let fast_record = calculate_record(path, &fast, &hashing_options).unwrap();
+        assert_eq!(fast_record.hashes[&HashingType::Blockhash], full_record.hashes[&HashingType::Blockhash]);
+        assert_eq!(fast_record.hashes[&HashingType::Pdq], full_record.hashes[&HashingType::Pdq]);
+        assert_eq!(fast_record.quality, full_record.quality);
+        let bytes = fs::read(path).unwrap();
+        assert_eq!(hash_bytes(&bytes, &fast, &hashing_options).unwrap(), fast_record);
+        // a single hash is decoded the same way, so that it finds the file in an index
+        for hashing_type in HashingType::ALL {
+            assert_eq!(hash_buffer(&bytes, hashing_type, &fast, &hashing_options).unwrap(), fast_record.hashes[&hashing_type]);
+        }
+    }
+
+    #[test]
+    fn imagehash_strings_are_computed_from_the_stored_image() {
+        // str(imagehash.dhash(img)) and str(imagehash.phash(img)) of test1.png
+        let expected = vec![(HashingType::DHash, "c0d0c09090c0c8f0".to_string()), (HashingType::PHash, "c16187631ec7689f".to_string())];
+        assert_eq!(imagehash_strings(&load_preview(Path::new("test_images/test1.png")).unwrap()), expected);
+
+        // converted from hashes of a fast decode and the resize filter of this crate they are only close
+        let path = Path::new("pictures/cats/robust/cats.jpg");
+        let fast = calculate_record(path, &PreprocessOptions { decode: DecodeMode::Fast, ..Default::default() }, &HashingOptions::default()).unwrap();
+        for (hashing_type, hex) in imagehash_strings(&load_preview(path).unwrap()) {
+            let converted = hashing_type.format_hash(&fast.hashes[&hashing_type], Encoding::Imagehash).unwrap();
+            let parse = |hex: &str| encoding::parse_imagehash_hex(hex).unwrap();
+            assert!((parse(&hex) ^ parse(&converted)).count_ones() <= 8, "{hashing_type}: {hex} {converted}");
+        }
+    }
+
+    #[test]
+    fn archive_entries_are_hashed_in_place() {
+        let dir = std::env::temp_dir().join(format!("perceptual_hashing_{}_archives", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (test1, test2) = (fs::read("test_images/test1.png").unwrap(), fs::read("test_images/test2.png").unwrap());
+        let (zip, tar) = (dir.join("photos.zip"), dir.join("photos.tar.gz"));
+        write_archive(&zip, &[("test1.png", &test1)]);
+        write_archive(&tar, &[("inner/test2.png", &test2)]);
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let scan = ScanOptions::default();
+        let (options, hashing_options) = (scan.options, scan.hashing_options);
+        let records = calculate_records(&dir, &scan, Some(&tx), &CancellationToken::new()).unwrap();
+        drop(tx);
+        assert_eq!(rx.iter().next(), Some(ProgressEvent::Discovered(2)));
+
+        let entry = locator(&zip, "test1.png");
+        assert_eq!(records.len(), 2);
+        assert!(records.contains_key(&locator(&tar, "inner/test2.png")));
+        assert_eq!(records[&entry], calculate_record(Path::new("test_images/test1.png"), &options, &hashing_options).unwrap());
+        // the locator opens like a path
+        assert_eq!(calculate_record(&entry, &options, &hashing_options).unwrap(), records[&entry]);
+        fs::remove_dir_all(dir).ok();
+    }
+}
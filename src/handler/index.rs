@@ -0,0 +1,686 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use image::Rgb;
+
+use crate::hashing::{BitHash, Encoding, Hash, HashingOptions};
+use crate::hashing::block_hash::BlockhashOptions;
+use crate::hashing::color::{ColorMode, ColorMoments};
+use crate::hashing::marr_hildreth::MarrHildrethOptions;
+use crate::hashing::radial_hash::RadialDigest;
+use crate::hashing::segments::{SegmentHashes, SegmentOptions};
+use crate::preprocessing::animation::FrameSampling;
+use crate::preprocessing::archive::split_locator;
+use crate::preprocessing::decoder::DecodeMode;
+use crate::preprocessing::preprocessor::{AlphaPolicy, PreprocessOptions};
+use super::handle::{HashRecord, HashingType};
+use super::score::csv_field;
+
+/// First bytes of every index file.
+pub const MAGIC: &[u8; 4] = b"PHIX";
+/// Format version written by this build; older versions are read, newer ones are rejected.
+pub const VERSION: u16 = 1;
+pub const EXTENSION: &str = "phix";
+// starts the comment line with the settings in CSV exports
+const CSV_MARKER: &str = "# perceptual-hashing index";
+// stored instead of a quality for files without one
+const NO_QUALITY: u8 = u8::MAX;
+// tags of the hash kinds in the binary format
+const BITS: u8 = 0;
+const RADIAL: u8 = 1;
+const MOMENTS: u8 = 2;
+const SEGMENTS: u8 = 3;
+
+#[derive(Debug)]
+pub enum IndexError {
+    Io(io::Error),
+    NotAnIndex,
+    UnsupportedVersion(u16),
+    Invalid(String),
+    /// Indexes built with different settings hold hashes that cannot be compared.
+    OptionsMismatch,
+}
+
+impl std::fmt::Display for IndexError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IndexError::Io(err) => write!(f, "{err}"),
+            IndexError::NotAnIndex => write!(f, "not an index file"),
+            IndexError::UnsupportedVersion(version) => write!(f, "index version {version} is newer than the supported version {VERSION}"),
+            IndexError::Invalid(message) => write!(f, "invalid index: {message}"),
+            IndexError::OptionsMismatch => write!(f, "the indexes were built with different settings"),
+        }
+    }
+}
+
+impl std::error::Error for IndexError {}
+
+impl From<io::Error> for IndexError {
+    fn from(err: io::Error) -> Self {
+        IndexError::Io(err)
+    }
+}
+
+/// A hashed file, with the size and modification time it had when it was hashed.
+/// Archive entries carry those of their archive.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexEntry {
+    pub path: PathBuf,
+    pub size: u64,
+    /// Milliseconds since the Unix epoch.
+    pub modified: u64,
+    pub record: HashRecord,
+}
+
+/// The hashes of a scan together with the settings they were computed with, so they can be
+/// queried and merged later without decoding the files again.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Index {
+    pub options: PreprocessOptions,
+    pub hashing_options: HashingOptions,
+    /// Sorted by path.
+    pub entries: Vec<IndexEntry>,
+}
+
+impl Index {
+    /// Index of the records of a scan; the files are looked up for their size and modification time.
+    pub fn new(records: HashMap<PathBuf, HashRecord>, options: PreprocessOptions, hashing_options: HashingOptions) -> Self {
+        let mut entries: Vec<IndexEntry> = records
+            .into_iter()
+            .map(|(path, record)| {
+                let (size, modified) = stamp(&path);
+                IndexEntry { path, size, modified, record }
+            })
+            .collect();
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        Index { options, hashing_options, entries }
+    }
+
+    /// The records, as `calculate_similarity` and `find_matches` take them.
+    pub fn records(&self) -> HashMap<PathBuf, HashRecord> {
+        self.entries.iter().map(|entry| (entry.path.clone(), entry.record.clone())).collect()
+    }
+
+    pub fn get(&self, path: &Path) -> Option<&IndexEntry> {
+        self.entries.binary_search_by(|entry| entry.path.as_path().cmp(path)).ok().map(|i| &self.entries[i])
+    }
+
+    /// Adds `entry`, returning the one it replaces.
+    pub fn insert(&mut self, entry: IndexEntry) -> Option<IndexEntry> {
+        match self.entries.binary_search_by(|kept| kept.path.cmp(&entry.path)) {
+            Ok(i) => Some(std::mem::replace(&mut self.entries[i], entry)),
+            Err(i) => {
+                self.entries.insert(i, entry);
+                None
+            }
+        }
+    }
+
+    pub fn remove(&mut self, path: &Path) -> Option<IndexEntry> {
+        let i = self.entries.binary_search_by(|entry| entry.path.as_path().cmp(path)).ok()?;
+        Some(self.entries.remove(i))
+    }
+
+    /// Adds the entries of `other`; of two entries for the same path the more recently modified one is kept.
+    pub fn merge(&mut self, other: Index) -> Result<(), IndexError> {
+        if self.options != other.options || self.hashing_options != other.hashing_options {
+            return Err(IndexError::OptionsMismatch);
+        }
+        let mut entries: BTreeMap<PathBuf, IndexEntry> = self.entries.drain(..).map(|entry| (entry.path.clone(), entry)).collect();
+        for entry in other.entries {
+            if entries.get(&entry.path).is_none_or(|kept| kept.modified <= entry.modified) {
+                entries.insert(entry.path.clone(), entry);
+            }
+        }
+        self.entries = entries.into_values().collect();
+        Ok(())
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), IndexError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, IndexError> {
+        Self::read(BufReader::new(File::open(path)?))
+    }
+
+    /// Writes the magic number and the version, followed by the gzip compressed settings and entries.
+    pub fn write<W: Write>(&self, mut writer: W) -> Result<(), IndexError> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+
+        let mut body = GzEncoder::new(writer, Compression::default());
+        let settings = settings(&self.options, &self.hashing_options);
+        body.write_all(&(settings.len() as u16).to_le_bytes())?;
+        for (key, value) in &settings {
+            write_str(&mut body, key)?;
+            write_str(&mut body, value)?;
+        }
+
+        body.write_all(&(self.entries.len() as u32).to_le_bytes())?;
+        for entry in &self.entries {
+            write_path(&mut body, &entry.path)?;
+            body.write_all(&entry.size.to_le_bytes())?;
+            body.write_all(&entry.modified.to_le_bytes())?;
+            body.write_all(&[entry.record.quality.unwrap_or(NO_QUALITY)])?;
+            write_hashes(&mut body, &entry.record.hashes)?;
+            body.write_all(&(entry.record.frames.len() as u16).to_le_bytes())?;
+            for frame in &entry.record.frames {
+                write_hashes(&mut body, frame)?;
+            }
+        }
+        body.finish()?;
+        Ok(())
+    }
+
+    pub fn read<R: Read>(mut reader: R) -> Result<Self, IndexError> {
+        let mut header = [0; 6];
+        reader.read_exact(&mut header).map_err(|_| IndexError::NotAnIndex)?;
+        if &header[..4] != MAGIC {
+            return Err(IndexError::NotAnIndex);
+        }
+        let version = u16::from_le_bytes([header[4], header[5]]);
+        if version > VERSION {
+            return Err(IndexError::UnsupportedVersion(version));
+        }
+
+        let mut body = Body(GzDecoder::new(reader));
+        let settings = (0..body.u16()?)
+            .map(|_| Ok((body.string()?, body.string()?)))
+            .collect::<Result<Vec<_>, IndexError>>()?;
+        let (options, hashing_options) = parse_settings(&settings)?;
+
+        let count = body.u32()?;
+        let mut entries = Vec::with_capacity(count.min(1 << 16) as usize);
+        for _ in 0..count {
+            let path = body.path()?;
+            let size = body.u64()?;
+            let modified = body.u64()?;
+            let quality = Some(body.u8()?).filter(|&quality| quality != NO_QUALITY);
+            let hashes = body.hashes()?;
+            let frames = (0..body.u16()?).map(|_| body.hashes()).collect::<Result<Vec<_>, IndexError>>()?;
+            entries.push(IndexEntry { path, size, modified, record: HashRecord { hashes, quality, frames } });
+        }
+        Ok(Index { options, hashing_options, entries })
+    }
+
+    /// Writes one row per file with the hex hashes of every algorithm, after a comment line with the
+    /// settings. Only the first frame of animations is written, and colour moments lose some precision.
+    pub fn export_csv<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let settings: Vec<String> = settings(&self.options, &self.hashing_options)
+            .into_iter()
+            .map(|(key, value)| format!("{key}={value}"))
+            .collect();
+        writeln!(writer, "{CSV_MARKER} {VERSION} {}", settings.join(" "))?;
+        let ids: Vec<&str> = HashingType::ALL.iter().map(|t| t.id()).collect();
+        writeln!(writer, "path,size,modified,quality,{}", ids.join(","))?;
+
+        for entry in &self.entries {
+            let quality = entry.record.quality.map_or(String::new(), |quality| quality.to_string());
+            let hashes: Vec<String> = HashingType::ALL
+                .iter()
+                .map(|t| entry.record.hashes.get(t).map_or(String::new(), Hash::to_hex))
+                .collect();
+            writeln!(writer, "{},{},{},{quality},{}", csv_field(&entry.path), entry.size, entry.modified, hashes.join(","))?;
+        }
+        Ok(())
+    }
+
+    /// Reads what `export_csv` wrote; columns of unknown algorithms are ignored, so are missing ones.
+    pub fn import_csv<R: Read>(mut reader: R) -> Result<Self, IndexError> {
+        let mut text = String::new();
+        reader.read_to_string(&mut text)?;
+        let (comment, rows) = text.split_once('\n').ok_or(IndexError::NotAnIndex)?;
+        let mut words = comment.trim_end().strip_prefix(CSV_MARKER).ok_or(IndexError::NotAnIndex)?.split_whitespace();
+        let version: u16 = words.next().and_then(|version| version.parse().ok()).ok_or(IndexError::NotAnIndex)?;
+        if version > VERSION {
+            return Err(IndexError::UnsupportedVersion(version));
+        }
+        let settings = words
+            .map(|word| word.split_once('=').map(|(key, value)| (key.to_string(), value.to_string())))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| IndexError::Invalid(format!("malformed settings: {comment}")))?;
+        let (options, hashing_options) = parse_settings(&settings)?;
+
+        let mut rows = parse_csv(rows).into_iter();
+        let columns: Vec<Option<HashingType>> = rows
+            .next()
+            .ok_or_else(|| IndexError::Invalid("missing header row".to_string()))?
+            .iter()
+            .map(|column| HashingType::from_id(column))
+            .collect();
+
+        let mut entries = Vec::new();
+        for (line, row) in rows.enumerate() {
+            // the comment and the header come first
+            let invalid = |what: &str| IndexError::Invalid(format!("{what} on line {}", line + 3));
+            if row.len() < 4 {
+                return Err(invalid("missing columns"));
+            }
+            let mut record = HashRecord {
+                quality: if row[3].is_empty() { None } else { Some(row[3].parse().map_err(|_| invalid("invalid quality"))?) },
+                ..Default::default()
+            };
+            for (column, hex) in columns.iter().zip(&row).skip(4) {
+                if let Some(hashing_type) = column && !hex.is_empty() {
+                    let hash = hashing_type.parse_hash(hex, Encoding::Native, &hashing_options).ok_or_else(|| invalid(&format!("invalid {} hash", hashing_type.id())))?;
+                    record.hashes.insert(*hashing_type, hash);
+                }
+            }
+            entries.push(IndexEntry {
+                path: PathBuf::from(&row[0]),
+                size: row[1].parse().map_err(|_| invalid("invalid size"))?,
+                modified: row[2].parse().map_err(|_| invalid("invalid modification time"))?,
+                record,
+            });
+        }
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(Index { options, hashing_options, entries })
+    }
+}
+
+// size and modification time of the file, or of the archive holding it; zeros when it cannot be read
+pub(crate) fn stamp(path: &Path) -> (u64, u64) {
+    let file = split_locator(path).map_or_else(|| path.to_path_buf(), |(archive, _)| archive);
+    let Ok(metadata) = fs::metadata(file) else {
+        return (0, 0);
+    };
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |duration| duration.as_millis() as u64);
+    (metadata.len(), modified)
+}
+
+/// Every setting that changes the hashes, as text shared by both formats and by configuration files.
+pub fn settings(options: &PreprocessOptions, hashing_options: &HashingOptions) -> Vec<(String, String)> {
+    let alpha = match options.alpha {
+        AlphaPolicy::Ignore => "ignore".to_string(),
+        AlphaPolicy::Mask => "mask".to_string(),
+        AlphaPolicy::Background(Rgb([r, g, b])) => format!("#{r:02x}{g:02x}{b:02x}"),
+    };
+    let frames = match options.frames {
+        FrameSampling::First => "first".to_string(),
+        FrameSampling::EveryNth(n) => format!("every:{n}"),
+        FrameSampling::SceneChange => "scenes".to_string(),
+    };
+    let color = match hashing_options.color {
+        ColorMode::Grayscale => "grayscale",
+        ColorMode::PerChannel => "per-channel",
+    };
+    let algorithms: Vec<&str> = HashingType::ALL.iter().map(|t| t.id()).collect();
+    [
+        ("algorithms", algorithms.join(",")),
+        ("alpha", alpha),
+        ("decode", options.decode.to_string()),
+        ("frames", frames),
+        ("blockhash.bits", hashing_options.blockhash.bits.to_string()),
+        ("blockhash.precise", hashing_options.blockhash.precise.to_string()),
+        ("marr-hildreth.alpha", hashing_options.marr_hildreth.alpha.to_string()),
+        ("marr-hildreth.level", hashing_options.marr_hildreth.level.to_string()),
+        ("marr-hildreth.sigma", hashing_options.marr_hildreth.sigma.to_string()),
+        ("color", color.to_string()),
+        ("segments.grid", hashing_options.segments.grid.to_string()),
+    ]
+    .into_iter()
+    .map(|(key, value)| (key.to_string(), value))
+    .collect()
+}
+
+/// The options described by `settings`; every setting but `algorithms` and `marr-hildreth.sigma` has to be present,
+/// and the hashing options have to pass `HashingOptions::validate`.
+pub fn parse_settings(settings: &[(String, String)]) -> Result<(PreprocessOptions, HashingOptions), IndexError> {
+    let get = |key: &str| {
+        settings
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+            .ok_or_else(|| IndexError::Invalid(format!("missing setting {key}")))
+    };
+    let invalid = |key: &str| IndexError::Invalid(format!("invalid setting {key}={}", get(key).unwrap_or_default()));
+    let number = |key: &str| get(key)?.parse::<u32>().map_err(|_| invalid(key));
+    let float = |key: &str| get(key)?.parse::<f32>().map_err(|_| invalid(key));
+
+    let alpha = match get("alpha")? {
+        "ignore" => AlphaPolicy::Ignore,
+        "mask" => AlphaPolicy::Mask,
+        hex => {
+            let rgb = hex.strip_prefix('#').filter(|rgb| rgb.len() == 6).and_then(|rgb| u32::from_str_radix(rgb, 16).ok()).ok_or_else(|| invalid("alpha"))?;
+            let [_, r, g, b] = rgb.to_be_bytes();
+            AlphaPolicy::Background(Rgb([r, g, b]))
+        }
+    };
+    let decode = match get("decode")? {
+        "full" => DecodeMode::Full,
+        "fast" => DecodeMode::Fast,
+        _ => return Err(invalid("decode")),
+    };
+    let frames = match get("frames")? {
+        "first" => FrameSampling::First,
+        "scenes" => FrameSampling::SceneChange,
+        every => FrameSampling::EveryNth(every.strip_prefix("every:").and_then(|n| n.parse().ok()).ok_or_else(|| invalid("frames"))?),
+    };
+    let color = match get("color")? {
+        "grayscale" => ColorMode::Grayscale,
+        "per-channel" => ColorMode::PerChannel,
+        _ => return Err(invalid("color")),
+    };
+    let hashing_options = HashingOptions {
+        blockhash: BlockhashOptions { bits: number("blockhash.bits")?, precise: get("blockhash.precise")?.parse().map_err(|_| invalid("blockhash.precise"))? },
+        marr_hildreth: MarrHildrethOptions {
+            alpha: float("marr-hildreth.alpha")?,
+            level: float("marr-hildreth.level")?,
+            // indexes written before the blur was configurable used the default
+            sigma: if get("marr-hildreth.sigma").is_ok() { float("marr-hildreth.sigma")? } else { MarrHildrethOptions::default().sigma },
+        },
+        color,
+        segments: SegmentOptions { grid: number("segments.grid")? },
+    };
+    hashing_options.validate().map_err(|message| IndexError::Invalid(format!("invalid setting {message}")))?;
+    Ok((PreprocessOptions { alpha, decode, frames }, hashing_options))
+}
+
+fn write_str<W: Write>(writer: &mut W, text: &str) -> io::Result<()> {
+    write_bytes(writer, text.as_bytes())
+}
+
+fn write_bytes<W: Write>(writer: &mut W, bytes: &[u8]) -> io::Result<()> {
+    writer.write_all(&(bytes.len() as u32).to_le_bytes())?;
+    writer.write_all(bytes)
+}
+
+// the raw bytes on Unix, where file names need not be UTF-8; elsewhere such paths are refused
+fn write_path<W: Write>(writer: &mut W, path: &Path) -> Result<(), IndexError> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        Ok(write_bytes(writer, path.as_os_str().as_bytes())?)
+    }
+    #[cfg(not(unix))]
+    {
+        let text = path.to_str().ok_or_else(|| IndexError::Invalid(format!("{} is not UTF-8", path.display())))?;
+        Ok(write_str(writer, text)?)
+    }
+}
+
+// hashes sorted by algorithm, each with its kind, so that equal records give equal bytes
+fn write_hashes<W: Write>(writer: &mut W, hashes: &HashMap<HashingType, Hash>) -> io::Result<()> {
+    writer.write_all(&[hashes.len() as u8])?;
+    for (i, hashing_type) in HashingType::ALL.iter().enumerate() {
+        let Some(hash) = hashes.get(hashing_type) else {
+            continue;
+        };
+        writer.write_all(&[i as u8])?;
+        match hash {
+            Hash::Bits(bits) => {
+                writer.write_all(&[BITS])?;
+                write_bits(writer, bits)?;
+            }
+            Hash::Radial(digest) => {
+                writer.write_all(&[RADIAL])?;
+                writer.write_all(&(digest.coeffs().len() as u16).to_le_bytes())?;
+                writer.write_all(digest.coeffs())?;
+            }
+            Hash::Moments(moments) => {
+                writer.write_all(&[MOMENTS])?;
+                writer.write_all(&(moments.values().len() as u16).to_le_bytes())?;
+                for value in moments.values() {
+                    writer.write_all(&value.to_le_bytes())?;
+                }
+            }
+            Hash::Segments(segments) => {
+                writer.write_all(&[SEGMENTS])?;
+                writer.write_all(&(segments.segments().len() as u16).to_le_bytes())?;
+                for segment in segments.segments() {
+                    write_bits(writer, segment)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn write_bits<W: Write>(writer: &mut W, bits: &BitHash) -> io::Result<()> {
+    writer.write_all(&(bits.len() as u32).to_le_bytes())?;
+    writer.write_all(bits.as_bytes())
+}
+
+// reads the decompressed body; running out of bytes means the file is truncated
+struct Body<R>(R);
+
+impl<R: Read> Body<R> {
+    fn bytes(&mut self, len: usize) -> Result<Vec<u8>, IndexError> {
+        let mut bytes = Vec::new();
+        (&mut self.0).take(len as u64).read_to_end(&mut bytes)?;
+        if bytes.len() < len {
+            return Err(IndexError::Invalid("truncated file".to_string()));
+        }
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], IndexError> {
+        Ok(self.bytes(N)?.try_into().expect("read N bytes"))
+    }
+
+    fn u8(&mut self) -> Result<u8, IndexError> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, IndexError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, IndexError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, IndexError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn string(&mut self) -> Result<String, IndexError> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.bytes(len)?).map_err(|_| IndexError::Invalid("text is not UTF-8".to_string()))
+    }
+
+    fn path(&mut self) -> Result<PathBuf, IndexError> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStringExt;
+            let len = self.u32()? as usize;
+            Ok(PathBuf::from(std::ffi::OsString::from_vec(self.bytes(len)?)))
+        }
+        #[cfg(not(unix))]
+        Ok(PathBuf::from(self.string()?))
+    }
+
+    fn bits(&mut self) -> Result<BitHash, IndexError> {
+        let len = self.u32()? as usize;
+        BitHash::from_bytes(self.bytes(len.div_ceil(8))?, len).ok_or_else(|| IndexError::Invalid("malformed hash".to_string()))
+    }
+
+    fn hashes(&mut self) -> Result<HashMap<HashingType, Hash>, IndexError> {
+        let mut hashes = HashMap::new();
+        for _ in 0..self.u8()? {
+            let index = self.u8()?;
+            let hashing_type = HashingType::from_index(index as usize).ok_or_else(|| IndexError::Invalid(format!("unknown algorithm {index}")))?;
+            let hash = match self.u8()? {
+                BITS => Hash::Bits(self.bits()?),
+                RADIAL => {
+                    let len = self.u16()? as usize;
+                    Hash::Radial(RadialDigest::from_coeffs(self.bytes(len)?))
+                }
+                MOMENTS => {
+                    let values = (0..self.u16()?).map(|_| Ok(f64::from_le_bytes(self.array()?))).collect::<Result<Vec<f64>, IndexError>>()?;
+                    Hash::Moments(ColorMoments::from_values(values))
+                }
+                SEGMENTS => {
+                    let segments = (0..self.u16()?).map(|_| self.bits()).collect::<Result<Vec<BitHash>, IndexError>>()?;
+                    Hash::Segments(SegmentHashes::from_segments(segments))
+                }
+                kind => return Err(IndexError::Invalid(format!("unknown hash kind {kind}"))),
+            };
+            hashes.insert(hashing_type, hash);
+        }
+        Ok(hashes)
+    }
+}
+
+// rows of `text` with unquoted fields; quoted fields may hold separators, quotes and line breaks
+fn parse_csv(text: &str) -> Vec<Vec<String>> {
+    let (mut rows, mut row, mut field) = (Vec::new(), Vec::new(), String::new());
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted => {
+                if chars.next_if_eq(&'"').is_some() {
+                    field.push('"');
+                } else {
+                    quoted = false;
+                }
+            }
+            '"' if field.is_empty() => quoted = true,
+            ',' if !quoted => row.push(std::mem::take(&mut field)),
+            '\r' if !quoted => {}
+            '\n' if !quoted => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            c => field.push(c),
+        }
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, RgbImage};
+    use crate::handler::handle::hash_image;
+    use super::*;
+
+    fn index() -> Index {
+        let options = PreprocessOptions { alpha: AlphaPolicy::Background(Rgb([1, 2, 3])), frames: FrameSampling::EveryNth(2), ..Default::default() };
+        let marr_hildreth = MarrHildrethOptions { sigma: 1.5, ..Default::default() };
+        let hashing_options = HashingOptions { color: ColorMode::PerChannel, marr_hildreth, ..Default::default() };
+        let img = DynamicImage::ImageRgb8(RgbImage::from_fn(64, 48, |x, y| Rgb([(x * 4) as u8, (y * 5) as u8, ((x + y) * 2) as u8])));
+        let flipped = img.fliph();
+
+        let still = hash_image(&img, &options, &hashing_options);
+        let mut animation = hash_image(&flipped, &options, &hashing_options);
+        animation.quality = None;
+        animation.frames = vec![animation.hashes.clone(), still.hashes.clone()];
+        let records = HashMap::from([(PathBuf::from("photos/a, \"b\".png"), still), (PathBuf::from("clip.gif"), animation)]);
+        Index::new(records, options, hashing_options)
+    }
+
+    #[test]
+    fn index_files_round_trip() {
+        let index = index();
+        let mut bytes = Vec::new();
+        index.write(&mut bytes).unwrap();
+        assert!(bytes.starts_with(MAGIC));
+        assert_eq!(Index::read(bytes.as_slice()).unwrap(), index);
+        assert_eq!(index.records().len(), 2);
+
+        assert!(matches!(Index::read(&b"PNG image"[..]), Err(IndexError::NotAnIndex)));
+        assert!(matches!(Index::read(&bytes[..bytes.len() / 2]), Err(IndexError::Io(_) | IndexError::Invalid(_))));
+        bytes[4] = 9;
+        assert!(matches!(Index::read(bytes.as_slice()), Err(IndexError::UnsupportedVersion(9))));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn paths_that_are_not_utf8_are_kept() {
+        use std::os::unix::ffi::OsStrExt;
+        let mut index = index();
+        index.entries[0].path = PathBuf::from(std::ffi::OsStr::from_bytes(b"photos/caf\xe9.png"));
+        let mut bytes = Vec::new();
+        index.write(&mut bytes).unwrap();
+        assert_eq!(Index::read(bytes.as_slice()).unwrap(), index);
+    }
+
+    #[test]
+    fn merging_keeps_the_newer_entry() {
+        let mut index = index();
+        let mut newer = index.clone();
+        newer.entries.truncate(1);
+        newer.entries[0].modified += 1;
+        newer.entries[0].record.quality = Some(42);
+        newer.entries.push(IndexEntry { path: PathBuf::from("new.png"), ..newer.entries[0].clone() });
+
+        index.merge(newer.clone()).unwrap();
+        assert_eq!(index.entries.len(), 3);
+        assert_eq!(index.entries[0], newer.entries[0]);
+
+        // merging the older copy back changes nothing
+        let mut older = self::index();
+        older.merge(index.clone()).unwrap();
+        index.merge(self::index()).unwrap();
+        assert_eq!(older, index);
+
+        newer.hashing_options.segments.grid = 3;
+        assert!(matches!(index.merge(newer), Err(IndexError::OptionsMismatch)));
+    }
+
+    #[test]
+    fn csv_exports_round_trip() {
+        let index = index();
+        let mut csv = Vec::new();
+        index.export_csv(&mut csv).unwrap();
+        let text = String::from_utf8(csv).unwrap();
+        assert!(text.starts_with("# perceptual-hashing index 1 algorithms=dhash,"));
+        assert!(text.contains("\n\"photos/a, \"\"b\"\".png\","));
+
+        let imported = Index::import_csv(text.as_bytes()).unwrap();
+        assert_eq!((imported.options, imported.hashing_options), (index.options, index.hashing_options));
+        for (entry, original) in imported.entries.iter().zip(&index.entries) {
+            assert_eq!((&entry.path, entry.size, entry.modified, entry.record.quality), (&original.path, original.size, original.modified, original.record.quality));
+            assert!(entry.record.frames.is_empty());
+            for hashing_type in HashingType::ALL {
+                let distance = entry.record.hashes[&hashing_type].distance(&original.record.hashes[&hashing_type]);
+                // colour moments are stored with 16 bits each
+                assert_eq!(distance, Some(0), "{hashing_type}");
+            }
+        }
+
+        // written before the blur was configurable
+        let without_sigma = text.replacen(" marr-hildreth.sigma=1.5", "", 1);
+        assert_ne!(without_sigma, text);
+        assert_eq!(Index::import_csv(without_sigma.as_bytes()).unwrap().hashing_options.marr_hildreth, MarrHildrethOptions::default());
+
+        let broken = text.replacen(",dhash,", ",pdq,", 1);
+        assert!(matches!(Index::import_csv(broken.as_bytes()), Err(IndexError::Invalid(_))));
+    }
+
+    #[test]
+    fn crafted_settings_are_refused() {
+        let mut index = index();
+        let mut csv = Vec::new();
+        index.export_csv(&mut csv).unwrap();
+        let text = String::from_utf8(csv).unwrap();
+        for (setting, crafted) in [("segments.grid=4", "segments.grid=0"), ("marr-hildreth.sigma=1.5", "marr-hildreth.sigma=-1")] {
+            let crafted_text = text.replacen(setting, crafted, 1);
+            assert_ne!(crafted_text, text);
+            assert!(matches!(Index::import_csv(crafted_text.as_bytes()), Err(IndexError::Invalid(message)) if message.contains(crafted)));
+        }
+
+        index.hashing_options.segments.grid = 100;
+        let mut bytes = Vec::new();
+        index.write(&mut bytes).unwrap();
+        assert!(matches!(Index::read(bytes.as_slice()), Err(IndexError::Invalid(message)) if message.contains("segments.grid=100")));
+    }
+}
@@ -1,3 +1,13 @@
 pub mod handle;
 pub mod similarity_analyzer;
+pub mod progress;
+pub mod composite;
+pub mod score;
+pub mod frames;
+pub mod index;
+pub mod watch;
+pub mod tags;
+pub mod scan;
+#[cfg(feature = "server")]
+pub mod server;
 pub use crate::handler::handle::calculate_similarity;
\ No newline at end of file
@@ -0,0 +1,136 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// Reported by the hashing pipeline while it works through a folder.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProgressEvent {
+    /// Number of files found in the folder, sent once before hashing starts.
+    Discovered(usize),
+    Started(PathBuf),
+    Hashed(PathBuf),
+    /// The file could not be decoded (not an image, unsupported format, ...).
+    Failed(PathBuf),
+}
+
+/// Shared flag used to stop a running hashing job.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "hashing was cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Running summary of the received `ProgressEvent`s.
+#[derive(Debug, Clone)]
+pub struct Progress {
+    pub discovered: usize,
+    pub hashed: usize,
+    pub failed: usize,
+    pub current: Option<PathBuf>,
+    started_at: Instant,
+}
+
+impl Progress {
+    pub fn new() -> Self {
+        Progress {
+            discovered: 0,
+            hashed: 0,
+            failed: 0,
+            current: None,
+            started_at: Instant::now(),
+        }
+    }
+
+    pub fn apply(&mut self, event: ProgressEvent) {
+        match event {
+            ProgressEvent::Discovered(count) => self.discovered = count,
+            ProgressEvent::Started(path) => self.current = Some(path),
+            ProgressEvent::Hashed(_) => self.hashed += 1,
+            ProgressEvent::Failed(_) => self.failed += 1,
+        }
+    }
+
+    pub fn done(&self) -> usize {
+        self.hashed + self.failed
+    }
+
+    // between 0.0 and 1.0, for the gauge
+    pub fn ratio(&self) -> f64 {
+        if self.discovered == 0 {
+            0.0
+        } else {
+            (self.done() as f64 / self.discovered as f64).min(1.0)
+        }
+    }
+
+    // linear estimate based on the files processed so far
+    pub fn eta(&self) -> Option<Duration> {
+        let done = self.done();
+        if done == 0 || self.discovered == 0 {
+            return None;
+        }
+        let per_file = self.started_at.elapsed().as_secs_f64() / done as f64;
+        Some(Duration::from_secs_f64(per_file * self.discovered.saturating_sub(done) as f64))
+    }
+}
+
+impl Default for Progress {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use super::*;
+
+    #[test]
+    fn progress_counts_events() {
+        let mut progress = Progress::new();
+        assert_eq!(progress.eta(), None);
+
+        progress.apply(ProgressEvent::Discovered(4));
+        progress.apply(ProgressEvent::Started(PathBuf::from("a.png")));
+        progress.apply(ProgressEvent::Hashed(PathBuf::from("a.png")));
+        progress.apply(ProgressEvent::Started(PathBuf::from("b.txt")));
+        progress.apply(ProgressEvent::Failed(PathBuf::from("b.txt")));
+
+        assert_eq!(progress.done(), 2);
+        assert_eq!(progress.ratio(), 0.5);
+        assert_eq!(progress.current, Some(PathBuf::from("b.txt")));
+        assert!(progress.eta().is_some());
+    }
+
+    #[test]
+    fn token_is_shared_between_clones() {
+        let token = CancellationToken::new();
+        let clone = token.clone();
+        assert!(!clone.is_cancelled());
+        token.cancel();
+        assert!(clone.is_cancelled());
+    }
+}
@@ -0,0 +1,106 @@
+use std::fs;
+use std::path::Path;
+
+/// Which files of a folder are hashed. Archives are files of the folder too, their entries are
+/// hashed when the archive itself is accepted.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScanFilter {
+    /// Extensions (without the dot, any case) of the files that are hashed; empty for every file.
+    pub extensions: Vec<String>,
+    /// File names that are skipped, `*` matching any text and `?` any character.
+    pub exclude: Vec<String>,
+    /// Skips files whose name starts with a dot.
+    pub skip_hidden: bool,
+    /// Files larger than this many bytes are skipped.
+    pub max_size: Option<u64>,
+}
+
+impl ScanFilter {
+    pub fn accepts(&self, path: &Path) -> bool {
+        let name = path.file_name().map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+        if self.skip_hidden && name.starts_with('.') {
+            return false;
+        }
+        if !self.extensions.is_empty() {
+            let extension = path.extension().map(|extension| extension.to_string_lossy().to_lowercase());
+            if !self.extensions.iter().any(|accepted| Some(accepted.to_lowercase()) == extension) {
+                return false;
+            }
+        }
+        if self.exclude.iter().any(|pattern| matches(pattern, &name)) {
+            return false;
+        }
+        self.max_size.is_none_or(|max_size| fs::metadata(path).is_ok_and(|metadata| metadata.len() <= max_size))
+    }
+}
+
+// wildcard match of a whole file name
+fn matches(pattern: &str, name: &str) -> bool {
+    let (pattern, name): (Vec<char>, Vec<char>) = (pattern.chars().collect(), name.chars().collect());
+    // positions to resume from after the last `*`, so that it can swallow one more character
+    let (mut p, mut n, mut star) = (0, 0, None);
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((after_star, swallowed)) => {
+                    star = Some((after_star, swallowed + 1));
+                    p = after_star;
+                    n = swallowed + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn wildcards_match_whole_names() {
+        assert!(matches("*.jpg", "a.jpg"));
+        assert!(matches("IMG_????.*", "IMG_0001.png"));
+        assert!(matches("*thumb*", "a_thumb_b.jpg"));
+        assert!(matches("*", ""));
+        assert!(!matches("*.jpg", "a.jpg.xmp"));
+        assert!(!matches("IMG_????.*", "IMG_001.png"));
+    }
+
+    #[test]
+    fn filters_by_extension_name_and_size() {
+        let dir = std::env::temp_dir().join(format!("perceptual_hashing_scan_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = |name: &str, size: usize| {
+            let path = dir.join(name);
+            fs::write(&path, vec![0; size]).unwrap();
+            path
+        };
+        let (photo, hidden, thumbnail, big) = (file("a.JPG", 10), file(".b.jpg", 10), file("a_thumb.jpg", 10), file("c.png", 1000));
+
+        let every = ScanFilter::default();
+        assert!([&photo, &hidden, &thumbnail, &big].iter().all(|path| every.accepts(path)));
+
+        let filter = ScanFilter {
+            extensions: vec!["jpg".to_string()],
+            exclude: vec!["*_thumb.*".to_string()],
+            skip_hidden: true,
+            max_size: Some(100),
+        };
+        let accepted: Vec<&PathBuf> = [&photo, &hidden, &thumbnail, &big].into_iter().filter(|path| filter.accepts(path)).collect();
+        assert_eq!(accepted, [&photo]);
+        assert!(!ScanFilter { max_size: Some(100), ..Default::default() }.accepts(&big));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Similarity in percent: 100 for identical hashes, 0 at `max_distance`.
+pub fn similarity(distance: u32, max_distance: u32) -> f64 {
+    if max_distance == 0 {
+        return 100.0;
+    }
+    (100.0 * (1.0 - distance as f64 / max_distance as f64)).clamp(0.0, 100.0)
+}
+
+/// How scores are shown in the results list and written to exports.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ScoreDisplay {
+    Distance,
+    Percent,
+    #[default]
+    Both,
+}
+
+impl ScoreDisplay {
+    pub fn next(self) -> Self {
+        match self {
+            ScoreDisplay::Distance => ScoreDisplay::Percent,
+            ScoreDisplay::Percent => ScoreDisplay::Both,
+            ScoreDisplay::Both => ScoreDisplay::Distance,
+        }
+    }
+
+    // `distance` is passed as text, so that callers can show it in their own way
+    pub fn format(self, distance: &str, similarity: f64) -> String {
+        match self {
+            ScoreDisplay::Distance => distance.to_string(),
+            ScoreDisplay::Percent => format!("{similarity:.0}%"),
+            ScoreDisplay::Both => format!("{distance} ({similarity:.0}%)"),
+        }
+    }
+}
+
+impl std::fmt::Display for ScoreDisplay {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScoreDisplay::Distance => write!(f, "distance"),
+            ScoreDisplay::Percent => write!(f, "percent"),
+            ScoreDisplay::Both => write!(f, "both"),
+        }
+    }
+}
+
+/// Limit on the listed files, in either unit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScoreFilter {
+    MaxDistance(u32),
+    MinSimilarity(f64),
+}
+
+impl ScoreFilter {
+    pub fn accepts(&self, scored: &Scored) -> bool {
+        match *self {
+            ScoreFilter::MaxDistance(max) => scored.distance <= max,
+            ScoreFilter::MinSimilarity(min) => scored.similarity >= min,
+        }
+    }
+
+    // steps of 1 for distances and of 5 for percentages
+    pub fn loosen(self) -> Self {
+        match self {
+            ScoreFilter::MaxDistance(max) => ScoreFilter::MaxDistance(max.saturating_add(1)),
+            ScoreFilter::MinSimilarity(min) => ScoreFilter::MinSimilarity((min - 5.0).max(0.0)),
+        }
+    }
+
+    pub fn tighten(self) -> Self {
+        match self {
+            ScoreFilter::MaxDistance(max) => ScoreFilter::MaxDistance(max.saturating_sub(1)),
+            ScoreFilter::MinSimilarity(min) => ScoreFilter::MinSimilarity((min + 5.0).min(100.0)),
+        }
+    }
+}
+
+impl std::fmt::Display for ScoreFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScoreFilter::MaxDistance(max) => write!(f, "distance ≤ {max}"),
+            ScoreFilter::MinSimilarity(min) => write!(f, "similarity ≥ {min:.0}%"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SortOrder {
+    /// Ascending distance, i.e. descending similarity.
+    #[default]
+    MostSimilarFirst,
+    LeastSimilarFirst,
+}
+
+impl SortOrder {
+    pub fn next(self) -> Self {
+        match self {
+            SortOrder::MostSimilarFirst => SortOrder::LeastSimilarFirst,
+            SortOrder::LeastSimilarFirst => SortOrder::MostSimilarFirst,
+        }
+    }
+}
+
+impl std::fmt::Display for SortOrder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SortOrder::MostSimilarFirst => write!(f, "most similar first"),
+            SortOrder::LeastSimilarFirst => write!(f, "least similar first"),
+        }
+    }
+}
+
+/// Display, filter and order of the scores, shared by the TUI and the export.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ScoreOptions {
+    pub display: ScoreDisplay,
+    pub filter: Option<ScoreFilter>,
+    pub order: SortOrder,
+}
+
+/// A similar file with its distance in both units.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scored {
+    pub path: PathBuf,
+    pub distance: u32,
+    pub similarity: f64,
+}
+
+/// Files similar to `file`, without `file` itself, filtered and ordered as `options` say.
+pub fn score(file: &Path, distances: &[(PathBuf, u32)], max_distance: u32, options: &ScoreOptions) -> Vec<Scored> {
+    let mut scored: Vec<Scored> = distances
+        .iter()
+        .filter(|(path, _)| path != file)
+        .map(|(path, distance)| Scored {
+            path: path.clone(),
+            distance: *distance,
+            similarity: similarity(*distance, max_distance),
+        })
+        .filter(|scored| options.filter.is_none_or(|filter| filter.accepts(scored)))
+        .collect();
+    scored.sort_by(|a, b| a.distance.cmp(&b.distance).then_with(|| a.path.cmp(&b.path)));
+    if options.order == SortOrder::LeastSimilarFirst {
+        scored.reverse();
+    }
+    scored
+}
+
+/// Writes every pair of `similarity_map` as CSV, with the columns chosen by `options.display`.
+pub fn export_csv<W: Write>(
+    writer: &mut W,
+    similarity_map: &HashMap<PathBuf, Vec<(PathBuf, u32)>>,
+    max_distance: u32,
+    options: &ScoreOptions
+) -> io::Result<()> {
+    let columns = match options.display {
+        ScoreDisplay::Distance => "distance",
+        ScoreDisplay::Percent => "similarity",
+        ScoreDisplay::Both => "distance,similarity",
+    };
+    writeln!(writer, "file,similar_file,{columns}")?;
+
+    let mut files: Vec<&PathBuf> = similarity_map.keys().collect();
+    files.sort();
+    for file in files {
+        for scored in score(file, &similarity_map[file], max_distance, options) {
+            let values = match options.display {
+                ScoreDisplay::Distance => scored.distance.to_string(),
+                ScoreDisplay::Percent => format!("{:.2}", scored.similarity),
+                ScoreDisplay::Both => format!("{},{:.2}", scored.distance, scored.similarity),
+            };
+            writeln!(writer, "{},{},{values}", csv_field(file), csv_field(&scored.path))?;
+        }
+    }
+    Ok(())
+}
+
+// quoted when it contains a separator, quote or line break
+pub(crate) fn csv_field(path: &Path) -> String {
+    let text = path.to_string_lossy();
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn distances() -> Vec<(PathBuf, u32)> {
+        vec![
+            (PathBuf::from("a.jpg"), 0),
+            (PathBuf::from("b.jpg"), 16),
+            (PathBuf::from("c, d.jpg"), 4),
+        ]
+    }
+
+    #[test]
+    fn similarity_is_relative_to_the_hash_size() {
+        assert_eq!(similarity(0, 64), 100.0);
+        assert_eq!(similarity(16, 64), 75.0);
+        assert_eq!(similarity(64, 256), 75.0);
+        assert_eq!(similarity(300, 256), 0.0);
+    }
+
+    #[test]
+    fn filters_work_in_either_unit() {
+        let file = Path::new("a.jpg");
+        let by_distance = ScoreOptions { filter: Some(ScoreFilter::MaxDistance(4)), ..Default::default() };
+        let by_percent = ScoreOptions { filter: Some(ScoreFilter::MinSimilarity(90.0)), ..Default::default() };
+
+        let scored = score(file, &distances(), 64, &by_distance);
+        assert_eq!(scored.len(), 1);
+        assert_eq!(scored[0].path, PathBuf::from("c, d.jpg"));
+        assert_eq!(score(file, &distances(), 64, &by_percent), scored);
+
+        let reversed = ScoreOptions { order: SortOrder::LeastSimilarFirst, ..Default::default() };
+        let paths: Vec<PathBuf> = score(file, &distances(), 64, &reversed).into_iter().map(|s| s.path).collect();
+        assert_eq!(paths, vec![PathBuf::from("b.jpg"), PathBuf::from("c, d.jpg")]);
+    }
+
+    #[test]
+    fn display_modes() {
+        assert_eq!(ScoreDisplay::Distance.format("16", 75.0), "16");
+        assert_eq!(ScoreDisplay::Percent.format("16", 75.0), "75%");
+        assert_eq!(ScoreDisplay::Both.format("16", 75.0), "16 (75%)");
+    }
+
+    #[test]
+    fn export_quotes_paths_and_follows_the_options() {
+        let map = HashMap::from([(PathBuf::from("a.jpg"), distances())]);
+        let options = ScoreOptions { filter: Some(ScoreFilter::MinSimilarity(50.0)), ..Default::default() };
+        let mut output = Vec::new();
+        export_csv(&mut output, &map, 64, &options).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "file,similar_file,distance,similarity\na.jpg,\"c, d.jpg\",4,93.75\na.jpg,b.jpg,16,75.00\n"
+        );
+    }
+}
@@ -0,0 +1,464 @@
+use std::collections::HashSet;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use serde_json::{Map, Value, json};
+use tiny_http::{Header, Request, Server};
+use tracing::{field, info, warn};
+
+use crate::hashing::{Encoding, HashingOptions};
+use crate::preprocessing::archive::split_locator;
+use crate::preprocessing::decoder::{DecodeMode, decode_bytes};
+use crate::preprocessing::preprocessor::PreprocessOptions;
+use super::handle::{HashRecord, HashingType, find_matches, hash_bytes, imagehash_strings};
+use super::index::{self, Index, IndexEntry, IndexError, settings};
+use super::progress::CancellationToken;
+use super::score::similarity;
+use super::watch::{Change, hash_path};
+
+pub const DEFAULT_BIND: &str = "127.0.0.1:8080";
+/// Larger uploads are refused.
+pub const MAX_BODY: u64 = 64 << 20;
+// how often serve checks for being cancelled and for changes to write
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Changes are collected this long before serve writes the index, so that a burst of them is written once.
+pub const SAVE_DELAY: Duration = Duration::from_secs(1);
+
+/// Status code and JSON body of an answer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub body: Value,
+}
+
+impl Response {
+    fn ok(body: Value) -> Self {
+        Response { status: 200, body }
+    }
+
+    fn error(status: u16, message: impl std::fmt::Display) -> Self {
+        Response { status, body: json!({ "error": message.to_string() }) }
+    }
+}
+
+/// Answers the requests of the HTTP service from an index, written back to `path` by [`Service::save_changes`]:
+///
+/// - `GET /stats` - number of entries, files and animations, and the settings of the index
+/// - `POST /hash?encoding=` - hashes of the image in the body, with the settings of the index
+/// - `POST /query?algorithm=&max_distance=&hash=&encoding=&min_quality=` - entries similar to the image in the body, or to `hash`
+/// - `PUT /entries?path=` - adds or replaces `path`, hashed from the body, or from disk under `root` when the body is empty
+/// - `DELETE /entries?path=` - removes `path`, or every entry of the archive at `path`
+///
+/// Requests can be handled from several threads at once; images are hashed without holding the index.
+pub struct Service {
+    index: RwLock<Index>,
+    path: Option<PathBuf>,
+    root: Option<PathBuf>,
+    // when the oldest change that is not written yet was made
+    unsaved: Mutex<Option<Instant>>,
+}
+
+impl Service {
+    /// Without a `root` images have to be uploaded; with one, files and archives under it can also be read from disk.
+    pub fn new(index: Index, path: Option<PathBuf>, root: Option<PathBuf>) -> Self {
+        Service { index: RwLock::new(index), path, root, unsaved: Mutex::new(None) }
+    }
+
+    pub fn index(&self) -> RwLockReadGuard<'_, Index> {
+        // a request that panicked leaves the index as consistent as any other
+        self.index.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn index_mut(&self) -> RwLockWriteGuard<'_, Index> {
+        self.index.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Writes the index to its path once the oldest unsaved change has waited `delay`; a zero delay writes
+    /// any change at once. Returns whether it was written; after a failure it is tried again by the next call.
+    pub fn save_changes(&self, delay: Duration) -> Result<bool, IndexError> {
+        let Some(path) = &self.path else { return Ok(false) };
+        {
+            let mut unsaved = self.unsaved.lock().unwrap_or_else(PoisonError::into_inner);
+            match *unsaved {
+                Some(since) if since.elapsed() >= delay => *unsaved = None,
+                _ => return Ok(false),
+            }
+        }
+        let result = self.index().save(path);
+        if result.is_err() {
+            self.changed();
+        }
+        result.map(|()| true)
+    }
+
+    fn changed(&self) {
+        self.unsaved.lock().unwrap_or_else(PoisonError::into_inner).get_or_insert_with(Instant::now);
+    }
+
+    pub fn handle(&self, method: &str, url: &str, body: &[u8]) -> Response {
+        let (route, query) = url.split_once('?').unwrap_or((url, ""));
+        let params = parse_query(query);
+        let param = |key: &str| params.iter().find(|(k, _)| k == key).map(|(_, value)| value.as_str());
+        let result = match (method, route) {
+            ("GET", "/stats") => Ok(self.stats()),
+            ("POST", "/hash") => self.hash(body, param("encoding")),
+            ("POST", "/query") => self.query(body, &param),
+            ("PUT", "/entries") => self.add(param("path"), body),
+            ("DELETE", "/entries") => self.remove(param("path")),
+            (_, "/stats" | "/hash" | "/query" | "/entries") => Err(Response::error(405, format!("{method} is not allowed on {route}"))),
+            _ => Err(Response::error(404, format!("no endpoint {route}"))),
+        };
+        result.unwrap_or_else(|response| response)
+    }
+
+    fn stats(&self) -> Response {
+        let index = self.index();
+        let files: HashSet<PathBuf> = index.entries
+            .iter()
+            .map(|entry| split_locator(&entry.path).map_or_else(|| entry.path.clone(), |(archive, _)| archive))
+            .collect();
+        let settings: Map<String, Value> = settings(&index.options, &index.hashing_options)
+            .into_iter()
+            .map(|(key, value)| (key, Value::String(value)))
+            .collect();
+        Response::ok(json!({
+            "entries": index.entries.len(),
+            "files": files.len(),
+            "animations": index.entries.iter().filter(|entry| !entry.record.frames.is_empty()).count(),
+            "version": index::VERSION,
+            "settings": settings,
+        }))
+    }
+
+    fn hash(&self, body: &[u8], encoding: Option<&str>) -> Result<Response, Response> {
+        let encoding = parse_encoding(encoding)?;
+        let record = self.hash_body(body)?;
+        let hashes: Map<String, Value> = match encoding {
+            // computed from the image as imagehash sees it rather than converted
+            Encoding::Imagehash => {
+                let img = decode_bytes(body, 0, 0, DecodeMode::Full).map_err(|err| Response::error(422, err))?;
+                imagehash_strings(&img).into_iter().map(|(hashing_type, hex)| (hashing_type.id().to_string(), Value::String(hex))).collect()
+            }
+            Encoding::Native => HashingType::ALL
+                .iter()
+                .filter_map(|hashing_type| {
+                    let hex = hashing_type.format_hash(record.hashes.get(hashing_type)?, Encoding::Native)?;
+                    Some((hashing_type.id().to_string(), Value::String(hex)))
+                })
+                .collect(),
+        };
+        Ok(Response::ok(json!({ "hashes": hashes, "quality": record.quality, "frames": record.frames.len() })))
+    }
+
+    fn query<'a>(&self, body: &[u8], param: &impl Fn(&str) -> Option<&'a str>) -> Result<Response, Response> {
+        let algorithm = match param("algorithm") {
+            Some(id) => HashingType::from_id(id).ok_or_else(|| Response::error(400, format!("unknown algorithm {id}")))?,
+            None => HashingType::PHash,
+        };
+        let (_, hashing_options) = self.options();
+        let max_distance = match param("max_distance") {
+            Some(value) => value.parse().map_err(|_| Response::error(400, format!("invalid max_distance {value}")))?,
+            None => algorithm.near_duplicate_distance(&hashing_options),
+        };
+        let min_quality = match param("min_quality") {
+            Some(value) => Some(value.parse().map_err(|_| Response::error(400, format!("invalid min_quality {value}")))?),
+            None => None,
+        };
+        let hash = match param("hash") {
+            Some(hex) => {
+                let encoding = parse_encoding(param("encoding"))?;
+                algorithm
+                    .parse_hash(hex, encoding, &hashing_options)
+                    .ok_or_else(|| Response::error(400, format!("{hex} is not a {algorithm} hash in the {encoding} encoding for the settings of the index")))?
+            }
+            None => self.hash_body(body)?
+                .hashes
+                .remove(&algorithm)
+                .ok_or_else(|| Response::error(422, format!("no {algorithm} hash for the image")))?,
+        };
+
+        let max = algorithm.max_distance(&hashing_options);
+        let matches: Vec<Value> = find_matches(&self.index().records(), algorithm, &hash, max_distance, min_quality)
+            .into_iter()
+            .map(|(path, distance)| json!({ "path": path, "distance": distance, "similarity": similarity(distance, max) }))
+            .collect();
+        Ok(Response::ok(json!({ "algorithm": algorithm.id(), "max_distance": max_distance, "matches": matches })))
+    }
+
+    fn add(&self, path: Option<&str>, body: &[u8]) -> Result<Response, Response> {
+        let path = PathBuf::from(path.ok_or_else(|| Response::error(400, "missing path"))?);
+        let (entries, archive) = if body.is_empty() {
+            self.check_readable(&path)?;
+            let (options, hashing_options) = self.options();
+            match hash_path(&path, &options, &hashing_options) {
+                Change::Hashed(entry) => (vec![entry], false),
+                Change::Archive { entries, .. } => (entries, true),
+                Change::Failed { error, .. } => return Err(Response::error(422, error)),
+                change => unreachable!("hashing returned {change:?}"),
+            }
+        } else {
+            let modified = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_millis() as u64);
+            (vec![IndexEntry { path: path.clone(), size: body.len() as u64, modified, record: self.hash_body(body)? }], false)
+        };
+
+        let mut index = self.index_mut();
+        // entries of the archive that are gone are dropped with it
+        let replaced = if archive { remove_entries(&mut index, &path) } else { Vec::new() };
+        let (mut added, mut updated) = (Vec::new(), Vec::new());
+        for entry in entries {
+            let path = entry.path.clone();
+            match index.insert(entry) {
+                None if !replaced.contains(&path) => added.push(path),
+                _ => updated.push(path),
+            }
+        }
+        drop(index);
+        self.changed();
+        Ok(Response::ok(json!({ "added": added, "updated": updated })))
+    }
+
+    fn remove(&self, path: Option<&str>) -> Result<Response, Response> {
+        let path = PathBuf::from(path.ok_or_else(|| Response::error(400, "missing path"))?);
+        let removed = remove_entries(&mut self.index_mut(), &path);
+        if removed.is_empty() {
+            return Err(Response::error(404, format!("{} is not in the index", path.display())));
+        }
+        self.changed();
+        Ok(Response::ok(json!({ "removed": removed })))
+    }
+
+    // files are only read from disk under the root, so that clients cannot have any file of the server hashed
+    fn check_readable(&self, path: &Path) -> Result<(), Response> {
+        let root = self.root.as_ref().ok_or_else(|| Response::error(403, "the service reads no files from disk, upload the image"))?;
+        let root = root.canonicalize().map_err(|err| Response::error(500, format!("the root {} is not readable: {err}", root.display())))?;
+        match path.canonicalize() {
+            Ok(file) if !file.starts_with(&root) => Err(Response::error(403, format!("{} is not under the root {}", path.display(), root.display()))),
+            Ok(file) if file.is_file() => Ok(()),
+            _ => Err(Response::error(404, format!("no file {}", path.display()))),
+        }
+    }
+
+    fn options(&self) -> (PreprocessOptions, HashingOptions) {
+        let index = self.index();
+        (index.options, index.hashing_options)
+    }
+
+    fn hash_body(&self, body: &[u8]) -> Result<HashRecord, Response> {
+        if body.is_empty() {
+            return Err(Response::error(400, "missing image"));
+        }
+        let (options, hashing_options) = self.options();
+        hash_bytes(body, &options, &hashing_options).map_err(|err| Response::error(422, err))
+    }
+}
+
+// the entry of `path` and the entries of the archive at `path`
+fn remove_entries(index: &mut Index, path: &Path) -> Vec<PathBuf> {
+    let inside = |entry: &IndexEntry| split_locator(&entry.path).is_some_and(|(archive, _)| archive == path);
+    let (removed, kept) = std::mem::take(&mut index.entries)
+        .into_iter()
+        .partition(|entry| entry.path == path || inside(entry));
+    index.entries = kept;
+    removed.into_iter().map(|entry: IndexEntry| entry.path).collect()
+}
+
+fn parse_encoding(name: Option<&str>) -> Result<Encoding, Response> {
+    name.map_or(Ok(Encoding::Native), |name| Encoding::from_name(name).ok_or_else(|| Response::error(400, format!("unknown encoding {name}"))))
+}
+
+// `key=value` pairs of a query string, percent-decoded
+fn parse_query(query: &str) -> Vec<(String, String)> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(key), percent_decode(value))
+        })
+        .collect()
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|hex| std::str::from_utf8(hex).ok()).and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+            (b'+', _) => decoded.push(b' '),
+            (byte, _) => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Listens on `address`, e.g. `127.0.0.1:8080`; port 0 picks a free one.
+pub fn bind(address: &str) -> io::Result<Server> {
+    Server::http(address).map_err(io::Error::other)
+}
+
+/// Answers requests on `workers` threads until `cancel` is triggered, writing changes to the index after
+/// [`SAVE_DELAY`] and once more before returning. A failing listener stops the other workers too.
+pub fn serve(server: &Server, service: &Service, workers: usize, cancel: &CancellationToken) -> io::Result<()> {
+    let answered = thread::scope(|scope| {
+        let workers: Vec<_> = (0..workers.max(1)).map(|_| scope.spawn(|| answer(server, service, cancel))).collect();
+        while !cancel.is_cancelled() {
+            thread::sleep(POLL_INTERVAL);
+            if let Err(err) = service.save_changes(SAVE_DELAY) {
+                warn!(path = service.path.as_deref().map(|path| field::display(path.display())), %err, "writing the index failed");
+            }
+        }
+        // every worker is joined before the first error is returned
+        let answered: Vec<io::Result<()>> = workers
+            .into_iter()
+            .map(|worker| worker.join().unwrap_or_else(|_| Err(io::Error::other("a worker panicked"))))
+            .collect();
+        answered.into_iter().collect::<io::Result<()>>()
+    });
+    service.save_changes(Duration::ZERO).map_err(io::Error::other)?;
+    answered
+}
+
+fn answer(server: &Server, service: &Service, cancel: &CancellationToken) -> io::Result<()> {
+    while !cancel.is_cancelled() {
+        match server.recv_timeout(POLL_INTERVAL) {
+            Ok(Some(request)) => {
+                if let Err(err) = respond(request, service) {
+                    warn!(%err, "answering a request failed");
+                }
+            }
+            Ok(None) => {}
+            Err(err) => {
+                cancel.cancel();
+                return Err(err);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn respond(mut request: Request, service: &Service) -> io::Result<()> {
+    let started = Instant::now();
+    let mut body = Vec::new();
+    request.as_reader().take(MAX_BODY + 1).read_to_end(&mut body)?;
+    let response = if body.len() as u64 > MAX_BODY {
+        Response::error(413, format!("uploads are limited to {MAX_BODY} bytes"))
+    } else {
+        service.handle(request.method().as_str(), request.url(), &body)
+    };
+    info!(method = %request.method(), url = request.url(), status = response.status, elapsed = ?started.elapsed(), "request");
+    let content_type = Header::from_bytes("Content-Type", "application/json").expect("valid header");
+    let answer = tiny_http::Response::from_string(response.body.to_string())
+        .with_status_code(response.status)
+        .with_header(content_type);
+    request.respond(answer)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::fs;
+    use std::io::Write;
+    use std::net::TcpStream;
+    use super::*;
+
+    fn empty_index() -> Index {
+        Index::new(HashMap::new(), PreprocessOptions::default(), HashingOptions::default())
+    }
+
+    fn service() -> Service {
+        Service::new(empty_index(), None, Some(PathBuf::from("test_images")))
+    }
+
+    #[test]
+    fn entries_can_be_added_queried_and_removed() {
+        let service = service();
+        let image = fs::read("test_images/test1.png").unwrap();
+        let added = service.handle("PUT", "/entries?path=uploads%2Fcat+1.png", &image);
+        assert_eq!(added, Response::ok(json!({ "added": ["uploads/cat 1.png"], "updated": [] })));
+        assert_eq!(service.handle("PUT", "/entries?path=test_images/test3.png", &[]).body["added"], json!(["test_images/test3.png"]));
+
+        let query = service.handle("POST", "/query?algorithm=dhash&max_distance=0", &image);
+        assert_eq!(query.body["matches"], json!([{ "path": "uploads/cat 1.png", "distance": 0, "similarity": 100.0 }]));
+        let hex = service.handle("POST", "/hash", &image).body["hashes"]["phash"].as_str().unwrap().to_string();
+        let imagehash = service.handle("POST", "/hash?encoding=imagehash", &image);
+        assert_eq!(imagehash.body["hashes"], json!({ "dhash": "c0d0c09090c0c8f0", "phash": "c16187631ec7689f" }));
+        let by_hash = service.handle("POST", &format!("/query?hash={hex}"), &[]);
+        assert_eq!(by_hash.body["matches"][0]["path"], "uploads/cat 1.png");
+
+        let stats = service.handle("GET", "/stats", &[]);
+        assert_eq!((stats.body["entries"].clone(), stats.body["files"].clone()), (json!(2), json!(2)));
+        assert_eq!(service.handle("DELETE", "/entries?path=uploads/cat%201.png", &[]).body, json!({ "removed": ["uploads/cat 1.png"] }));
+        assert_eq!(service.index().entries.len(), 1);
+    }
+
+    #[test]
+    fn files_are_only_read_from_disk_under_the_root() {
+        let service = service();
+        assert_eq!(service.handle("PUT", "/entries?path=test_images/../Cargo.toml", &[]).status, 403);
+        assert_eq!(service.handle("PUT", "/entries?path=test_images/missing.png", &[]).status, 404);
+        let uploads_only = Service::new(empty_index(), None, None);
+        assert_eq!(uploads_only.handle("PUT", "/entries?path=test_images/test3.png", &[]).status, 403);
+        assert!(uploads_only.index().entries.is_empty());
+    }
+
+    #[test]
+    fn changes_are_written_together() {
+        let path = std::env::temp_dir().join(format!("perceptual_hashing_{}_served.phix", std::process::id()));
+        let service = Service::new(empty_index(), Some(path.clone()), Some(PathBuf::from("test_images")));
+        assert!(!service.save_changes(Duration::ZERO).unwrap());
+        service.handle("PUT", "/entries?path=test_images/test1.png", &[]);
+        service.handle("PUT", "/entries?path=test_images/test3.png", &[]);
+        assert!(!service.save_changes(Duration::from_secs(60)).unwrap());
+        assert!(!path.exists());
+
+        assert!(service.save_changes(Duration::ZERO).unwrap());
+        assert_eq!(Index::load(&path).unwrap().entries.len(), 2);
+        assert!(!service.save_changes(Duration::ZERO).unwrap());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn bad_requests_are_reported_as_json() {
+        let service = service();
+        assert_eq!(service.handle("GET", "/nothing", &[]).status, 404);
+        assert_eq!(service.handle("GET", "/hash", &[]).status, 405);
+        assert_eq!(service.handle("POST", "/hash", &[]).body, json!({ "error": "missing image" }));
+        assert_eq!(service.handle("POST", "/hash", b"not an image").status, 422);
+        assert_eq!(service.handle("POST", "/query?algorithm=sha1", b"x").status, 400);
+        assert_eq!(service.handle("DELETE", "/entries?path=missing.png", &[]).status, 404);
+    }
+
+    #[test]
+    fn requests_are_answered_over_http() {
+        let server = bind("127.0.0.1:0").unwrap();
+        let address = server.server_addr().to_ip().unwrap();
+        let cancel = CancellationToken::new();
+        let token = cancel.clone();
+        let handle = thread::spawn(move || serve(&server, &service(), 2, &token));
+
+        // an upload that is still coming in keeps one worker busy while the other answers
+        let mut upload = TcpStream::connect(address).unwrap();
+        upload.write_all(b"POST /hash HTTP/1.1\r\nHost: localhost\r\nContent-Length: 1000\r\n\r\nGIF89a").unwrap();
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(b"GET /stats HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        assert!(response.contains("Content-Type: application/json"), "{response}");
+        let (_, body) = response.split_once("\r\n\r\n").unwrap();
+        assert_eq!(serde_json::from_str::<Value>(body).unwrap()["entries"], 0);
+
+        drop(upload);
+        cancel.cancel();
+        handle.join().unwrap().unwrap();
+    }
+}
@@ -1,23 +1,126 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::collections::HashMap;
+use std::io::{self, Write};
+use std::sync::mpsc::Sender;
 use color_eyre::{Result, Report, eyre::Ok};
 use super::calculate_similarity;
-use super::handle::HashingType;
+use super::handle::{HashingType, HashRecord, MatchOptions, ScanOptions, calculate_records, record_distance};
+use super::progress::{CancellationToken, ProgressEvent};
+use super::composite::{CompositeResult, CompositeRule};
+use super::score::{self, ScoreOptions, Scored};
+use super::frames::{Alignment, align};
+use super::index::Index;
+use crate::preprocessing::preprocessor::PreprocessOptions;
+use crate::hashing::HashingOptions;
 
 pub struct SimilarityAnalyzer {
+    dir_path : PathBuf,
+    options : PreprocessOptions,
+    hashing_options : HashingOptions,
+    records : HashMap<PathBuf, HashRecord>,
+    hashing_type : Option<HashingType>,
+    match_options : MatchOptions,
     pub similarity_map : HashMap<PathBuf, Vec<(PathBuf, u32)>>
 }
 
 impl SimilarityAnalyzer {
-    // constructor
-    pub fn new(dir_path : PathBuf, hash_type : HashingType) -> Result<Self, Report> {
-        let similarities: HashMap<PathBuf, Vec<(PathBuf, u32)>> = calculate_similarity(&dir_path, hash_type);
+    // constructor - decodes and hashes every file with all algorithms, or takes the hashes from tags
+    pub fn new(
+        dir_path : PathBuf,
+        scan : &ScanOptions,
+        progress : Option<Sender<ProgressEvent>>,
+        cancel : &CancellationToken
+    ) -> Result<Self, Report> {
+        let records = calculate_records(&dir_path, scan, progress.as_ref(), cancel)?;
         Ok(Self {
-            similarity_map : similarities
+            dir_path,
+            options : scan.options,
+            hashing_options : scan.hashing_options,
+            records,
+            hashing_type : None,
+            match_options : MatchOptions::default(),
+            similarity_map : HashMap::new()
         })
     }
 
+    // whether the cached hashes can be reused for this folder and settings
+    pub fn is_valid_for(&self, dir_path : &Path, options : &PreprocessOptions, hashing_options : &HashingOptions) -> bool {
+        self.dir_path == dir_path && self.options == *options && self.hashing_options == *hashing_options
+    }
+
+    // switching the algorithm only recomputes the distances
+    pub fn analyze(&mut self, hash_type : HashingType, match_options : &MatchOptions) {
+        self.similarity_map = calculate_similarity(&self.records, hash_type, &self.hashing_options, match_options);
+        self.hashing_type = Some(hash_type);
+        self.match_options = *match_options;
+    }
+
+    // replaces the hashes of one file (None when it is gone) and its distances, without comparing every pair again
+    pub fn update(&mut self, file_path : &Path, record : Option<HashRecord>) {
+        self.records.remove(file_path);
+        self.similarity_map.remove(file_path);
+        for distances in self.similarity_map.values_mut() {
+            distances.retain(|(path, _)| path != file_path);
+        }
+        let Some(record) = record else { return };
+        self.records.insert(file_path.to_path_buf(), record);
+        let Some(hashing_type) = self.hashing_type else { return };
+
+        let record = &self.records[file_path];
+        let mut distances : Vec<(PathBuf, u32)> = self.records
+            .iter()
+            .filter_map(|(path, other)| {
+                let distance = record_distance(record, other, hashing_type, &self.hashing_options, &self.match_options)?;
+                Some((path.clone(), distance))
+            })
+            .collect();
+        for (path, distance) in &distances {
+            if let Some(others) = self.similarity_map.get_mut(path) {
+                others.push((file_path.to_path_buf(), *distance));
+                others.sort_by_key(|&(_, distance)| distance);
+            }
+        }
+        if !distances.is_empty() {
+            distances.sort_by_key(|&(_, distance)| distance);
+            self.similarity_map.insert(file_path.to_path_buf(), distances);
+        }
+    }
+
+    fn max_distance(&self) -> u32 {
+        self.hashing_type.map_or(0, |hashing_type| hashing_type.max_distance(&self.hashing_options))
+    }
+
+    // files similar to file_path for the last analyzed algorithm, as the results screen lists them
+    pub fn similar_files(&self, file_path : &Path, options : &ScoreOptions) -> Vec<Scored> {
+        let distances = self.similarity_map.get(file_path).map_or(&[][..], Vec::as_slice);
+        score::score(file_path, distances, self.max_distance(), options)
+    }
+
+    pub fn export_csv<W: Write>(&self, writer : &mut W, options : &ScoreOptions) -> io::Result<()> {
+        score::export_csv(writer, &self.similarity_map, self.max_distance(), options)
+    }
+
+    // the hashes of the scan with the settings they were computed with, to be saved and queried later
+    pub fn index(&self) -> Index {
+        Index::new(self.records.clone(), self.options, self.hashing_options)
+    }
+
+    // None when either file has no hashes
+    pub fn evaluate(&self, rule : &CompositeRule, file_a : &Path, file_b : &Path) -> Option<CompositeResult> {
+        Some(rule.evaluate(self.records.get(file_a)?, self.records.get(file_b)?))
+    }
+
+    // None unless both files have several frames
+    pub fn alignment(&self, file_a : &Path, file_b : &Path, min_similarity : f64) -> Option<Alignment> {
+        let hashing_type = self.hashing_type?;
+        let (a, b) = (self.records.get(file_a)?, self.records.get(file_b)?);
+        if a.frames.is_empty() || b.frames.is_empty() {
+            return None;
+        }
+        align(&a.frame_hashes(hashing_type), &b.frame_hashes(hashing_type), self.max_distance(), min_similarity)
+    }
+
     pub fn get_one_file_similarity(&self, file_path : &PathBuf) -> &Vec<(PathBuf, u32)> {
         &self.similarity_map[file_path]
     }
-}
\ No newline at end of file
+}
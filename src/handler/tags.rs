@@ -0,0 +1,405 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use image::ImageError;
+use tracing::{debug, warn};
+
+use crate::hashing::{Encoding, HashingOptions};
+use crate::preprocessing::preprocessor::PreprocessOptions;
+use super::handle::{HashRecord, HashingType, calculate_record};
+use super::index::settings;
+
+/// Name of the extended attribute holding the hashes.
+pub const XATTR_NAME: &str = "user.perceptual_hashing";
+/// XMP namespace of the hashes in sidecars.
+pub const XMP_NAMESPACE: &str = "https://github.com/OlaszPL/perceptual-hashing/xmp/1.0/";
+const XMP_PREFIX: &str = "phash";
+// bumped when the fields change; tags of other versions are ignored
+const VERSION: &str = "1";
+
+/// Where the hashes of a file are kept next to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TagStore {
+    /// The `user.perceptual_hashing` extended attribute, on file systems that support them.
+    Xattr,
+    /// An XMP sidecar named after the file, `photo.jpg.xmp`.
+    Xmp,
+}
+
+impl TagStore {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "xattr" => Some(TagStore::Xattr),
+            "xmp" => Some(TagStore::Xmp),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for TagStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TagStore::Xattr => write!(f, "xattr"),
+            TagStore::Xmp => write!(f, "xmp"),
+        }
+    }
+}
+
+/// The hashes of `path` from `store`; `None` when there are none, or when they were computed from
+/// other content or with other settings.
+pub fn read(path: &Path, store: TagStore, options: &PreprocessOptions, hashing_options: &HashingOptions) -> io::Result<Option<HashRecord>> {
+    let Some(fields) = stored_fields(path, store)? else { return Ok(None) };
+    Ok(parse_fields(&fields, fingerprint(path)?, options, hashing_options))
+}
+
+/// Stores the hashes of `path` in `store`, with the settings they were computed with and the size and
+/// checksum of the file, so that they are not used once the file changes. Other metadata in a sidecar is kept.
+pub fn write(path: &Path, store: TagStore, record: &HashRecord, options: &PreprocessOptions, hashing_options: &HashingOptions) -> io::Result<()> {
+    store_fields(path, store, &fields(record, fingerprint(path)?, options, hashing_options))
+}
+
+fn stored_fields(path: &Path, store: TagStore) -> io::Result<Option<Vec<(String, String)>>> {
+    match store {
+        TagStore::Xattr => read_xattr(path),
+        TagStore::Xmp => match fs::read_to_string(sidecar_path(path)) {
+            Ok(xmp) => Ok(xmp_block(&xmp).map(|(start, end)| xmp_fields(&xmp[start..end]))),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        },
+    }
+}
+
+fn store_fields(path: &Path, store: TagStore, fields: &[(String, String)]) -> io::Result<()> {
+    match store {
+        TagStore::Xattr => write_xattr(path, fields),
+        TagStore::Xmp => {
+            let sidecar = sidecar_path(path);
+            let xmp = match fs::read_to_string(&sidecar) {
+                Ok(xmp) => merge_xmp(&xmp, fields).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("{} is not an XMP packet", sidecar.display())))?,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => new_xmp(fields),
+                Err(err) => return Err(err),
+            };
+            fs::write(sidecar, xmp)
+        }
+    }
+}
+
+/// Removes the hashes of `path` from `store`, returning whether there were any. A sidecar holding
+/// nothing else is deleted.
+pub fn strip(path: &Path, store: TagStore) -> io::Result<bool> {
+    match store {
+        TagStore::Xattr => strip_xattr(path),
+        TagStore::Xmp => {
+            let sidecar = sidecar_path(path);
+            let xmp = match fs::read_to_string(&sidecar) {
+                Ok(xmp) => xmp,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+                Err(err) => return Err(err),
+            };
+            let Some((start, end)) = xmp_block(&xmp) else { return Ok(false) };
+            let rest = format!("{}{}", xmp[..start].trim_end_matches([' ', '\t']), xmp[end..].trim_start_matches(['\r', '\n']));
+            if rest.contains("<rdf:Description") {
+                fs::write(sidecar, rest)?;
+            } else {
+                fs::remove_file(sidecar)?;
+            }
+            Ok(true)
+        }
+    }
+}
+
+/// Like `calculate_record`, but takes the hashes from `store` when they are current and stores them
+/// there otherwise. Failing to store them, e.g. on a read-only file system, is not an error.
+pub fn tagged_record(path: &Path, store: TagStore, options: &PreprocessOptions, hashing_options: &HashingOptions) -> Result<HashRecord, ImageError> {
+    let stored = stored_fields(path, store).unwrap_or_else(|err| {
+        debug!(path = %path.display(), %store, error = %err, "reading the tags failed");
+        None
+    });
+    // read once, for checking the stored hashes and for storing new ones
+    let fingerprint = fingerprint(path)?;
+    if let Some(record) = stored.and_then(|fields| parse_fields(&fields, fingerprint, options, hashing_options)) {
+        debug!(path = %path.display(), %store, "hashes taken from the tags");
+        return Ok(record);
+    }
+    let record = calculate_record(path, options, hashing_options)?;
+    // the hashes are still good when they cannot be stored, e.g. on read-only media
+    if let Err(err) = store_fields(path, store, &fields(&record, fingerprint, options, hashing_options)) {
+        warn!(path = %path.display(), %store, error = %err, "storing the hashes failed");
+    }
+    Ok(record)
+}
+
+/// `photo.jpg.xmp` for `photo.jpg`, so that files differing only in their extension keep apart.
+pub fn sidecar_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".xmp");
+    PathBuf::from(name)
+}
+
+// size and CRC-32 of the content, which unlike the modification time survive copies
+fn fingerprint(path: &Path) -> io::Result<(u64, u32)> {
+    let mut file = File::open(path)?;
+    let (mut crc, mut buffer) = (flate2::Crc::new(), vec![0; 64 * 1024]);
+    loop {
+        match file.read(&mut buffer)? {
+            0 => break,
+            n => crc.update(&buffer[..n]),
+        }
+    }
+    Ok((crc.amount() as u64, crc.sum()))
+}
+
+// the stored fields: version, fingerprint, the settings of index files, then the hashes as hex
+fn fields(record: &HashRecord, (size, crc): (u64, u32), options: &PreprocessOptions, hashing_options: &HashingOptions) -> Vec<(String, String)> {
+    let mut fields = vec![
+        ("version".to_string(), VERSION.to_string()),
+        ("size".to_string(), size.to_string()),
+        ("crc32".to_string(), format!("{crc:08x}")),
+    ];
+    fields.extend(settings(options, hashing_options));
+    if let Some(quality) = record.quality {
+        fields.push(("quality".to_string(), quality.to_string()));
+    }
+    let hashes = |prefix: String, hashes: &HashMap<HashingType, _>| -> Vec<(String, String)> {
+        HashingType::ALL
+            .iter()
+            .filter_map(|hashing_type| {
+                let hex = hashing_type.format_hash(hashes.get(hashing_type)?, Encoding::Native)?;
+                Some((format!("{prefix}{}", hashing_type.id()), hex))
+            })
+            .collect()
+    };
+    fields.extend(hashes("hash.".to_string(), &record.hashes));
+    for (i, frame) in record.frames.iter().enumerate() {
+        fields.extend(hashes(format!("frame.{i}."), frame));
+    }
+    fields
+}
+
+fn parse_fields(fields: &[(String, String)], (size, crc): (u64, u32), options: &PreprocessOptions, hashing_options: &HashingOptions) -> Option<HashRecord> {
+    let get = |key: &str| fields.iter().find(|(k, _)| k == key).map(|(_, value)| value.as_str());
+    if get("version") != Some(VERSION) || get("size") != Some(&size.to_string()) || get("crc32") != Some(&format!("{crc:08x}")) {
+        return None;
+    }
+    if settings(options, hashing_options).iter().any(|(key, value)| get(key) != Some(value)) {
+        return None;
+    }
+
+    let hashes = |prefix: &str| -> Option<HashMap<HashingType, _>> {
+        HashingType::ALL
+            .iter()
+            .filter_map(|&hashing_type| Some((hashing_type, get(&format!("{prefix}{}", hashing_type.id()))?)))
+            .map(|(hashing_type, hex)| Some((hashing_type, hashing_type.parse_hash(hex, Encoding::Native, hashing_options)?)))
+            .collect()
+    };
+    let mut record = HashRecord { hashes: hashes("hash.")?, ..Default::default() };
+    record.quality = match get("quality") {
+        Some(quality) => Some(quality.parse().ok()?),
+        None => None,
+    };
+    while get(&format!("frame.{}.{}", record.frames.len(), HashingType::DHash.id())).is_some() {
+        record.frames.push(hashes(&format!("frame.{}.", record.frames.len()))?);
+    }
+    Some(record)
+}
+
+// zlib-compressed, as ext4 keeps a value within one block and segment hashes alone take about 4 KB as text
+#[cfg(unix)]
+fn read_xattr(path: &Path) -> io::Result<Option<Vec<(String, String)>>> {
+    let Some(value) = xattr::get(path, XATTR_NAME)? else { return Ok(None) };
+    let mut text = String::new();
+    // values written before the compression are plain text
+    if flate2::read::ZlibDecoder::new(value.as_slice()).read_to_string(&mut text).is_err() {
+        text = String::from_utf8_lossy(&value).into_owned();
+    }
+    Ok(Some(text.lines().filter_map(|line| line.split_once('=')).map(|(key, value)| (key.to_string(), value.to_string())).collect()))
+}
+
+#[cfg(unix)]
+fn write_xattr(path: &Path, fields: &[(String, String)]) -> io::Result<()> {
+    let text: String = fields.iter().map(|(key, value)| format!("{key}={value}\n")).collect();
+    let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::best());
+    encoder.write_all(text.as_bytes())?;
+    xattr::set(path, XATTR_NAME, &encoder.finish()?)
+}
+
+#[cfg(unix)]
+fn strip_xattr(path: &Path) -> io::Result<bool> {
+    if xattr::get(path, XATTR_NAME)?.is_none() {
+        return Ok(false);
+    }
+    xattr::remove(path, XATTR_NAME)?;
+    Ok(true)
+}
+
+#[cfg(not(unix))]
+fn read_xattr(_path: &Path) -> io::Result<Option<Vec<(String, String)>>> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "extended attributes are not supported on this platform"))
+}
+
+#[cfg(not(unix))]
+fn write_xattr(path: &Path, _fields: &[(String, String)]) -> io::Result<()> {
+    read_xattr(path).map(|_| ())
+}
+
+#[cfg(not(unix))]
+fn strip_xattr(path: &Path) -> io::Result<bool> {
+    read_xattr(path).map(|_| false)
+}
+
+// our rdf:Description, written with the fields as attributes
+fn xmp_description(fields: &[(String, String)]) -> String {
+    let mut description = format!("  <rdf:Description rdf:about=\"\"\n    xmlns:{XMP_PREFIX}=\"{XMP_NAMESPACE}\"");
+    for (key, value) in fields {
+        description.push_str(&format!("\n    {XMP_PREFIX}:{key}=\"{}\"", escape(value)));
+    }
+    description.push_str("/>\n");
+    description
+}
+
+fn new_xmp(fields: &[(String, String)]) -> String {
+    format!(
+        "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n{} </rdf:RDF>\n</x:xmpmeta>\n<?xpacket end=\"w\"?>\n",
+        xmp_description(fields)
+    )
+}
+
+// replaces our description in an existing packet, or adds it; None when there is no rdf:RDF to add it to
+fn merge_xmp(xmp: &str, fields: &[(String, String)]) -> Option<String> {
+    let description = xmp_description(fields);
+    if let Some((start, end)) = xmp_block(xmp) {
+        let end = end + xmp[end..].len() - xmp[end..].trim_start_matches(['\r', '\n']).len();
+        return Some(format!("{}{description}{}", &xmp[..start], &xmp[end..]));
+    }
+    let close = xmp.find("</rdf:RDF>")?;
+    let line_start = xmp[..close].rfind('\n').map_or(0, |i| i + 1);
+    Some(format!("{}{description}{}", &xmp[..line_start], &xmp[line_start..]))
+}
+
+// byte range of our rdf:Description, from the indentation of its line to after its `/>`
+fn xmp_block(xmp: &str) -> Option<(usize, usize)> {
+    let namespace = xmp.find(&format!("xmlns:{XMP_PREFIX}=\"{XMP_NAMESPACE}\""))?;
+    let start = xmp[..namespace].rfind("<rdf:Description")?;
+    let start = xmp[..start].rfind('\n').map_or(0, |i| i + 1);
+    let end = namespace + xmp[namespace..].find("/>")? + 2;
+    Some((start, end))
+}
+
+fn xmp_fields(description: &str) -> Vec<(String, String)> {
+    let attribute = format!("{XMP_PREFIX}:");
+    description
+        .split_whitespace()
+        .filter_map(|token| token.strip_prefix(&attribute))
+        .filter_map(|token| {
+            let (key, value) = token.split_once('=')?;
+            let value = value.trim_end_matches("/>").strip_prefix('"')?.strip_suffix('"')?;
+            Some((key.to_string(), unescape(value)))
+        })
+        .collect()
+}
+
+fn escape(value: &str) -> String {
+    value.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn unescape(value: &str) -> String {
+    value.replace("&quot;", "\"").replace("&gt;", ">").replace("&lt;", "<").replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::handler::handle::{ScanOptions, calculate_records};
+    use crate::handler::progress::{CancellationToken, ProgressEvent};
+    use super::*;
+
+    fn temp_copy(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("perceptual_hashing_{}_tags", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        fs::copy("test_images/test1.png", &path).unwrap();
+        path
+    }
+
+    #[test]
+    fn sidecars_keep_other_metadata() {
+        let path = temp_copy("sidecar.png");
+        let (options, hashing_options) = (PreprocessOptions::default(), HashingOptions::default());
+        let record = calculate_record(&path, &options, &hashing_options).unwrap();
+        let other = "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n  <rdf:Description rdf:about=\"\" xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\" xmp:Rating=\"5\"/>\n </rdf:RDF>\n</x:xmpmeta>\n";
+        fs::write(sidecar_path(&path), other).unwrap();
+
+        write(&path, TagStore::Xmp, &record, &options, &hashing_options).unwrap();
+        // written twice, the description is replaced rather than added again
+        write(&path, TagStore::Xmp, &record, &options, &hashing_options).unwrap();
+        let xmp = fs::read_to_string(sidecar_path(&path)).unwrap();
+        assert_eq!(xmp.matches(XMP_NAMESPACE).count(), 1);
+        assert!(xmp.contains("xmp:Rating=\"5\""));
+        assert!(xmp.contains(&format!("phash:hash.dhash=\"{}\"", record.hashes[&HashingType::DHash].to_hex())));
+
+        let read_back = read(&path, TagStore::Xmp, &options, &hashing_options).unwrap().unwrap();
+        assert_eq!(read_back.hashes[&HashingType::PHash], record.hashes[&HashingType::PHash]);
+        assert_eq!(read_back.quality, record.quality);
+        // other settings, other hashes
+        let other_settings = HashingOptions { segments: crate::hashing::segments::SegmentOptions { grid: 2 }, ..Default::default() };
+        assert_eq!(read(&path, TagStore::Xmp, &options, &other_settings).unwrap(), None);
+
+        assert!(strip(&path, TagStore::Xmp).unwrap());
+        assert_eq!(fs::read_to_string(sidecar_path(&path)).unwrap(), other);
+        assert!(!strip(&path, TagStore::Xmp).unwrap());
+    }
+
+    #[test]
+    fn tags_of_modified_files_are_ignored() {
+        let path = temp_copy("modified.png");
+        let (options, hashing_options) = (PreprocessOptions::default(), HashingOptions::default());
+        let record = tagged_record(&path, TagStore::Xmp, &options, &hashing_options).unwrap();
+        assert!(sidecar_path(&path).exists());
+        assert_eq!(tagged_record(&path, TagStore::Xmp, &options, &hashing_options).unwrap().hashes[&HashingType::Pdq], record.hashes[&HashingType::Pdq]);
+
+        fs::copy("test_images/test3.png", &path).unwrap();
+        assert_eq!(read(&path, TagStore::Xmp, &options, &hashing_options).unwrap(), None);
+        assert!(strip(&path, TagStore::Xmp).unwrap());
+        assert!(!sidecar_path(&path).exists());
+    }
+
+    #[test]
+    fn scans_reuse_sidecars() {
+        let dir = std::env::temp_dir().join(format!("perceptual_hashing_{}_tagged_scan", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::copy("test_images/test2.png", dir.join("a.png")).unwrap();
+        let options = ScanOptions { tags: Some(TagStore::Xmp), ..Default::default() };
+        let scan = |tx| calculate_records(&dir, &options, Some(&tx), &CancellationToken::new()).unwrap();
+
+        let (tx, _rx) = std::sync::mpsc::channel();
+        let first = scan(tx);
+        assert!(sidecar_path(&dir.join("a.png")).exists());
+        let (tx, rx) = std::sync::mpsc::channel();
+        let second = scan(tx);
+        // the sidecar is neither hashed nor counted
+        assert_eq!(rx.iter().next(), Some(ProgressEvent::Discovered(1)));
+        assert_eq!(second[&dir.join("a.png")].hashes[&HashingType::Segments], first[&dir.join("a.png")].hashes[&HashingType::Segments]);
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hashes_round_trip_through_extended_attributes() {
+        let path = temp_copy("xattr.png");
+        let (options, hashing_options) = (PreprocessOptions::default(), HashingOptions::default());
+        let record = calculate_record(&path, &options, &hashing_options).unwrap();
+        match write(&path, TagStore::Xattr, &record, &options, &hashing_options) {
+            Ok(()) => {}
+            // e.g. tmpfs without user xattrs
+            Err(err) if err.kind() == io::ErrorKind::Unsupported || err.raw_os_error() == Some(95) => return,
+            Err(err) => panic!("{err}"),
+        }
+        let read_back = read(&path, TagStore::Xattr, &options, &hashing_options).unwrap().unwrap();
+        assert_eq!(read_back.hashes[&HashingType::Blockhash], record.hashes[&HashingType::Blockhash]);
+        assert!(strip(&path, TagStore::Xattr).unwrap());
+        assert_eq!(read(&path, TagStore::Xattr, &options, &hashing_options).unwrap(), None);
+        // plain text from before the compression
+        xattr::set(&path, XATTR_NAME, b"version=1\n").unwrap();
+        assert_eq!(read_xattr(&path).unwrap(), Some(vec![("version".to_string(), "1".to_string())]));
+    }
+}
@@ -0,0 +1,399 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{RecvTimeoutError, Sender, channel};
+use std::time::{Duration, Instant};
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use notify::event::{ModifyKind, RenameMode};
+use rayon::prelude::*;
+use serde::Serialize;
+use tracing::{debug, info, warn};
+
+use crate::hashing::HashingOptions;
+use crate::preprocessing::archive::{ArchiveKind, locator, split_locator, visit_entries};
+use crate::preprocessing::preprocessor::PreprocessOptions;
+use super::handle::{HashingType, MatchOptions, calculate_record, hash_bytes, record_distance};
+use super::index::{self, Index, IndexEntry, stamp};
+use super::progress::CancellationToken;
+use super::scan::ScanFilter;
+use super::score::similarity;
+
+/// A file is hashed once no event arrived for it for this long, so that files still being written are not.
+pub const SETTLE_TIME: Duration = Duration::from_millis(500);
+// how often pending files are checked for having settled
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A change of the watched folder, with the hashes of new and modified files already computed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    Hashed(IndexEntry),
+    /// Every entry of an archive that was added or modified.
+    Archive { path: PathBuf, entries: Vec<IndexEntry> },
+    /// A file, or an archive with all its entries, is gone.
+    Removed(PathBuf),
+    Renamed { from: PathBuf, to: PathBuf },
+    Failed { path: PathBuf, error: String },
+}
+
+/// What watch mode reports, one JSON object per line when headless.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum WatchEvent {
+    Added { path: PathBuf },
+    Updated { path: PathBuf },
+    Removed { path: PathBuf },
+    Renamed { from: PathBuf, to: PathBuf },
+    Failed { path: PathBuf, error: String },
+    /// `path` was just added or modified and is a near-duplicate of `similar`.
+    Duplicate { path: PathBuf, similar: PathBuf, distance: u32, similarity: f64 },
+}
+
+/// Hashes the file at `path`, or every entry of the archive at `path`.
+pub fn hash_path(path: &Path, options: &PreprocessOptions, hashing_options: &HashingOptions) -> Change {
+    let (size, modified) = stamp(path);
+    let failed = |error: String| Change::Failed { path: path.to_path_buf(), error };
+    if ArchiveKind::of(path).is_none() {
+        return match calculate_record(path, options, hashing_options) {
+            Ok(record) => Change::Hashed(IndexEntry { path: path.to_path_buf(), size, modified, record }),
+            Err(err) => failed(err.to_string()),
+        };
+    }
+
+    // entries that are not images are left out, like when scanning
+    let mut entries = Vec::new();
+    let visited = visit_entries(path, |name, bytes| {
+        if let Ok(record) = hash_bytes(&bytes, options, hashing_options) {
+            entries.push(IndexEntry { path: locator(path, &name), size, modified, record });
+        }
+        ControlFlow::Continue(())
+    });
+    match visited {
+        Ok(()) => Change::Archive { path: path.to_path_buf(), entries },
+        Err(err) => failed(err.to_string()),
+    }
+}
+
+// the file an entry was read from: the archive for archive entries
+fn container(path: &Path) -> PathBuf {
+    split_locator(path).map_or_else(|| path.to_path_buf(), |(archive, _)| archive)
+}
+
+fn is_index_file(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == index::EXTENSION)
+}
+
+/// The index `watch` keeps when none is given: `<name>.phix` next to the folder, or `index.phix` inside a folder
+/// without a name of its own like `/`.
+pub fn default_index_path(dir: &Path) -> io::Result<PathBuf> {
+    let dir = fs::canonicalize(dir)?;
+    Ok(match (dir.parent(), dir.file_name()) {
+        (Some(parent), Some(name)) => {
+            let mut name = name.to_os_string();
+            name.push(format!(".{}", index::EXTENSION));
+            parent.join(name)
+        }
+        _ => dir.join(format!("index.{}", index::EXTENSION)),
+    })
+}
+
+/// Changes that bring `index` up to date with the files in `dir` that `filter` accepts: files that are new or whose
+/// size or modification time differ are hashed with the settings of the index, files that are gone are removed.
+pub fn sync(dir: &Path, index: &Index, filter: &ScanFilter) -> Vec<Change> {
+    let files: HashSet<PathBuf> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && !is_index_file(path) && filter.accepts(path))
+        .collect();
+    let known: HashMap<PathBuf, (u64, u64)> = index.entries
+        .iter()
+        .map(|entry| (container(&entry.path), (entry.size, entry.modified)))
+        .collect();
+
+    let mut gone: Vec<&PathBuf> = known.keys().filter(|file| file.parent() == Some(dir) && !files.contains(*file)).collect();
+    gone.sort();
+    let mut changed: Vec<&PathBuf> = files.iter().filter(|file| known.get(*file) != Some(&stamp(file))).collect();
+    changed.sort();
+
+    let hashed: Vec<Change> = changed.par_iter().map(|file| hash_path(file, &index.options, &index.hashing_options)).collect();
+    gone.into_iter().map(|file| Change::Removed(file.clone())).chain(hashed).collect()
+}
+
+/// Watches the files of `dir` that `filter` accepts (not its subfolders, like scans) until `cancel` is triggered or `changes` is dropped,
+/// sending a change as soon as a file has settled. A file moved away settles as removed unless the
+/// other half of the rename arrives first; the renamed file is hashed again in case it was unknown,
+/// e.g. a temporary file that was written completely.
+pub fn watch(
+    dir: &Path,
+    options: &PreprocessOptions,
+    hashing_options: &HashingOptions,
+    filter: &ScanFilter,
+    changes: &Sender<Change>,
+    cancel: &CancellationToken
+) -> notify::Result<()> {
+    let (tx, rx) = channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    watcher.watch(dir, RecursiveMode::NonRecursive)?;
+    info!(dir = %dir.display(), "watching");
+
+    let mut pending: HashMap<PathBuf, Instant> = HashMap::new();
+    while !cancel.is_cancelled() {
+        let event: Event = match rx.recv_timeout(POLL_INTERVAL) {
+            Ok(event) => event?,
+            Err(RecvTimeoutError::Timeout) => Event::default(),
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        let paths: Vec<PathBuf> = event.paths.into_iter().filter(|path| !is_index_file(path)).collect();
+        let mut immediate = Vec::new();
+        match event.kind {
+            EventKind::Access(_) | EventKind::Any | EventKind::Other => {}
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if paths.len() == 2 => {
+                pending.remove(&paths[0]);
+                pending.insert(paths[1].clone(), Instant::now());
+                immediate.push(Change::Renamed { from: paths[0].clone(), to: paths[1].clone() });
+            }
+            EventKind::Remove(_) => {
+                for path in paths {
+                    pending.remove(&path);
+                    immediate.push(Change::Removed(path));
+                }
+            }
+            EventKind::Create(_) | EventKind::Modify(_) => {
+                pending.extend(paths.into_iter().map(|path| (path, Instant::now())));
+            }
+        }
+
+        let settled: Vec<PathBuf> = pending.iter().filter(|(_, since)| since.elapsed() >= SETTLE_TIME).map(|(path, _)| path.clone()).collect();
+        for path in settled {
+            pending.remove(&path);
+            if path.is_file() && filter.accepts(&path) {
+                let started = Instant::now();
+                let change = hash_path(&path, options, hashing_options);
+                match &change {
+                    Change::Failed { error, .. } => warn!(path = %path.display(), error, "hashing failed"),
+                    _ => debug!(path = %path.display(), elapsed = ?started.elapsed(), "hashed"),
+                }
+                immediate.push(change);
+            } else if !path.is_dir() {
+                // gone, or no longer accepted by the filter, e.g. renamed to an excluded name
+                immediate.push(Change::Removed(path));
+            }
+        }
+        for change in immediate {
+            if changes.send(change).is_err() {
+                info!(dir = %dir.display(), "stopped watching");
+                return Ok(());
+            }
+        }
+    }
+    info!(dir = %dir.display(), "stopped watching");
+    Ok(())
+}
+
+/// An index kept up to date with the changes of a folder, reporting the near-duplicates of every file
+/// that is added or modified.
+pub struct LiveIndex {
+    pub index: Index,
+    pub hashing_type: HashingType,
+    pub match_options: MatchOptions,
+    /// Files within this distance are reported as near-duplicates.
+    pub max_distance: u32,
+}
+
+impl LiveIndex {
+    pub fn new(index: Index, hashing_type: HashingType, match_options: MatchOptions, max_distance: u32) -> Self {
+        LiveIndex { index, hashing_type, match_options, max_distance }
+    }
+
+    pub fn apply(&mut self, change: Change) -> Vec<WatchEvent> {
+        match change {
+            Change::Hashed(entry) => self.upsert(entry),
+            Change::Archive { path, entries } => {
+                let kept: HashSet<&PathBuf> = entries.iter().map(|entry| &entry.path).collect();
+                let stale: Vec<PathBuf> = self.entries_of(&path).into_iter().filter(|entry| !kept.contains(entry)).collect();
+                let mut events: Vec<WatchEvent> = stale.into_iter().flat_map(|entry| self.remove(&entry)).collect();
+                events.extend(entries.into_iter().flat_map(|entry| self.upsert(entry)));
+                events
+            }
+            Change::Removed(path) => {
+                let mut events = self.remove(&path);
+                events.extend(self.entries_of(&path).into_iter().flat_map(|entry| self.remove(&entry)));
+                events
+            }
+            Change::Renamed { from, to } => {
+                let moved: Vec<(PathBuf, PathBuf)> = self.index.get(&from)
+                    .map(|_| (from.clone(), to.clone()))
+                    .into_iter()
+                    .chain(self.entries_of(&from).into_iter().map(|entry| {
+                        let name = split_locator(&entry).expect("entry of an archive").1;
+                        (entry, locator(&to, &name))
+                    }))
+                    .collect();
+                moved
+                    .into_iter()
+                    .filter_map(|(from, to)| {
+                        let entry = self.index.remove(&from)?;
+                        self.index.insert(IndexEntry { path: to.clone(), ..entry });
+                        Some(WatchEvent::Renamed { from, to })
+                    })
+                    .collect()
+            }
+            Change::Failed { path, error } => vec![WatchEvent::Failed { path, error }],
+        }
+    }
+
+    // locators of the entries of the archive at `path`
+    fn entries_of(&self, path: &Path) -> Vec<PathBuf> {
+        self.index.entries
+            .iter()
+            .filter(|entry| split_locator(&entry.path).is_some_and(|(archive, _)| archive == path))
+            .map(|entry| entry.path.clone())
+            .collect()
+    }
+
+    fn remove(&mut self, path: &Path) -> Vec<WatchEvent> {
+        self.index.remove(path).map(|entry| WatchEvent::Removed { path: entry.path }).into_iter().collect()
+    }
+
+    // files whose hashes did not change, e.g. after a rename or a touch, are not reported again
+    fn upsert(&mut self, entry: IndexEntry) -> Vec<WatchEvent> {
+        let path = entry.path.clone();
+        let event = match self.index.insert(entry) {
+            Some(old) if old.record == self.index.get(&path).expect("just inserted").record => return Vec::new(),
+            Some(_) => WatchEvent::Updated { path: path.clone() },
+            None => WatchEvent::Added { path: path.clone() },
+        };
+
+        let record = &self.index.get(&path).expect("just inserted").record;
+        let hashing_options = &self.index.hashing_options;
+        let max_distance = self.hashing_type.max_distance(hashing_options);
+        let mut duplicates: Vec<(PathBuf, u32)> = self.index.entries
+            .iter()
+            .filter(|other| other.path != path)
+            .filter_map(|other| {
+                let distance = record_distance(record, &other.record, self.hashing_type, hashing_options, &self.match_options)?;
+                (distance <= self.max_distance).then(|| (other.path.clone(), distance))
+            })
+            .collect();
+        duplicates.sort_by(|(path1, distance1), (path2, distance2)| distance1.cmp(distance2).then_with(|| path1.cmp(path2)));
+
+        std::iter::once(event)
+            .chain(duplicates.into_iter().map(|(similar, distance)| WatchEvent::Duplicate {
+                path: path.clone(),
+                similar,
+                distance,
+                similarity: similarity(distance, max_distance),
+            }))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::write_archive;
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("perceptual_hashing_{}_{name}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn live_index() -> LiveIndex {
+        let index = Index::new(HashMap::new(), PreprocessOptions::default(), HashingOptions::default());
+        LiveIndex::new(index, HashingType::DHash, MatchOptions::default(), 10)
+    }
+
+    #[test]
+    fn default_index_paths_are_named_after_the_folder() {
+        let dir = temp_dir("default.index");
+        assert_eq!(default_index_path(&dir).unwrap(), fs::canonicalize(&dir).unwrap().with_file_name(format!("{}.phix", dir.file_name().unwrap().to_string_lossy())));
+        let cwd = std::env::current_dir().unwrap();
+        assert_eq!(default_index_path(Path::new(".")).unwrap(), cwd.parent().unwrap().join(format!("{}.phix", cwd.file_name().unwrap().to_string_lossy())));
+        #[cfg(unix)]
+        assert_eq!(default_index_path(Path::new("/")).unwrap(), Path::new("/index.phix"));
+        assert!(default_index_path(&dir.join("missing")).is_err());
+    }
+
+    #[test]
+    fn changes_are_reported_with_their_near_duplicates() {
+        let dir = temp_dir("live_index");
+        let (copy, other) = (dir.join("copy.png"), dir.join("other.png"));
+        fs::copy("test_images/test1.png", &copy).unwrap();
+        fs::copy("test_images/test3.png", &other).unwrap();
+        let (options, hashing_options) = (PreprocessOptions::default(), HashingOptions::default());
+        let mut live = live_index();
+
+        let original = hash_path(Path::new("test_images/test1.png"), &options, &hashing_options);
+        assert_eq!(live.apply(original), vec![WatchEvent::Added { path: PathBuf::from("test_images/test1.png") }]);
+        assert_eq!(live.apply(hash_path(&other, &options, &hashing_options)), vec![WatchEvent::Added { path: other.clone() }]);
+        let events = live.apply(hash_path(&copy, &options, &hashing_options));
+        assert_eq!(events, vec![
+            WatchEvent::Added { path: copy.clone() },
+            WatchEvent::Duplicate { path: copy.clone(), similar: PathBuf::from("test_images/test1.png"), distance: 0, similarity: 100.0 },
+        ]);
+        assert_eq!(
+            serde_json::to_string(&events[0]).unwrap(),
+            format!("{{\"event\":\"added\",\"path\":{}}}", serde_json::to_string(&copy).unwrap())
+        );
+
+        // hashing an unchanged file again reports nothing
+        assert_eq!(live.apply(hash_path(&copy, &options, &hashing_options)), vec![]);
+        let renamed = dir.join("renamed.png");
+        assert_eq!(live.apply(Change::Renamed { from: copy.clone(), to: renamed.clone() }), vec![WatchEvent::Renamed { from: copy, to: renamed.clone() }]);
+        assert_eq!(live.apply(Change::Removed(renamed.clone())), vec![WatchEvent::Removed { path: renamed }]);
+        assert_eq!(live.index.entries.len(), 2);
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn sync_hashes_what_changed_since_the_index_was_written() {
+        let dir = temp_dir("sync");
+        fs::copy("test_images/test1.png", dir.join("a.png")).unwrap();
+        fs::copy("test_images/test2.png", dir.join("b.png")).unwrap();
+        let mut live = live_index();
+        for change in sync(&dir, &live.index, &ScanFilter::default()) {
+            live.apply(change);
+        }
+        assert_eq!(live.index.entries.len(), 2);
+        assert!(sync(&dir, &live.index, &ScanFilter::default()).is_empty());
+
+        fs::remove_file(dir.join("a.png")).unwrap();
+        write_archive(&dir.join("photos.zip"), &[("c.png", &fs::read("test_images/test3.png").unwrap()), ("notes.txt", b"no image")]);
+        let changes = sync(&dir, &live.index, &ScanFilter::default());
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0], Change::Removed(dir.join("a.png")));
+        let events: Vec<WatchEvent> = changes.into_iter().flat_map(|change| live.apply(change)).collect();
+        assert_eq!(events, vec![
+            WatchEvent::Removed { path: dir.join("a.png") },
+            WatchEvent::Added { path: locator(&dir.join("photos.zip"), "c.png") },
+        ]);
+
+        assert_eq!(live.apply(Change::Removed(dir.join("photos.zip"))), vec![WatchEvent::Removed { path: locator(&dir.join("photos.zip"), "c.png") }]);
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn new_files_are_picked_up_once_settled() {
+        let dir = temp_dir("watch");
+        let (tx, rx) = channel();
+        let cancel = CancellationToken::new();
+        let (watched, token) = (dir.clone(), cancel.clone());
+        let watcher = std::thread::spawn(move || watch(&watched, &PreprocessOptions::default(), &HashingOptions::default(), &ScanFilter::default(), &tx, &token));
+        // give the watcher time to start
+        std::thread::sleep(Duration::from_millis(200));
+
+        fs::copy("test_images/test1.png", dir.join("new.png")).unwrap();
+        let change = rx.recv_timeout(Duration::from_secs(10)).expect("a change");
+        assert!(matches!(&change, Change::Hashed(entry) if entry.path == dir.join("new.png")), "{change:?}");
+
+        fs::remove_file(dir.join("new.png")).unwrap();
+        assert_eq!(rx.recv_timeout(Duration::from_secs(10)), Ok(Change::Removed(dir.join("new.png"))));
+        cancel.cancel();
+        watcher.join().unwrap().unwrap();
+        fs::remove_dir_all(dir).ok();
+    }
+}
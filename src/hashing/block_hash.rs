@@ -0,0 +1,203 @@
+use image::{DynamicImage, RgbaImage};
+use super::hash::BitHash;
+
+/// Parameters of the blockhash algorithm (Yang, Gu, Niu: "Block Mean Value Based Image Perceptual Hashing").
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockhashOptions {
+    /// The image is divided into `bits` x `bits` blocks, producing a `bits * bits` bit hash.
+    /// Must be a multiple of 4.
+    pub bits: u32,
+    /// Precise (method 2) weights pixels lying on block borders; quick (method 1)
+    /// assigns whole pixels and ignores the remainder when the size does not divide evenly.
+    pub precise: bool,
+}
+
+impl Default for BlockhashOptions {
+    fn default() -> Self {
+        // defaults of the blockhash.io command line tools
+        BlockhashOptions { bits: 16, precise: true }
+    }
+}
+
+/// Blockhash of the image, bit-for-bit identical to blockhash.py of blockhash.io when `img` is the
+/// original with its alpha channel; records see it flattened as `PreprocessOptions::alpha` says.
+pub fn block_hash(img: &DynamicImage, options: &BlockhashOptions) -> BitHash {
+    assert!(options.bits >= 4 && options.bits.is_multiple_of(4), "Blockhash bits must be a positive multiple of 4");
+
+    let rgba = img.to_rgba8();
+    let bits = options.bits;
+    let even = rgba.width().is_multiple_of(bits) && rgba.height().is_multiple_of(bits);
+
+    let (blocks, pixels_per_block) = if options.precise && !even {
+        precise_blocks(&rgba, bits)
+    } else {
+        quick_blocks(&rgba, bits)
+    };
+
+    translate_blocks_to_bits(&blocks, pixels_per_block)
+}
+
+// fully transparent pixels count as white
+fn total_value(rgba: &RgbaImage, x: u32, y: u32) -> f64 {
+    let [r, g, b, a] = rgba.get_pixel(x, y).0;
+    if a == 0 {
+        765.0
+    } else {
+        r as f64 + g as f64 + b as f64
+    }
+}
+
+fn quick_blocks(rgba: &RgbaImage, bits: u32) -> (Vec<f64>, f64) {
+    let block_width = rgba.width() / bits;
+    let block_height = rgba.height() / bits;
+
+    let mut blocks = Vec::with_capacity((bits * bits) as usize);
+    for y in 0..bits {
+        for x in 0..bits {
+            let mut value = 0.0;
+            for iy in 0..block_height {
+                for ix in 0..block_width {
+                    value += total_value(rgba, x * block_width + ix, y * block_height + iy);
+                }
+            }
+            blocks.push(value);
+        }
+    }
+
+    (blocks, (block_width * block_height) as f64)
+}
+
+fn precise_blocks(rgba: &RgbaImage, bits: u32) -> (Vec<f64>, f64) {
+    let (width, height) = rgba.dimensions();
+    let even_x = width.is_multiple_of(bits);
+    let even_y = height.is_multiple_of(bits);
+    let block_width = width as f64 / bits as f64;
+    let block_height = height as f64 / bits as f64;
+
+    let bits = bits as usize;
+    let mut blocks = vec![0.0; bits * bits];
+
+    // splits pixel `i` between the block it starts in and the next one
+    let split = |i: u32, len: u32, block_len: f64, even: bool| -> (usize, usize, f64, f64) {
+        if even {
+            let block = (i as f64 / block_len).floor() as usize;
+            return (block, block, 1.0, 0.0);
+        }
+        let position = (i + 1) as f64 % block_len;
+        let (fraction, integer) = (position.fract(), position.trunc());
+        let first = (i as f64 / block_len).floor() as usize;
+        // integer part is 0 on the bottom/right border and on block boundaries
+        let second = if integer > 0.0 || i + 1 == len {
+            first
+        } else {
+            (i as f64 / block_len).ceil() as usize
+        };
+        (first, second, 1.0 - fraction, fraction)
+    };
+
+    for y in 0..height {
+        let (top, bottom, weight_top, weight_bottom) = split(y, height, block_height, even_y);
+        for x in 0..width {
+            let (left, right, weight_left, weight_right) = split(x, width, block_width, even_x);
+            let value = total_value(rgba, x, y);
+
+            blocks[top * bits + left] += value * weight_top * weight_left;
+            blocks[top * bits + right] += value * weight_top * weight_right;
+            blocks[bottom * bits + left] += value * weight_bottom * weight_left;
+            blocks[bottom * bits + right] += value * weight_bottom * weight_right;
+        }
+    }
+
+    (blocks, block_width * block_height)
+}
+
+fn median(values: &[f64]) -> f64 {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let mid = sorted.len() / 2;
+    if sorted.len().is_multiple_of(2) {
+        (sorted[mid - 1] + sorted[mid]) / 2.0
+    } else {
+        sorted[mid]
+    }
+}
+
+// each of the 4 horizontal bands is compared against its own median
+fn translate_blocks_to_bits(blocks: &[f64], pixels_per_block: f64) -> BitHash {
+    let half_block_value = pixels_per_block * 256.0 * 3.0 / 2.0;
+    let band_size = blocks.len() / 4;
+
+    BitHash::from_bits(blocks.chunks(band_size).flat_map(|band| {
+        let m = median(band);
+        // for images dominated by black or white many blocks equal the median,
+        // output 0 if the median is in the lower half of the value range, 1 otherwise
+        band.iter().map(move |&v| v > m || ((v - m).abs() < 1.0 && m > half_block_value))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use image::{DynamicImage, Rgb, RgbImage, Rgba, RgbaImage};
+    use crate::preprocessing::decoder::{DecodeMode, decode};
+    use super::*;
+
+    // bright left half, dark right half
+    fn halves(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, _| {
+            if x < width / 2 { Rgb([200, 200, 200]) } else { Rgb([20, 20, 20]) }
+        }))
+    }
+
+    #[test]
+    fn quick_and_precise_agree_on_evenly_divisible_images() {
+        let img = halves(32, 32);
+        let quick = block_hash(&img, &BlockhashOptions { bits: 16, precise: false });
+        let precise = block_hash(&img, &BlockhashOptions { bits: 16, precise: true });
+        assert_eq!(quick, precise);
+        assert_eq!(quick.len(), 256);
+        assert_eq!(quick.to_hex(), "ff00".repeat(16));
+    }
+
+    #[test]
+    fn precise_handles_sizes_not_divisible_by_bits() {
+        // 37 / 8 is not an integer, the border between the halves falls inside block 3
+        let img = halves(37, 37);
+        let hash = block_hash(&img, &BlockhashOptions { bits: 8, precise: true });
+        assert_eq!(hash.len(), 64);
+        for row in 0..8 {
+            assert!(hash.bit(row * 8 + 2), "row {row} should start bright");
+            assert!(!hash.bit(row * 8 + 6), "row {row} should end dark");
+        }
+    }
+
+    #[test]
+    fn uniform_images_follow_the_median_tie_rule() {
+        let white = DynamicImage::ImageRgb8(RgbImage::from_pixel(16, 16, Rgb([255, 255, 255])));
+        let black = DynamicImage::ImageRgb8(RgbImage::from_pixel(16, 16, Rgb([0, 0, 0])));
+        let options = BlockhashOptions { bits: 8, precise: true };
+        assert_eq!(block_hash(&white, &options).to_hex(), "ffffffffffffffff");
+        assert_eq!(block_hash(&black, &options).to_hex(), "0000000000000000");
+
+        // fully transparent pixels count as white no matter what RGB they hide
+        let transparent = DynamicImage::ImageRgba8(RgbaImage::from_pixel(16, 16, Rgba([0, 0, 0, 0])));
+        assert_eq!(block_hash(&transparent, &options), block_hash(&white, &options));
+    }
+
+    // hashes of blockhash.py of blockhash.io for the test images, none of whose sizes divides by 8 or 16
+    #[test]
+    fn compare_with_reference_implementation() {
+        let expected = [
+            ("test1.png", "1fa03fe03fa03fc01fe01be03bb03bf03bf03fd03f403ec01fb00ff00ff807f0", "1fa03fe03fa03fc01ff01be03ba03bf03bf03bf03fc03f003e401ff80ff007f8", "7878785c5c783c3c"),
+            ("test2.png", "1fa03fe03fa03fc01fe01b603ba03ff83bf83ff03b603e001fb00ff00ff807f0", "1fa03fe03fa03fc01fe01a403be03ffa3bf03ff03f503e003e401ff80ff007f8", "7878707c7c703c3c"),
+            ("test3.png", "d8c0f9c0f9c0dfc8ef1af31af11ac3100fb8073a0f331f331f730f7307630707", "f8c0f9c0f9c0dec8e71ae71af09ae11887ba0738073b1f331f3b1f730f730103", "e8e8f5903c353535"),
+            ("test4.png", "fc00fc00fe61fee0fce0eee4f30ce005f04df04c13fc03fc01d407fc0ffc03fc", "fc00fc00fc61fef0fce0ece0fe44e205f145f04493fc03fc05f401fc07fc07fc", "e0ececc2ca1e1e1e"),
+        ];
+        for (name, precise_16, quick_16, precise_8) in expected {
+            let img = decode(&Path::new("test_images").join(name), 0, 0, DecodeMode::Full).expect("decode failed");
+            assert_eq!(block_hash(&img, &BlockhashOptions { bits: 16, precise: true }).to_hex(), precise_16, "{name}");
+            assert_eq!(block_hash(&img, &BlockhashOptions { bits: 16, precise: false }).to_hex(), quick_16, "{name}");
+            assert_eq!(block_hash(&img, &BlockhashOptions { bits: 8, precise: true }).to_hex(), precise_8, "{name}");
+        }
+    }
+}
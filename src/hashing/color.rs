@@ -0,0 +1,259 @@
+use std::f64::consts::TAU;
+use image::{DynamicImage, GrayImage, RgbImage, imageops};
+use super::hash::BitHash;
+
+// the moments are statistics over all pixels, a small image describes them well enough
+pub const IMG_SIZE: u32 = 128;
+const CHANNELS: usize = 6;
+const MOMENTS: usize = 3;
+// position of the mean hue among the values, whose difference wraps around
+const HUE_MEAN: usize = 0;
+/// Upper bound of `ColorMoments::distance`. Of the five linear channels the means differ by at most 1, the
+/// standard deviations by 0.5 and the skewnesses, within +-cbrt(sqrt(3) / 18), by 0.917; of the hue the mean
+/// differs by at most 0.5 around the circle and the variance and skewness by 1: sqrt(5 * 2.09 + 2.25) * 100.
+pub const MAX_DISTANCE: u32 = 357;
+
+/// Which colour information the dHash and pHash algorithms look at.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColorMode {
+    /// Hash the luminance only, colour changes go unnoticed.
+    #[default]
+    Grayscale,
+    /// Hash the red, green and blue channels separately and concatenate the hashes.
+    PerChannel,
+}
+
+impl ColorMode {
+    pub fn next(self) -> Self {
+        match self {
+            ColorMode::Grayscale => ColorMode::PerChannel,
+            ColorMode::PerChannel => ColorMode::Grayscale,
+        }
+    }
+}
+
+impl std::fmt::Display for ColorMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ColorMode::Grayscale => write!(f, "grayscale"),
+            ColorMode::PerChannel => write!(f, "per channel"),
+        }
+    }
+}
+
+/// Hashes every RGB channel of `img` with `hash`; the red channel's hash comes first.
+pub fn per_channel(img: &DynamicImage, hash: fn(&DynamicImage) -> u64) -> BitHash {
+    let rgb = img.to_rgb8();
+    BitHash::from_bits((0..3).flat_map(|channel| {
+        let plane = GrayImage::from_fn(rgb.width(), rgb.height(), |x, y| image::Luma([rgb.get_pixel(x, y)[channel]]));
+        BitHash::from_u64(hash(&DynamicImage::ImageLuma8(plane))).bits().collect::<Vec<_>>()
+    }))
+}
+
+/// Mean, spread and skewness of the H, S, V, Y, Cb and Cr channels in the unit of the channel, 0-1;
+/// those of the hue are circular statistics, see `circular_moments`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColorMoments {
+    values: Vec<f64>,
+}
+
+impl ColorMoments {
+    pub fn values(&self) -> &[f64] {
+        &self.values
+    }
+
+    pub fn from_values(values: Vec<f64>) -> Self {
+        ColorMoments { values }
+    }
+
+    /// Euclidean distance between the moment vectors, times 100; mean hues are compared around the circle.
+    pub fn distance(&self, other: &ColorMoments) -> u32 {
+        let sum: f64 = self.values.iter().zip(&other.values).enumerate().map(|(i, (a, b))| {
+            let difference = (a - b).abs();
+            if i == HUE_MEAN { difference.min(1.0 - difference) } else { difference }
+        }.powi(2)).sum();
+        // values read from hex strings may lie outside the ranges of computed ones
+        ((sum.sqrt() * 100.0).round() as u32).min(MAX_DISTANCE)
+    }
+
+    pub fn to_hex(&self) -> String {
+        // each moment as a 16-bit fixed point number
+        self.values
+            .iter()
+            .map(|v| format!("{:04x}", (v.clamp(-1.0, 1.0) * i16::MAX as f64).round() as i16 as u16))
+            .collect()
+    }
+
+    /// Inverse of `to_hex`, up to the precision of the fixed point numbers.
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        if hex.len() != CHANNELS * MOMENTS * 4 || !hex.is_ascii() {
+            return None;
+        }
+        let values = (0..hex.len())
+            .step_by(4)
+            .map(|i| u16::from_str_radix(&hex[i..i + 4], 16).ok().map(|v| v as i16 as f64 / i16::MAX as f64))
+            .collect::<Option<Vec<f64>>>()?;
+        Some(ColorMoments { values })
+    }
+}
+
+/// Colour moment hash after Stricker and Orengo, computed in the HSV and YCbCr spaces.
+pub fn color_moment_hash(img: &DynamicImage) -> ColorMoments {
+    let rgb: RgbImage = imageops::resize(&img.to_rgb8(), IMG_SIZE, IMG_SIZE, imageops::Triangle);
+
+    let mut channels: Vec<Vec<f64>> = (0..CHANNELS).map(|_| Vec::with_capacity((IMG_SIZE * IMG_SIZE) as usize)).collect();
+    for pixel in rgb.pixels() {
+        let [r, g, b] = pixel.0.map(|v| v as f64 / 255.0);
+        let (h, s, v) = hsv(r, g, b);
+        let (y, cb, cr) = ycbcr(r, g, b);
+        for (channel, value) in channels.iter_mut().zip([h, s, v, y, cb, cr]) {
+            channel.push(value);
+        }
+    }
+
+    let mut values = Vec::with_capacity(CHANNELS * MOMENTS);
+    values.extend(circular_moments(&channels[0]));
+    for channel in &channels[1..] {
+        values.extend(moments(channel));
+    }
+    ColorMoments { values }
+}
+
+// hue as a fraction of the full circle
+fn hsv(r: f64, g: f64, b: f64) -> (f64, f64, f64) {
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+    let hue = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        (b - r) / delta + 2.0
+    } else {
+        (r - g) / delta + 4.0
+    };
+    let saturation = if max == 0.0 { 0.0 } else { delta / max };
+    (hue / 6.0, saturation, max)
+}
+
+// full range (JPEG) YCbCr
+fn ycbcr(r: f64, g: f64, b: f64) -> (f64, f64, f64) {
+    let y = 0.299 * r + 0.587 * g + 0.114 * b;
+    let cb = 0.5 - 0.168736 * r - 0.331264 * g + 0.5 * b;
+    let cr = 0.5 + 0.5 * r - 0.418688 * g - 0.081312 * b;
+    (y, cb, cr)
+}
+
+// mean, standard deviation and cube root of the third central moment, all in the unit of the values
+fn moments(values: &[f64]) -> [f64; MOMENTS] {
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n;
+    let third = values.iter().map(|v| (v - mean).powi(3)).sum::<f64>() / n;
+    [mean, variance.sqrt(), third.cbrt()]
+}
+
+// hues of 0.99 and 0.01 are close, so their mean is taken of the angles: the direction of the mean of the
+// unit vectors as a fraction of the circle, the circular variance (1 - their length) and Pewsey's skewness
+// mean(sin(2 * (angle - mean angle))), halved into -0.5..0.5
+fn circular_moments(hues: &[f64]) -> [f64; MOMENTS] {
+    let n = hues.len() as f64;
+    let (sin, cos) = hues.iter().fold((0.0, 0.0), |(sin, cos), h| (sin + (h * TAU).sin(), cos + (h * TAU).cos()));
+    let (sin, cos) = (sin / n, cos / n);
+    let mean = sin.atan2(cos);
+    let skewness = hues.iter().map(|h| (2.0 * (h * TAU - mean)).sin()).sum::<f64>() / n;
+    [(mean / TAU).rem_euclid(1.0), 1.0 - sin.hypot(cos), skewness / 2.0]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use image::{Rgb, imageops};
+    use crate::hashing::d_hash_image;
+    use crate::preprocessing::preprocessor::{PreprocessOptions, load};
+    use super::*;
+
+    fn load_image() -> DynamicImage {
+        load(Path::new("test_images/test1.png"), IMG_SIZE, &PreprocessOptions::default()).expect("load failed")
+    }
+
+    // the same picture with the red and blue channels exchanged
+    fn swap_red_blue(img: &DynamicImage) -> DynamicImage {
+        let mut rgb = img.to_rgb8();
+        rgb.pixels_mut().for_each(|p| *p = Rgb([p[2], p[1], p[0]]));
+        DynamicImage::ImageRgb8(rgb)
+    }
+
+    #[test]
+    fn moments_of_a_uniform_colour() {
+        let red = DynamicImage::ImageRgb8(RgbImage::from_pixel(10, 10, Rgb([255, 0, 0])));
+        let moments = color_moment_hash(&red);
+        assert_eq!(moments.values().len(), 18);
+        // hue 0, saturation 1, value 1, then Y, Cb and Cr of pure red
+        let means: Vec<f64> = moments.values().iter().step_by(3).copied().collect();
+        let expected = [0.0, 1.0, 1.0, 0.299, 0.331264, 1.0];
+        for (mean, expected) in means.iter().zip(expected) {
+            assert!((mean - expected).abs() < 1e-6, "{means:?}");
+        }
+        assert!(moments.values().iter().skip(1).step_by(3).all(|&deviation| deviation.abs() < 1e-6));
+    }
+
+    #[test]
+    fn hue_wraps_around() {
+        let uniform = |rgb| color_moment_hash(&DynamicImage::ImageRgb8(RgbImage::from_pixel(10, 10, Rgb(rgb))));
+        // hues of 0.02 and 0.98, both next to pure red; they differ in Cb and Cr a little
+        let (orange_red, pink_red) = (uniform([255, 31, 0]), uniform([255, 0, 31]));
+        let cyan = uniform([0, 255, 255]);
+        assert!(orange_red.distance(&pink_red) * 5 < orange_red.distance(&cyan), "{}", orange_red.distance(&pink_red));
+
+        // the mean of both halves is red, not cyan, and they hardly spread
+        let halves = color_moment_hash(&DynamicImage::ImageRgb8(RgbImage::from_fn(10, 10, |x, _| {
+            if x < 5 { Rgb([255, 31, 0]) } else { Rgb([255, 0, 31]) }
+        })));
+        let [mean, variance, skewness] = [0, 1, 2].map(|i| halves.values()[i]);
+        assert!(mean.min(1.0 - mean) < 1e-6, "mean hue {mean}");
+        assert!(variance < 0.01 && skewness.abs() < 1e-6, "{variance} {skewness}");
+    }
+
+    #[test]
+    fn distances_stay_below_the_maximum() {
+        let black_and_white = DynamicImage::ImageRgb8(RgbImage::from_fn(10, 10, |x, y| {
+            if x * y == 0 { Rgb([255, 255, 255]) } else { Rgb([0, 0, 0]) }
+        }));
+        let blue_and_yellow = DynamicImage::ImageRgb8(RgbImage::from_fn(10, 10, |x, y| {
+            if x * y == 0 { Rgb([0, 0, 255]) } else { Rgb([255, 255, 0]) }
+        }));
+        let distance = color_moment_hash(&black_and_white).distance(&color_moment_hash(&blue_and_yellow));
+        assert!(distance > 100 && distance < MAX_DISTANCE, "{distance}");
+
+        let extreme = ColorMoments::from_values(vec![-1.0; CHANNELS * MOMENTS]);
+        assert_eq!(extreme.distance(&ColorMoments::from_values(vec![1.0; CHANNELS * MOMENTS])), MAX_DISTANCE);
+    }
+
+    #[test]
+    fn colour_changes_are_detected_by_moments_but_not_by_resizing() {
+        let img = load_image();
+        let original = color_moment_hash(&img);
+        let resized = color_moment_hash(&img.resize_exact(img.width() / 2, img.height() / 2, imageops::Lanczos3));
+        let recoloured = color_moment_hash(&swap_red_blue(&img));
+
+        assert!(original.distance(&resized) < 5, "resized at {}", original.distance(&resized));
+        assert!(original.distance(&recoloured) > 5 * original.distance(&resized).max(1));
+        assert_eq!(original.to_hex().len(), 18 * 4);
+    }
+
+    #[test]
+    fn per_channel_hashes_follow_the_channels() {
+        let img = load_image();
+        let original = per_channel(&img, d_hash_image);
+        let swapped = per_channel(&swap_red_blue(&img), d_hash_image);
+        assert_eq!(original.len(), 192);
+
+        let bits: Vec<bool> = original.bits().collect();
+        let (red, green, blue) = (&bits[..64], &bits[64..128], &bits[128..]);
+        let expected = BitHash::from_bits(blue.iter().chain(green).chain(red).copied());
+        assert_eq!(swapped, expected);
+        assert!(original.hamming(&swapped) > 0);
+    }
+}
@@ -1,14 +1,18 @@
-use crate::preprocessing::preprocessor::preprocess;
+use crate::preprocessing::preprocessor::{PreprocessOptions, load, preprocess_image};
 use std::path::Path;
-use image::{GrayImage, ImageError};
+use image::{DynamicImage, GrayImage, ImageError};
 
 // to produce 64 bit hash
 const IMG_HEIGHT: usize = 8;
-const IMG_WIDTH: usize = 9;
+pub const IMG_WIDTH: usize = 9;
 
 pub fn d_hash(path: &Path) -> Result<u64, ImageError> {
-    let img = preprocess(path, IMG_HEIGHT as u32, IMG_WIDTH as u32)?;
-    Ok(hash(&img))
+    let img = load(path, IMG_WIDTH as u32, &PreprocessOptions::default())?;
+    Ok(d_hash_image(&img))
+}
+
+pub fn d_hash_image(img: &DynamicImage) -> u64 {
+    hash(&preprocess_image(img, IMG_HEIGHT as u32, IMG_WIDTH as u32))
 }
 
 fn hash(img: &GrayImage) -> u64 {
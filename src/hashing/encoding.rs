@@ -0,0 +1,128 @@
+// Bit layouts of the 64-bit hashes in Python's imagehash (hash_size=8): `str(hash)` is the
+// boolean matrix flattened row by row, the first element being the most significant bit.
+const BITS: u32 = 64;
+const SIDE: u32 = 8;
+
+/// Convention a hash string follows.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Encoding {
+    /// `Hash::to_hex` of this crate; blockhash and PDQ strings match their reference implementations.
+    #[default]
+    Native,
+    /// `str(hash)` of Python's `imagehash` for its `dhash` and `phash` with the default `hash_size=8`.
+    /// Converting hashes of this crate to and from it is approximate; `hashing::imagehash` computes
+    /// the exact strings from an image.
+    Imagehash,
+}
+
+impl Encoding {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "native" => Some(Encoding::Native),
+            "imagehash" => Some(Encoding::Imagehash),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Encoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Encoding::Native => write!(f, "native"),
+            Encoding::Imagehash => write!(f, "imagehash"),
+        }
+    }
+}
+
+/// dHash in the layout of `imagehash.dhash`. Both compare horizontal neighbours row by row, most
+/// significant bit first, but imagehash sets a bit where the right pixel is brighter and this crate
+/// where the left one is. Neighbours of equal brightness are 0 in both, which the inversion turns into
+/// 1, and the pixels are resized differently, so the result is approximate: a few bits off the string
+/// imagehash prints, which `hashing::imagehash::d_hash` computes.
+pub fn d_hash_to_imagehash(hash: u64) -> u64 {
+    !hash
+}
+
+/// Approximate inverse of `d_hash_to_imagehash`; neighbours of equal brightness come out as 1.
+pub fn d_hash_from_imagehash(hash: u64) -> u64 {
+    !hash
+}
+
+/// pHash in the layout of `imagehash.phash`. This crate sets bit `8u + v` (least significant first) for
+/// the coefficient of horizontal frequency `u` and vertical frequency `v`, imagehash sets bit `8v + u`
+/// counted from the most significant one. Coefficients equal to the median are 1 here and 0 there, and
+/// the pixels are resized differently, so like for dHash the result is approximate.
+pub fn p_hash_to_imagehash(hash: u64) -> u64 {
+    (0..BITS)
+        .filter(|i| hash >> i & 1 == 1)
+        .map(|i| 1 << (BITS - 1 - transpose(i)))
+        .fold(0, |acc, bit| acc | bit)
+}
+
+pub fn p_hash_from_imagehash(hash: u64) -> u64 {
+    (0..BITS)
+        .filter(|i| hash >> (BITS - 1 - i) & 1 == 1)
+        .map(|i| 1 << transpose(i))
+        .fold(0, |acc, bit| acc | bit)
+}
+
+// index of the same coefficient with rows and columns swapped
+fn transpose(i: u32) -> u32 {
+    i % SIDE * SIDE + i / SIDE
+}
+
+/// 16 hex digits, as imagehash prints 64-bit hashes.
+pub fn imagehash_hex(hash: u64) -> String {
+    format!("{hash:016x}")
+}
+
+pub fn parse_imagehash_hex(hex: &str) -> Option<u64> {
+    if hex.len() != (BITS / 4) as usize {
+        return None;
+    }
+    u64::from_str_radix(hex, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, GrayImage, Luma};
+    use crate::hashing::{d_hash_image, p_hash_image};
+    use super::*;
+
+    // expected strings come from imagehash's formulas applied to images of its input size,
+    // so that neither library resizes them
+    fn gray(width: u32, height: u32, pixel: fn(u32, u32) -> u32) -> DynamicImage {
+        DynamicImage::ImageLuma8(GrayImage::from_fn(width, height, |x, y| Luma([pixel(x, y) as u8])))
+    }
+
+    #[test]
+    fn d_hashes_match_imagehash() {
+        let img = gray(9, 8, |x, y| (x * 53 + y * 29 + (x * y) % 7 * 11) % 256);
+        let hash = d_hash_image(&img);
+        assert_eq!(imagehash_hex(d_hash_to_imagehash(hash)), "f7edcdbedebd7d7b");
+        assert_eq!(parse_imagehash_hex("f7edcdbedebd7d7b").map(d_hash_from_imagehash), Some(hash));
+
+        // brighter to the right everywhere
+        let gradient = gray(9, 8, |x, _| x * 20);
+        assert_eq!(imagehash_hex(d_hash_to_imagehash(d_hash_image(&gradient))), "ffffffffffffffff");
+    }
+
+    #[test]
+    fn p_hashes_match_imagehash() {
+        let img = gray(32, 32, |x, y| (x * 37 + y * 91 + (x * y) % 17 * 5) % 256);
+        let hash = p_hash_image(&img);
+        assert_eq!(imagehash_hex(p_hash_to_imagehash(hash)), "ad91c392346f49f4");
+        assert_eq!(parse_imagehash_hex("AD91C392346F49F4").map(p_hash_from_imagehash), Some(hash));
+    }
+
+    #[test]
+    fn layouts_are_inverse() {
+        // the DC coefficient and the lowest horizontal frequency
+        assert_eq!(p_hash_to_imagehash(0b1_0000_0001), 0xc000_0000_0000_0000);
+        for hash in [0, 1, 0x0123_4567_89ab_cdef, u64::MAX] {
+            assert_eq!(p_hash_from_imagehash(p_hash_to_imagehash(hash)), hash);
+            assert_eq!(d_hash_from_imagehash(d_hash_to_imagehash(hash)), hash);
+        }
+        assert_eq!(parse_imagehash_hex("abc"), None);
+    }
+}
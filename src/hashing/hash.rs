@@ -0,0 +1,197 @@
+use super::color::ColorMoments;
+use super::radial_hash::RadialDigest;
+use super::segments::SegmentHashes;
+
+/// A perceptual hash of arbitrary length, stored as bits with the first bit
+/// being the most significant one (the order used by hex representations).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BitHash {
+    bytes: Vec<u8>,
+    len: usize,
+}
+
+impl BitHash {
+    pub fn from_bits<I: IntoIterator<Item = bool>>(bits: I) -> Self {
+        let mut bytes = Vec::new();
+        let mut len = 0;
+        for bit in bits {
+            if len % 8 == 0 {
+                bytes.push(0);
+            }
+            if bit {
+                bytes[len / 8] |= 0x80 >> (len % 8);
+            }
+            len += 1;
+        }
+        BitHash { bytes, len }
+    }
+
+    // bit 63 of the value becomes the first bit of the hash
+    pub fn from_u64(value: u64) -> Self {
+        BitHash { bytes: value.to_be_bytes().to_vec(), len: 64 }
+    }
+
+    pub fn to_u64(&self) -> Option<u64> {
+        let bytes: [u8; 8] = self.bytes.as_slice().try_into().ok()?;
+        Some(u64::from_be_bytes(bytes))
+    }
+
+    /// The bits packed into bytes, first bit first; the last byte is padded with zeros.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Inverse of `as_bytes`; `None` when `bytes` does not hold exactly `len` bits.
+    pub fn from_bytes(bytes: Vec<u8>, len: usize) -> Option<Self> {
+        (bytes.len() == len.div_ceil(8)).then_some(BitHash { bytes, len })
+    }
+
+    // number of bits
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn bit(&self, i: usize) -> bool {
+        assert!(i < self.len, "Bit index {i} out of range for a {}-bit hash", self.len);
+        self.bytes[i / 8] & (0x80 >> (i % 8)) != 0
+    }
+
+    pub fn bits(&self) -> impl Iterator<Item = bool> + '_ {
+        (0..self.len).map(|i| self.bit(i))
+    }
+
+    pub fn hamming(&self, other: &BitHash) -> u32 {
+        assert_eq!(self.len, other.len, "Cannot compare hashes of different lengths");
+        self.bytes
+            .iter()
+            .zip(&other.bytes)
+            .map(|(a, b)| (a ^ b).count_ones())
+            .sum()
+    }
+
+    /// Hex string with the first bit as the most significant one, left-padded to `ceil(len / 4)` digits.
+    pub fn to_hex(&self) -> String {
+        let digits = self.len.div_ceil(4);
+        // right-align the bits, so that a hash whose length is not a multiple of 4 is padded at the front
+        let padding = digits * 4 - self.len;
+        let aligned: Vec<bool> = std::iter::repeat_n(false, padding).chain(self.bits()).collect();
+        aligned
+            .chunks(4)
+            .map(|nibble| {
+                let value = nibble.iter().fold(0u32, |acc, &bit| (acc << 1) | bit as u32);
+                char::from_digit(value, 16).unwrap()
+            })
+            .collect()
+    }
+
+    /// Inverse of `to_hex`; `len` is the number of bits the hash is expected to have.
+    pub fn from_hex(hex: &str, len: usize) -> Option<Self> {
+        if hex.len() != len.div_ceil(4) {
+            return None;
+        }
+        let mut bits = Vec::with_capacity(hex.len() * 4);
+        for c in hex.chars() {
+            let value = c.to_digit(16)?;
+            bits.extend((0..4).rev().map(|shift| value >> shift & 1 == 1));
+        }
+        let padding = bits.len() - len;
+        if bits[..padding].iter().any(|&bit| bit) {
+            return None;
+        }
+        Some(BitHash::from_bits(bits.into_iter().skip(padding)))
+    }
+}
+
+impl std::fmt::Display for BitHash {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_hex())
+    }
+}
+
+/// Output of any of the hashing algorithms; each kind brings its own distance function.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Hash {
+    /// Compared by Hamming distance.
+    Bits(BitHash),
+    /// Compared by peak cross-correlation.
+    Radial(RadialDigest),
+    /// Compared by Euclidean distance.
+    Moments(ColorMoments),
+    /// Compared by the number of segments without a close counterpart.
+    Segments(SegmentHashes),
+}
+
+impl Hash {
+    /// Distance to `other`, lower meaning more similar; `None` when the hashes
+    /// are of different kinds or were computed with different parameters.
+    pub fn distance(&self, other: &Hash) -> Option<u32> {
+        match (self, other) {
+            (Hash::Bits(a), Hash::Bits(b)) if a.len() == b.len() => Some(a.hamming(b)),
+            (Hash::Radial(a), Hash::Radial(b)) if a.coeffs().len() == b.coeffs().len() => Some(a.distance(b)),
+            (Hash::Moments(a), Hash::Moments(b)) if a.values().len() == b.values().len() => Some(a.distance(b)),
+            // the number of segments follows the aspect ratio
+            (Hash::Segments(a), Hash::Segments(b)) => Some(a.distance(b)),
+            _ => None,
+        }
+    }
+
+    pub fn to_hex(&self) -> String {
+        match self {
+            Hash::Bits(hash) => hash.to_hex(),
+            Hash::Radial(digest) => digest.to_hex(),
+            Hash::Moments(moments) => moments.to_hex(),
+            Hash::Segments(segments) => segments.to_hex(),
+        }
+    }
+}
+
+impl std::fmt::Display for Hash {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_hex())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn u64_and_hex_round_trip() {
+        let hash = BitHash::from_u64(0x8000_0000_0000_00ff);
+        assert!(hash.bit(0));
+        assert!(!hash.bit(1));
+        assert_eq!(hash.to_hex(), "80000000000000ff");
+        assert_eq!(hash.to_u64(), Some(0x8000_0000_0000_00ff));
+        assert_eq!(BitHash::from_hex(&hash.to_hex(), 64), Some(hash));
+    }
+
+    #[test]
+    fn odd_lengths_are_padded_at_the_front() {
+        let hash = BitHash::from_bits([true, false, true, true, false, true]);
+        assert_eq!(hash.to_hex(), "2d");
+        assert_eq!(BitHash::from_hex("2d", 6), Some(hash));
+        assert_eq!(BitHash::from_hex("ed", 6), None);
+    }
+
+    #[test]
+    fn hamming_counts_differing_bits() {
+        let a = BitHash::from_bits([true; 12]);
+        let b = BitHash::from_bits((0..12).map(|i| i % 3 == 0));
+        assert_eq!(a.hamming(&b), 8);
+        assert_eq!(a.hamming(&a), 0);
+    }
+
+    #[test]
+    fn hashes_of_different_kinds_have_no_distance() {
+        let short = Hash::Bits(BitHash::from_u64(0));
+        let long = Hash::Bits(BitHash::from_bits([false; 256]));
+        let radial = Hash::Radial(RadialDigest::from_hex(&"00".repeat(40)).unwrap());
+        assert_eq!(short.distance(&Hash::Bits(BitHash::from_u64(0b111))), Some(3));
+        assert_eq!(short.distance(&long), None);
+        assert_eq!(short.distance(&radial), None);
+    }
+}
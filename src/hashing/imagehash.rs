@@ -0,0 +1,84 @@
+// dHash and pHash the way Python's imagehash computes them through Pillow, so that the strings match
+// its `str(imagehash.dhash(img))` and `str(imagehash.phash(img))` with the default `hash_size=8`:
+// `Image.convert("L")` (Convert.c), `Image.resize(size, LANCZOS)` (Resample.c, 8 bits per channel in
+// fixed point) and imagehash's formulas. The hashes of this crate resize with the `image` crate and
+// weight the channels differently, so they are a few bits off these.
+use image::{DynamicImage, GrayImage, Luma};
+use rustdct::{Dct2, DctPlanner};
+use std::sync::Arc;
+use once_cell::sync::Lazy;
+use super::p_hash::calculate_2d_dct;
+use super::pillow::{Filter, resize};
+
+const HASH_SIZE: u32 = 8;
+const IMG_SIZE: u32 = 32;
+
+static DCT2_IMG: Lazy<Arc<dyn Dct2<f64>>> = Lazy::new(|| {
+    let mut planner = DctPlanner::<f64>::new();
+    planner.plan_dct2(IMG_SIZE as usize)
+});
+
+/// `imagehash.dhash`: bit set where the right neighbour is brighter, row by row, most significant first.
+pub fn d_hash(img: &DynamicImage) -> u64 {
+    let pixels = resize(&luma(img), HASH_SIZE + 1, HASH_SIZE, Filter::Lanczos);
+    let width = (HASH_SIZE + 1) as usize;
+    (0..HASH_SIZE as usize)
+        .flat_map(|y| (0..HASH_SIZE as usize).map(move |x| y * width + x))
+        .fold(0, |hash, i| hash << 1 | (pixels.as_raw()[i + 1] > pixels.as_raw()[i]) as u64)
+}
+
+/// `imagehash.phash`: bit set where the coefficient of the 8 x 8 lowest frequencies is above their
+/// median, row by row (vertical frequency first), most significant first.
+pub fn p_hash(img: &DynamicImage) -> u64 {
+    let pixels: Vec<f64> = resize(&luma(img), IMG_SIZE, IMG_SIZE, Filter::Lanczos).pixels().map(|p| p[0] as f64).collect();
+    // transposed: element [u * size + v] has horizontal frequency u and vertical frequency v
+    let dct = calculate_2d_dct(&pixels, IMG_SIZE as usize, &**DCT2_IMG);
+    let low: Vec<f64> = (0..HASH_SIZE as usize)
+        .flat_map(|v| (0..HASH_SIZE as usize).map(move |u| (u, v)))
+        .map(|(u, v)| dct[u * IMG_SIZE as usize + v])
+        .collect();
+    let mut sorted = low.clone();
+    sorted.sort_by(f64::total_cmp);
+    let mid = sorted.len() / 2;
+    let median = (sorted[mid - 1] + sorted[mid]) / 2.0;
+    low.iter().fold(0, |hash, &coeff| hash << 1 | (coeff > median) as u64)
+}
+
+// Pillow's "L": ITU-R 601-2 luma in 16-bit fixed point, alpha dropped
+fn luma(img: &DynamicImage) -> GrayImage {
+    match img {
+        DynamicImage::ImageLuma8(gray) => gray.clone(),
+        DynamicImage::ImageLumaA8(gray) => GrayImage::from_fn(gray.width(), gray.height(), |x, y| Luma([gray.get_pixel(x, y)[0]])),
+        DynamicImage::ImageLuma16(_) | DynamicImage::ImageLumaA16(_) => img.to_luma8(),
+        _ => {
+            let rgb = img.to_rgb8();
+            GrayImage::from_fn(rgb.width(), rgb.height(), |x, y| {
+                let [r, g, b] = rgb.get_pixel(x, y).0;
+                Luma([((r as u32 * 19595 + g as u32 * 38470 + b as u32 * 7471 + 0x8000) >> 16) as u8])
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use super::*;
+
+    // str(imagehash.dhash(img)) and str(imagehash.phash(img)) of Python's imagehash
+    const VECTORS: [(&str, u64, u64); 4] = [
+        ("test1.png", 0xc0d0c09090c0c8f0, 0xc16187631ec7689f),
+        ("test2.png", 0xc0d0c09490c0c8f0, 0xc1618fe31c87689f),
+        ("test3.png", 0x535224346deded69, 0xdef9490259f22636),
+        ("test4.png", 0x0e09082232b0707c, 0xdbf307246c2c13d3),
+    ];
+
+    #[test]
+    fn hashes_match_imagehash() {
+        for (name, d, p) in VECTORS {
+            let img = image::open(Path::new("test_images").join(name)).unwrap();
+            assert_eq!(d_hash(&img), d, "dhash of {name}");
+            assert_eq!(p_hash(&img), p, "phash of {name}");
+        }
+    }
+}
@@ -0,0 +1,214 @@
+use image::{DynamicImage, GrayImage, imageops};
+use super::hash::BitHash;
+use super::radial_hash::luma;
+
+// the image is normalized to IMG_SIZE x IMG_SIZE, of which BLOCKS x BLOCKS disjoint blocks of
+// BLOCK_SIZE x BLOCK_SIZE pixels are summed; every 3x3 group of blocks, 4 blocks apart, contributes 9 bits
+pub const IMG_SIZE: u32 = 512;
+const BLOCK_SIZE: usize = 16;
+const BLOCKS: usize = 31;
+const GROUP_STEP: usize = 4;
+const GROUP_SIZE: usize = 3;
+/// 64 groups of 9 blocks.
+pub const BITS: u32 = 576;
+
+/// Parameters of the Marr–Hildreth (Laplacian of Gaussian) hash of pHash.org.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MarrHildrethOptions {
+    /// Scale step of the Gaussian; the kernel has a radius of `4 * alpha^level` pixels.
+    pub alpha: f32,
+    /// Scale level, higher levels respond to coarser edges.
+    pub level: f32,
+    /// Standard deviation of the Gaussian blur before the image is normalized, against noise and JPEG artifacts.
+    pub sigma: f32,
+}
+
+impl Default for MarrHildrethOptions {
+    fn default() -> Self {
+        // defaults of ph_mh_imagehash
+        MarrHildrethOptions { alpha: 2.0, level: 1.0, sigma: 1.0 }
+    }
+}
+
+/// 576-bit Marr–Hildreth hash, following `ph_mh_imagehash` of pHash.org.
+pub fn marr_hildreth_hash(img: &DynamicImage, options: &MarrHildrethOptions) -> BitHash {
+    let img = imageops::blur(&luma(img), options.sigma);
+    let img = imageops::resize(&img, IMG_SIZE, IMG_SIZE, imageops::CatmullRom);
+    let img = equalize(&img);
+
+    let response = normalize(&correlate(&img, &kernel(options)));
+    hash(&block_sums(&response))
+}
+
+// histogram equalization over the range of values present in the image
+fn equalize(img: &GrayImage) -> GrayImage {
+    let (min, max) = img.pixels().fold((u8::MAX, u8::MIN), |(min, max), p| (min.min(p[0]), max.max(p[0])));
+    if min == max {
+        return img.clone();
+    }
+    let range = (max - min) as f64;
+    let level = |v: u8| ((v - min) as f64 * 255.0 / range) as usize;
+
+    let mut cumulative = [0u64; 256];
+    img.pixels().for_each(|p| cumulative[level(p[0])] += 1);
+    for i in 1..cumulative.len() {
+        cumulative[i] += cumulative[i - 1];
+    }
+    let total = cumulative[255] as f64;
+
+    GrayImage::from_fn(img.width(), img.height(), |x, y| {
+        let v = img.get_pixel(x, y)[0];
+        image::Luma([(min as f64 + range * cumulative[level(v)] as f64 / total) as u8])
+    })
+}
+
+// Laplacian of Gaussian, sampled on a square of (2 * radius + 1) pixels; row-major
+fn kernel(options: &MarrHildrethOptions) -> Vec<Vec<f64>> {
+    let scale = (options.alpha as f64).powf(options.level as f64);
+    let radius = (4.0 * scale) as i64;
+    (-radius..=radius)
+        .map(|y| {
+            (-radius..=radius)
+                .map(|x| {
+                    let a = (x * x + y * y) as f64 / (scale * scale);
+                    (2.0 - a) * (-a / 2.0).exp()
+                })
+                .collect()
+        })
+        .collect()
+}
+
+// kernel centred on every pixel, repeating the border pixels outside the image
+fn correlate(img: &GrayImage, kernel: &[Vec<f64>]) -> Vec<f64> {
+    let (width, height) = (img.width() as usize, img.height() as usize);
+    let radius = kernel.len() / 2;
+    let padded_width = width + 2 * radius;
+    let padded: Vec<f64> = (0..height + 2 * radius)
+        .flat_map(|y| {
+            let sy = y.saturating_sub(radius).min(height - 1) as u32;
+            (0..padded_width).map(move |x| {
+                let sx = x.saturating_sub(radius).min(width - 1) as u32;
+                img.get_pixel(sx, sy)[0] as f64
+            })
+        })
+        .collect();
+
+    let mut response = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = 0.0;
+            for (ky, row) in kernel.iter().enumerate() {
+                let start = (y + ky) * padded_width + x;
+                sum += row.iter().zip(&padded[start..start + row.len()]).map(|(w, v)| w * v).sum::<f64>();
+            }
+            response.push(sum);
+        }
+    }
+    response
+}
+
+// stretch to the 0-1 range
+fn normalize(values: &[f64]) -> Vec<f64> {
+    let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    if max <= min {
+        return vec![0.0; values.len()];
+    }
+    values.iter().map(|v| (v - min) / (max - min)).collect()
+}
+
+// sums of the BLOCKS x BLOCKS blocks; row-major
+fn block_sums(response: &[f64]) -> Vec<f64> {
+    let size = IMG_SIZE as usize;
+    let mut blocks = vec![0.0; BLOCKS * BLOCKS];
+    for (i, block) in blocks.iter_mut().enumerate() {
+        let (top, left) = ((i / BLOCKS) * BLOCK_SIZE, (i % BLOCKS) * BLOCK_SIZE);
+        for y in top..top + BLOCK_SIZE {
+            *block += response[y * size + left..y * size + left + BLOCK_SIZE].iter().sum::<f64>();
+        }
+    }
+    blocks
+}
+
+// each block of a 3x3 group is compared against the mean of the group
+fn hash(blocks: &[f64]) -> BitHash {
+    let starts: Vec<usize> = (0..BLOCKS - 2).step_by(GROUP_STEP).collect();
+    let mut bits = Vec::with_capacity(starts.len() * starts.len() * GROUP_SIZE * GROUP_SIZE);
+    for &top in &starts {
+        for &left in &starts {
+            let group: Vec<f64> = (top..top + GROUP_SIZE)
+                .flat_map(|y| (left..left + GROUP_SIZE).map(move |x| blocks[y * BLOCKS + x]))
+                .collect();
+            let mean = group.iter().sum::<f64>() / group.len() as f64;
+            bits.extend(group.iter().map(|&v| v > mean));
+        }
+    }
+    BitHash::from_bits(bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use image::{Luma, imageops};
+    use crate::preprocessing::preprocessor::{PreprocessOptions, load};
+    use super::*;
+
+    #[test]
+    fn kernel_matches_the_reference() {
+        // values of GetMHKernel(2.0, 1.0): 17 x 17, (2 - A) * exp(-A / 2) with A = ((X - 8)^2 + (Y - 8)^2) / 4
+        let kernel = kernel(&MarrHildrethOptions::default());
+        assert_eq!(kernel.len(), 17);
+        assert_eq!(kernel[8][8], 2.0);
+        assert!((kernel[8][10] - (1.0f64 * (-0.5f64).exp())).abs() < 1e-12);
+        assert!((kernel[0][0] - (2.0 - 32.0) * (-16.0f64).exp()).abs() < 1e-12);
+        assert_eq!(kernel[3][5], kernel[5][3]);
+
+        let coarse = super::kernel(&MarrHildrethOptions { level: 2.0, ..Default::default() });
+        assert_eq!(coarse.len(), 33);
+    }
+
+    #[test]
+    fn regression_snapshot_after_the_preprocessing() {
+        // a snapshot of the steps that follow the blur, resize and equalization, from a Python port of those
+        // steps of ph_mh_imagehash; it is not a hash of pHash.org, whose CImg preprocessing differs slightly
+        let img = GrayImage::from_fn(IMG_SIZE, IMG_SIZE, |x, y| {
+            let (x, y) = (x as i64, y as i64);
+            Luma([((((x - 256).pow(2) + (y - 200).pow(2)) / 300 + x * y % 11) % 256) as u8])
+        });
+        let response = normalize(&correlate(&img, &kernel(&MarrHildrethOptions::default())));
+        assert_eq!(
+            hash(&block_sums(&response)).to_hex(),
+            "fac03a1e8f4783a0ee4a2e870391e0e1744092249766db68dd249a9334d8f45a2f1d0cc3d324f8f45a2f1d24c34ea49b16db66e9244a9a44470381c0e01c5f122e8ef503c63d7cba"
+        );
+    }
+
+    #[test]
+    fn equalize_spreads_the_histogram() {
+        // two values only: the lower half maps to the middle, the upper to the top of the range
+        let img = GrayImage::from_fn(4, 4, |x, _| Luma([if x < 2 { 100 } else { 120 }]));
+        let equalized = equalize(&img);
+        assert_eq!(equalized.get_pixel(0, 0)[0], 110);
+        assert_eq!(equalized.get_pixel(3, 0)[0], 120);
+    }
+
+    #[test]
+    fn hash_has_576_bits_and_tolerates_resizing() {
+        let img = load(Path::new("test_images/test1.png"), IMG_SIZE, &PreprocessOptions::default()).expect("load failed");
+        let options = MarrHildrethOptions::default();
+        let original = marr_hildreth_hash(&img, &options);
+        assert_eq!(original.len(), 576);
+        assert_eq!(original.to_hex().len(), 144);
+
+        let resized = img.resize_exact(img.width() * 3 / 4, img.height() * 3 / 4, imageops::Lanczos3);
+        let resized_distance = marr_hildreth_hash(&resized, &options).hamming(&original);
+
+        let other = load(Path::new("test_images/test3.png"), IMG_SIZE, &PreprocessOptions::default()).expect("load failed");
+        let other_distance = marr_hildreth_hash(&other, &options).hamming(&original);
+
+        assert!(resized_distance < 576 / 8, "resized image differs in {resized_distance} bits");
+        assert!(other_distance > resized_distance * 2, "different image differs in only {other_distance} bits");
+
+        let sharper = marr_hildreth_hash(&img, &MarrHildrethOptions { sigma: 0.5, ..options });
+        assert_ne!(sharper, original);
+    }
+}
@@ -1,5 +1,25 @@
 pub mod p_hash;
 pub mod d_hash;
+pub mod block_hash;
+pub mod pdq_hash;
+pub mod radial_hash;
+pub mod marr_hildreth;
+pub mod color;
+pub mod segments;
+pub mod hash;
+pub mod options;
+pub mod encoding;
+pub mod imagehash;
+mod pillow;
 
-pub use p_hash::p_hash;
-pub use d_hash::d_hash;
\ No newline at end of file
+pub use p_hash::{p_hash, p_hash_image};
+pub use d_hash::{d_hash, d_hash_image};
+pub use block_hash::block_hash;
+pub use pdq_hash::pdq_hash;
+pub use radial_hash::radial_hash;
+pub use marr_hildreth::marr_hildreth_hash;
+pub use color::color_moment_hash;
+pub use segments::segment_hash;
+pub use hash::{BitHash, Hash};
+pub use options::HashingOptions;
+pub use encoding::Encoding;
@@ -0,0 +1,69 @@
+use super::block_hash::BlockhashOptions;
+use super::marr_hildreth::MarrHildrethOptions;
+use super::color::ColorMode;
+use super::segments::{self, SegmentOptions};
+
+// bounds that keep hashes and kernels of a sensible size: a 256 x 256 bit blockhash, a Marr-Hildreth kernel radius of 64 pixels
+const MAX_BLOCKHASH_BITS: u32 = 256;
+const MAX_MARR_HILDRETH_SCALE: f32 = 16.0;
+
+/// Parameters of the hashing algorithms that have any.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct HashingOptions {
+    pub blockhash: BlockhashOptions,
+    pub marr_hildreth: MarrHildrethOptions,
+    /// Applies to dHash and pHash.
+    pub color: ColorMode,
+    pub segments: SegmentOptions,
+}
+
+impl HashingOptions {
+    /// Checks the ranges the hashers rely on; the error names the setting as index files and
+    /// configuration files call it.
+    pub fn validate(&self) -> Result<(), String> {
+        let bits = self.blockhash.bits;
+        if !(4..=MAX_BLOCKHASH_BITS).contains(&bits) || !bits.is_multiple_of(4) {
+            return Err(format!("blockhash.bits={bits}: expected a multiple of 4 from 4 to {MAX_BLOCKHASH_BITS}"));
+        }
+        let MarrHildrethOptions { alpha, level, sigma } = self.marr_hildreth;
+        if !(alpha.is_finite() && alpha > 1.0) {
+            return Err(format!("marr-hildreth.alpha={alpha}: expected a number above 1"));
+        }
+        if !(level.is_finite() && level >= 0.0) {
+            return Err(format!("marr-hildreth.level={level}: expected a number of at least 0"));
+        }
+        if alpha.powf(level) > MAX_MARR_HILDRETH_SCALE {
+            return Err(format!("marr-hildreth.level={level}: expected alpha^level of at most {MAX_MARR_HILDRETH_SCALE}"));
+        }
+        if !(sigma.is_finite() && sigma > 0.0) {
+            return Err(format!("marr-hildreth.sigma={sigma}: expected a positive number"));
+        }
+        let grid = self.segments.grid;
+        if !(1..=segments::IMG_SIZE / 4).contains(&grid) {
+            return Err(format!("segments.grid={grid}: expected 1 to {} tiles", segments::IMG_SIZE / 4));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn out_of_range_settings_are_named() {
+        assert_eq!(HashingOptions::default().validate(), Ok(()));
+        let invalid = |change: fn(&mut HashingOptions)| {
+            let mut options = HashingOptions::default();
+            change(&mut options);
+            options.validate().unwrap_err()
+        };
+        assert!(invalid(|options| options.blockhash.bits = 6).starts_with("blockhash.bits=6"));
+        assert!(invalid(|options| options.marr_hildreth.alpha = 1.0).starts_with("marr-hildreth.alpha"));
+        assert!(invalid(|options| options.marr_hildreth.level = f32::NAN).starts_with("marr-hildreth.level"));
+        assert!(invalid(|options| options.marr_hildreth.level = 40.0).starts_with("marr-hildreth.level"));
+        assert!(invalid(|options| options.marr_hildreth.sigma = -1.0).starts_with("marr-hildreth.sigma"));
+        assert!(invalid(|options| options.segments.grid = 0).starts_with("segments.grid=0"));
+        assert!(invalid(|options| options.segments.grid = 100).starts_with("segments.grid=100"));
+    }
+}
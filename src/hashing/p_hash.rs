@@ -1,12 +1,12 @@
 use std::path::Path;
-use image::{GrayImage, ImageError};
+use image::{DynamicImage, GrayImage, ImageError};
 use rustdct::{DctPlanner, Dct2};
 use std::sync::Arc;
 use once_cell::sync::Lazy;
-use crate::preprocessing::preprocessor::preprocess;
+use crate::preprocessing::preprocessor::{PreprocessOptions, load, preprocess_image};
 
 const HASH_SIZE: usize = 8;
-const IMG_SIZE: u32 = 32;
+pub const IMG_SIZE: u32 = 32;
 
 static DCT2_IMG: Lazy<Arc<dyn Dct2<f64>>> = Lazy::new(|| {
     let mut planner = DctPlanner::<f64>::new();
@@ -14,8 +14,13 @@ static DCT2_IMG: Lazy<Arc<dyn Dct2<f64>>> = Lazy::new(|| {
 });
 
 pub fn p_hash(path: &Path) -> Result<u64, ImageError> {
-    let img: GrayImage = preprocess(path, IMG_SIZE, IMG_SIZE)?;
-    Ok(hash(&img))
+    let img = load(path, IMG_SIZE, &PreprocessOptions::default())?;
+    Ok(p_hash_image(&img))
+}
+
+pub fn p_hash_image(img: &DynamicImage) -> u64 {
+    let img: GrayImage = preprocess_image(img, IMG_SIZE, IMG_SIZE);
+    hash(&img)
 }
 
 fn hash(img: &GrayImage) -> u64 {
@@ -24,7 +29,7 @@ fn hash(img: &GrayImage) -> u64 {
 
     let pixels_f64: Vec<f64> = img.pixels().map(|p| p[0] as f64).collect();
 
-    let dct_coeffs = calculate_2d_dct(&pixels_f64, IMG_SIZE as usize);
+    let dct_coeffs = calculate_2d_dct(&pixels_f64, IMG_SIZE as usize, &**DCT2_IMG);
 
     let mut low_freq_coeffs = Vec::with_capacity(HASH_SIZE * HASH_SIZE);
     for r in 0..HASH_SIZE {
@@ -54,9 +59,9 @@ fn hash(img: &GrayImage) -> u64 {
     hash
 }
 
-fn calculate_2d_dct(pixels: &[f64], size: usize) -> Vec<f64> {
-    let dct = &*DCT2_IMG;
-
+// unnormalized DCT-II of a size x size matrix, the result is transposed:
+// element [u * size + v] has horizontal frequency u and vertical frequency v
+pub(crate) fn calculate_2d_dct(pixels: &[f64], size: usize, dct: &dyn Dct2<f64>) -> Vec<f64> {
     let mut buffer = pixels.to_vec();
 
     for row_chunk in buffer.chunks_mut(size) {
@@ -0,0 +1,290 @@
+use image::DynamicImage;
+use rustdct::{DctPlanner, Dct2};
+use std::sync::Arc;
+use once_cell::sync::Lazy;
+use super::hash::BitHash;
+use super::p_hash::calculate_2d_dct;
+use super::pillow;
+
+// the image is filtered and decimated to BUFFER_SIZE x BUFFER_SIZE,
+// of whose DCT the lowest HASH_SIZE x HASH_SIZE frequencies (without DC) make the 256-bit hash
+pub const BUFFER_SIZE: usize = 64;
+const HASH_SIZE: usize = 16;
+const JAROSZ_PASSES: usize = 2;
+// bigger images are downsized to fit this square first, as `Image.thumbnail` does in the reference
+const DOWNSAMPLE_SIZE: u32 = 512;
+
+// luma coefficients used by the reference implementation
+const LUMA_FROM_R: f64 = 0.299;
+const LUMA_FROM_G: f64 = 0.587;
+const LUMA_FROM_B: f64 = 0.114;
+
+/// Hashes with a quality below this are considered unreliable by the PDQ authors.
+pub const MIN_QUALITY: u8 = 50;
+
+static DCT2_BUFFER: Lazy<Arc<dyn Dct2<f64>>> = Lazy::new(|| {
+    let mut planner = DctPlanner::<f64>::new();
+    planner.plan_dct2(BUFFER_SIZE)
+});
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PdqHash {
+    /// 256 bits in the order of the reference hex representation.
+    pub hash: BitHash,
+    /// Gradient based quality between 0 (featureless) and 100.
+    pub quality: u8,
+}
+
+/// PDQ hash of Meta's ThreatExchange, like its Python implementation, which hashes a thumbnail of at
+/// most 512 pixels made by Pillow; `img` is expected to be the original.
+pub fn pdq_hash(img: &DynamicImage) -> PdqHash {
+    let rgb = img.to_rgb8();
+    let rgb = pillow::thumbnail(&rgb, DOWNSAMPLE_SIZE).unwrap_or(rgb);
+    let (num_cols, num_rows) = (rgb.width() as usize, rgb.height() as usize);
+
+    let mut luma: Vec<f64> = rgb
+        .pixels()
+        .map(|p| LUMA_FROM_R * p[0] as f64 + LUMA_FROM_G * p[1] as f64 + LUMA_FROM_B * p[2] as f64)
+        .collect();
+
+    let window_along_rows = jarosz_window_size(num_cols, BUFFER_SIZE);
+    let window_along_cols = jarosz_window_size(num_rows, BUFFER_SIZE);
+    jarosz_filter(&mut luma, num_rows, num_cols, window_along_rows, window_along_cols);
+
+    let buffer = decimate(&luma, num_rows, num_cols);
+    let quality = quality_metric(&buffer);
+
+    PdqHash { hash: hash(&buffer), quality }
+}
+
+fn jarosz_window_size(old_dimension: usize, new_dimension: usize) -> usize {
+    old_dimension.div_ceil(2 * new_dimension)
+}
+
+// repeated box filters approximate a tent filter
+fn jarosz_filter(buffer: &mut [f64], num_rows: usize, num_cols: usize, window_along_rows: usize, window_along_cols: usize) {
+    let mut temp = vec![0.0; buffer.len()];
+    for _ in 0..JAROSZ_PASSES {
+        for row in 0..num_rows {
+            box_1d(&buffer[row * num_cols..], &mut temp[row * num_cols..], num_cols, 1, window_along_rows);
+        }
+        for col in 0..num_cols {
+            box_1d(&temp[col..], &mut buffer[col..], num_rows, num_cols, window_along_cols);
+        }
+    }
+}
+
+// centred moving average over `length` elements `stride` apart, with shrinking windows at both ends
+fn box_1d(input: &[f64], output: &mut [f64], length: usize, stride: usize, window: usize) {
+    let half_window = (window + 2) / 2;
+    let phase_1 = half_window - 1;
+    let phase_2 = window - half_window + 1;
+    let phase_3 = length.saturating_sub(window);
+    let phase_4 = half_window - 1;
+
+    let (mut li, mut ri, mut oi) = (0, 0, 0);
+    let mut sum = 0.0;
+    let mut current_window = 0.0;
+
+    // initial accumulation
+    for _ in 0..phase_1 {
+        sum += input[ri];
+        current_window += 1.0;
+        ri += stride;
+    }
+    // initial writes with a growing window
+    for _ in 0..phase_2 {
+        sum += input[ri];
+        current_window += 1.0;
+        output[oi] = sum / current_window;
+        ri += stride;
+        oi += stride;
+    }
+    // writes with the full window
+    for _ in 0..phase_3 {
+        sum += input[ri];
+        sum -= input[li];
+        output[oi] = sum / current_window;
+        li += stride;
+        ri += stride;
+        oi += stride;
+    }
+    // final writes with a shrinking window
+    for _ in 0..phase_4 {
+        sum -= input[li];
+        current_window -= 1.0;
+        output[oi] = sum / current_window;
+        li += stride;
+        oi += stride;
+    }
+}
+
+fn decimate(input: &[f64], num_rows: usize, num_cols: usize) -> Vec<f64> {
+    let mut output = Vec::with_capacity(BUFFER_SIZE * BUFFER_SIZE);
+    for i in 0..BUFFER_SIZE {
+        let row = ((i as f64 + 0.5) * num_rows as f64 / BUFFER_SIZE as f64) as usize;
+        for j in 0..BUFFER_SIZE {
+            let col = ((j as f64 + 0.5) * num_cols as f64 / BUFFER_SIZE as f64) as usize;
+            output.push(input[row * num_cols + col]);
+        }
+    }
+    output
+}
+
+// sum of absolute differences between neighbours, in percent of the full range
+fn quality_metric(buffer: &[f64]) -> u8 {
+    let mut gradient_sum: i64 = 0;
+    for i in 0..BUFFER_SIZE - 1 {
+        for j in 0..BUFFER_SIZE {
+            let (u, v) = (buffer[i * BUFFER_SIZE + j], buffer[(i + 1) * BUFFER_SIZE + j]);
+            gradient_sum += (((u - v) * 100.0 / 255.0) as i64).abs();
+        }
+    }
+    for i in 0..BUFFER_SIZE {
+        for j in 0..BUFFER_SIZE - 1 {
+            let (u, v) = (buffer[i * BUFFER_SIZE + j], buffer[i * BUFFER_SIZE + j + 1]);
+            gradient_sum += (((u - v) * 100.0 / 255.0) as i64).abs();
+        }
+    }
+    (gradient_sum / 90).min(100) as u8
+}
+
+fn hash(buffer: &[f64]) -> BitHash {
+    let dct = calculate_2d_dct(buffer, BUFFER_SIZE, &**DCT2_BUFFER);
+
+    // coefficient (i, j): vertical frequency i + 1, horizontal frequency j + 1, DC is skipped
+    let coeffs: Vec<f64> = (0..HASH_SIZE)
+        .flat_map(|i| (0..HASH_SIZE).map(move |j| (i, j)))
+        .map(|(i, j)| dct[(j + 1) * BUFFER_SIZE + i + 1])
+        .collect();
+
+    // the reference takes the lower of the two middle values
+    let mut sorted = coeffs.clone();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let median = sorted[(sorted.len() - 1) / 2];
+
+    // the reference hex string starts with the last coefficient
+    BitHash::from_bits(coeffs.iter().rev().map(|&c| c > median))
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, Rgb, RgbImage, imageops};
+    use std::path::Path;
+    use crate::preprocessing::decoder::{DecodeMode, decode};
+    use crate::preprocessing::preprocessor::{PreprocessOptions, load};
+    use super::*;
+
+    // direct D * A * D^T product as written in the reference, D being the 16x64 DCT matrix without the DC row
+    fn reference_dct_16x16(buffer: &[f64]) -> Vec<f64> {
+        let n = BUFFER_SIZE as f64;
+        let d = |i: usize, j: usize| {
+            (2.0 / n).sqrt() * (std::f64::consts::PI / 2.0 / n * (i + 1) as f64 * (2 * j + 1) as f64).cos()
+        };
+        let mut out = vec![0.0; HASH_SIZE * HASH_SIZE];
+        for i in 0..HASH_SIZE {
+            for j in 0..HASH_SIZE {
+                let mut sum = 0.0;
+                for k in 0..BUFFER_SIZE {
+                    for l in 0..BUFFER_SIZE {
+                        sum += d(i, k) * buffer[k * BUFFER_SIZE + l] * d(j, l);
+                    }
+                }
+                out[i * HASH_SIZE + j] = sum;
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn rustdct_matches_the_reference_dct_matrix() {
+        let buffer: Vec<f64> = (0..BUFFER_SIZE * BUFFER_SIZE).map(|i| ((i * 37) % 251) as f64).collect();
+        let expected = reference_dct_16x16(&buffer);
+
+        let dct = calculate_2d_dct(&buffer, BUFFER_SIZE, &**DCT2_BUFFER);
+        // rustdct is unnormalized, the reference scales each pass by sqrt(2 / 64)
+        let scale = 2.0 / BUFFER_SIZE as f64;
+        for i in 0..HASH_SIZE {
+            for j in 0..HASH_SIZE {
+                let actual = dct[(j + 1) * BUFFER_SIZE + i + 1] * scale;
+                assert!((actual - expected[i * HASH_SIZE + j]).abs() < 1e-6, "coefficient ({i}, {j}) differs");
+            }
+        }
+    }
+
+    #[test]
+    fn box_filter_keeps_constant_signal() {
+        let input = vec![7.0; 20];
+        let mut output = vec![0.0; 20];
+        box_1d(&input, &mut output, 20, 1, 5);
+        assert!(output.iter().all(|&v| (v - 7.0).abs() < 1e-12));
+    }
+
+    #[test]
+    fn flat_images_have_zero_quality() {
+        let flat = DynamicImage::ImageRgb8(RgbImage::from_pixel(300, 200, Rgb([90, 120, 150])));
+        assert_eq!(pdq_hash(&flat).quality, 0);
+    }
+
+    #[test]
+    fn similar_images_have_close_hashes() {
+        let img = load(Path::new("test_images/test1.png"), BUFFER_SIZE as u32, &PreprocessOptions::default())
+            .expect("load failed");
+        let original = pdq_hash(&img);
+        assert_eq!(original.hash.len(), 256);
+        assert_eq!(original.hash.to_hex().len(), 64);
+        assert!(original.quality >= MIN_QUALITY, "photo got quality {}", original.quality);
+
+        let resized = img.resize_exact(img.width() / 2, img.height() / 2, imageops::Lanczos3);
+        assert!(pdq_hash(&resized).hash.hamming(&original.hash) < 32);
+
+        let other = load(Path::new("test_images/test3.png"), BUFFER_SIZE as u32, &PreprocessOptions::default())
+            .expect("load failed");
+        assert!(pdq_hash(&other).hash.hamming(&original.hash) > 64);
+    }
+
+    // hashes and qualities of a port of pdq_hasher.py of ThreatExchange for the test images, which are
+    // small enough for its thumbnail step to leave them alone
+    #[test]
+    fn compare_with_reference_implementation() {
+        let expected = [
+            ("test1.png", "69605b33c989e0f2e61cc383c8f01b1e2fc3c67c834be071d8bcfce3d8f063c3", 100),
+            ("test2.png", "29e15b3ecdcae0f2e21cc383dcf21f1e07c3207c830bc471d81cf8e3d8f8e3c3", 100),
+            ("test3.png", "5520e812a133758acb60cbe1f2c775ff37b1313679b2b1b7495d496042c9c4cf", 100),
+            ("test4.png", "6851f782fe9e39eb1ba41877145f8784366d736543446b9a8a9b8d9215e0386f", 100),
+        ];
+        for (name, hash, quality) in expected {
+            let img = decode(&Path::new("test_images").join(name), 0, 0, DecodeMode::Full).expect("decode failed");
+            let pdq = pdq_hash(&img);
+            assert_eq!(pdq.hash.to_hex(), hash, "{name}");
+            assert_eq!(pdq.quality, quality, "{name}");
+        }
+
+        // smooth enough for a quality in between
+        let smooth = DynamicImage::ImageRgb8(RgbImage::from_fn(300, 200, |x, y| {
+            Rgb([(x * y % 20 + x * 200 / 299) as u8, (y * 255 / 199) as u8, ((x * x + y * y) / 400 % 256) as u8])
+        }));
+        let pdq = pdq_hash(&smooth);
+        assert_eq!(pdq.hash.to_hex(), "55ad2a0296ad6952d5adaa52d4a9ab5454adaa5a56ada5562aa9555aaad5b6aa");
+        assert_eq!(pdq.quality, 27);
+    }
+
+    // the same, on a port of the Image.thumbnail((512, 512)) it starts with: one image only resized,
+    // one reduced by 2 first and one reduced by 3 with partial blocks at the edges
+    #[test]
+    fn large_images_are_downsized_like_the_reference() {
+        let expected = [
+            (1300, 700, "5a0197ff3f00f07f0fe0b415e101ceff33c0ce1fb9f06381ce3f8c7898f031c0"),
+            (2100, 1500, "c03f01ff0fff3fc0fe00f800e05fc0ff83ff0ff80fd03f803f807f007e007e00"),
+            (1700, 3100, "e1ffe0fff7e0c7e8f1fec7e0c7e0e1e88fc0c7e087808f80c3c08f008f80c7c0"),
+        ];
+        for (width, height, hash) in expected {
+            let img = DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+                Rgb([((x / 10 + y / 7) * 13 % 256) as u8, ((x * x + y * y) / 900 % 256) as u8, (((x * 3) ^ (y * 5)) % 256) as u8])
+            }));
+            let pdq = pdq_hash(&img);
+            assert_eq!(pdq.hash.to_hex(), hash, "{width}x{height}");
+            assert_eq!(pdq.quality, 100, "{width}x{height}");
+        }
+    }
+}
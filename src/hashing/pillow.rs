@@ -0,0 +1,220 @@
+// Resampling the way Pillow does it for 8-bit images, so that hashes of Python implementations built on
+// it can be matched: `Image.resize` (Resample.c, weights in fixed point), `Image.reduce` (Reduce.c) and
+// `Image.thumbnail`, which combines the two.
+use image::{GrayImage, Luma, RgbImage};
+
+// fractional bits of the filter coefficients of Pillow for 8-bit images
+const PRECISION_BITS: u32 = 32 - 8 - 2;
+// the default of `Image.thumbnail`: reduce by whole factors first while the image stays twice the target size
+const REDUCING_GAP: f64 = 2.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Filter {
+    Bicubic,
+    Lanczos,
+}
+
+impl Filter {
+    // input pixels the window reaches to either side, scaled when downsizing
+    fn support(self) -> f64 {
+        match self {
+            Filter::Bicubic => 2.0,
+            Filter::Lanczos => 3.0,
+        }
+    }
+
+    fn weight(self, x: f64) -> f64 {
+        match self {
+            // Keys' cubic with a = -0.5
+            Filter::Bicubic => {
+                const A: f64 = -0.5;
+                let x = x.abs();
+                if x < 1.0 {
+                    ((A + 2.0) * x - (A + 3.0)) * x * x + 1.0
+                } else if x < 2.0 {
+                    (((x - 5.0) * x + 8.0) * x - 4.0) * A
+                } else {
+                    0.0
+                }
+            }
+            Filter::Lanczos => {
+                if (-3.0..3.0).contains(&x) { sinc(x) * sinc(x / 3.0) } else { 0.0 }
+            }
+        }
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        return 1.0;
+    }
+    let x = x * std::f64::consts::PI;
+    x.sin() / x
+}
+
+// first input pixel and fixed point weights of every output pixel along one axis, for the input
+// pixels from `start` to `end` (fractional, as after a reduction)
+fn coefficients(in_size: u32, start: f64, end: f64, out_size: u32, filter: Filter) -> Vec<(usize, Vec<i32>)> {
+    let scale = (end - start) / out_size as f64;
+    let filter_scale = scale.max(1.0);
+    let (support, inverse_scale) = (filter.support() * filter_scale, 1.0 / filter_scale);
+    (0..out_size)
+        .map(|out| {
+            let center = start + (out as f64 + 0.5) * scale;
+            // truncated like the C casts
+            let min = ((center - support + 0.5) as i64).max(0) as usize;
+            let max = ((center + support + 0.5) as i64).min(in_size as i64) as usize;
+            let weights: Vec<f64> = (min..max).map(|x| filter.weight((x as f64 - center + 0.5) * inverse_scale)).collect();
+            let total: f64 = weights.iter().sum();
+            let fixed = weights
+                .iter()
+                .map(|&weight| if total != 0.0 { weight / total } else { weight })
+                .map(|weight| {
+                    let scaled = weight * (1u32 << PRECISION_BITS) as f64;
+                    (if weight < 0.0 { scaled - 0.5 } else { scaled + 0.5 }) as i32
+                })
+                .collect();
+            (min, fixed)
+        })
+        .collect()
+}
+
+fn clip8(sum: i64) -> u8 {
+    if sum >= 1 << PRECISION_BITS << 8 {
+        255
+    } else if sum <= 0 {
+        0
+    } else {
+        (sum >> PRECISION_BITS) as u8
+    }
+}
+
+// one pass over `at(i)`, the input pixels along the axis
+fn convolve(weights: &(usize, Vec<i32>), at: impl Fn(usize) -> u8) -> Luma<u8> {
+    let (min, weights) = weights;
+    let sum = weights.iter().enumerate().fold(1i64 << (PRECISION_BITS - 1), |sum, (i, &weight)| sum + at(min + i) as i64 * weight as i64);
+    Luma([clip8(sum)])
+}
+
+/// `Image.resize((width, height), filter)`.
+pub(crate) fn resize(img: &GrayImage, width: u32, height: u32, filter: Filter) -> GrayImage {
+    resize_box(img, [0.0, 0.0, img.width() as f64, img.height() as f64], width, height, filter)
+}
+
+// `Image.resize` of the region `[left, top, right, bottom]`: horizontally, then vertically, each only
+// when the size or the region changes
+fn resize_box(img: &GrayImage, region: [f64; 4], width: u32, height: u32, filter: Filter) -> GrayImage {
+    let [left, top, right, bottom] = region;
+    let mut img = img.clone();
+    // the reference compares the right and bottom edges with the output size
+    if img.width() != width || left != 0.0 || right != width as f64 {
+        let horizontal = coefficients(img.width(), left, right, width, filter);
+        img = GrayImage::from_fn(width, img.height(), |x, y| convolve(&horizontal[x as usize], |i| img.get_pixel(i as u32, y)[0]));
+    }
+    if img.height() != height || top != 0.0 || bottom != height as f64 {
+        let vertical = coefficients(img.height(), top, bottom, height, filter);
+        img = GrayImage::from_fn(img.width(), height, |x, y| convolve(&vertical[y as usize], |i| img.get_pixel(x, i as u32)[0]));
+    }
+    img
+}
+
+/// `Image.reduce((factor_x, factor_y))`: means of `factor_x` x `factor_y` blocks, the last ones of a row
+/// or column over the pixels that are left.
+pub(crate) fn reduce(img: &GrayImage, factor_x: u32, factor_y: u32) -> GrayImage {
+    let (width, height) = (img.width(), img.height());
+    GrayImage::from_fn(width.div_ceil(factor_x), height.div_ceil(factor_y), |x, y| {
+        let (xs, ys) = (x * factor_x..((x + 1) * factor_x).min(width), y * factor_y..((y + 1) * factor_y).min(height));
+        let count = xs.len() as u32 * ys.len() as u32;
+        let sum: u32 = ys.flat_map(|y| xs.clone().map(move |x| (x, y))).map(|(x, y)| img.get_pixel(x, y)[0] as u32).sum();
+        // the division is a multiplication by a 24-bit fraction computed in single precision
+        let multiplier = (4_294_967_296f32 / (256 * count) as f32) as u32;
+        Luma([((u64::from(sum + count / 2) * u64::from(multiplier)) >> 24) as u8])
+    })
+}
+
+/// `Image.thumbnail((max_size, max_size))` with its defaults: the size that keeps the aspect ratio best,
+/// reached by reducing and then resizing with the bicubic filter. `None` when the image already fits.
+/// The JPEG draft mode Pillow decodes images of twice the size or more with is not part of it.
+pub(crate) fn thumbnail(img: &RgbImage, max_size: u32) -> Option<RgbImage> {
+    let (width, height) = thumbnail_size(img.width(), img.height(), max_size)?;
+    if (width, height) == img.dimensions() {
+        return None;
+    }
+    let factor = |from: u32, to: u32| ((from as f64 / to as f64 / REDUCING_GAP) as u32).max(1);
+    let (factor_x, factor_y) = (factor(img.width(), width), factor(img.height(), height));
+    let region = [0.0, 0.0, img.width() as f64 / factor_x as f64, img.height() as f64 / factor_y as f64];
+
+    let channels: Vec<GrayImage> = (0..3)
+        .map(|channel| {
+            let plane = GrayImage::from_fn(img.width(), img.height(), |x, y| Luma([img.get_pixel(x, y)[channel]]));
+            let plane = if factor_x > 1 || factor_y > 1 { reduce(&plane, factor_x, factor_y) } else { plane };
+            resize_box(&plane, region, width, height, Filter::Bicubic)
+        })
+        .collect();
+    Some(RgbImage::from_fn(width, height, |x, y| image::Rgb(std::array::from_fn(|channel| channels[channel].get_pixel(x, y)[0]))))
+}
+
+// the rounding of `preserve_aspect_ratio` in `Image.thumbnail`: down or up, whichever ratio is closer
+fn thumbnail_size(width: u32, height: u32, max_size: u32) -> Option<(u32, u32)> {
+    if width <= max_size && height <= max_size {
+        return None;
+    }
+    let (aspect, max) = (width as f64 / height as f64, max_size as f64);
+    let closest = |number: f64, error: &dyn Fn(f64) -> f64| {
+        let (down, up) = (number.floor(), number.ceil());
+        (if error(up) < error(down) { up } else { down }).max(1.0) as u32
+    };
+    Some(if aspect <= 1.0 {
+        (closest(max * aspect, &|n| (aspect - n / max).abs()), max_size)
+    } else {
+        (max_size, closest(max / aspect, &|n| if n == 0.0 { 0.0 } else { (aspect - max / n).abs() }))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn weights_are_normalized_in_fixed_point() {
+        // upsizing keeps the unscaled window, downsizing widens it by the scale
+        let up = coefficients(8, 0.0, 8.0, 9, Filter::Lanczos);
+        assert!(up.iter().all(|(_, weights)| weights.len() <= 6));
+        let down = coefficients(512, 0.0, 512.0, 8, Filter::Lanczos);
+        assert_eq!(down[4].0, 96);
+        assert_eq!(down[4].1.len(), 384);
+        for (_, weights) in up.iter().chain(&down).chain(&coefficients(1334, 0.0, 4000.0 / 3.0, 512, Filter::Bicubic)) {
+            let total: i32 = weights.iter().sum();
+            assert!((total - (1 << PRECISION_BITS)).abs() <= weights.len() as i32, "{total}");
+        }
+        // a flat image stays flat
+        let flat = GrayImage::from_pixel(100, 70, Luma([77]));
+        assert!(resize(&flat, 9, 8, Filter::Lanczos).pixels().all(|p| p[0] == 77));
+        assert!(resize(&flat, 30, 20, Filter::Bicubic).pixels().all(|p| p[0] == 77));
+    }
+
+    #[test]
+    fn reductions_round_the_block_means() {
+        let img = GrayImage::from_fn(7, 5, |x, y| Luma([(x * 30 + y * 7) as u8]));
+        let reduced = reduce(&img, 3, 2);
+        assert_eq!(reduced.dimensions(), (3, 3));
+        // (0 + 30 + 60 + 7 + 37 + 67) / 6 = 33.5, which the truncated multiplier rounds down
+        assert_eq!(reduced.get_pixel(0, 0)[0], 33);
+        // the last column is a single pixel wide, the last row a single pixel high
+        assert_eq!(reduced.get_pixel(2, 0)[0], 184);
+        assert_eq!(reduced.get_pixel(2, 2)[0], 208);
+    }
+
+    #[test]
+    fn thumbnails_keep_the_aspect_ratio() {
+        assert_eq!(thumbnail_size(500, 333, 512), None);
+        assert_eq!(thumbnail_size(4000, 3000, 512), Some((512, 384)));
+        assert_eq!(thumbnail_size(1000, 3000, 512), Some((171, 512)));
+        assert_eq!(thumbnail_size(5000, 1, 512), Some((512, 1)));
+
+        let img = RgbImage::from_fn(1300, 700, |x, y| image::Rgb([(x % 256) as u8, (y % 256) as u8, ((x + y) / 8 % 256) as u8]));
+        let small = thumbnail(&img, 512).unwrap();
+        assert_eq!(small.dimensions(), (512, 276));
+        assert!(thumbnail(&RgbImage::new(512, 300), 512).is_none());
+    }
+}
@@ -0,0 +1,256 @@
+use image::{DynamicImage, GrayImage, imageops};
+
+// defaults of ph_image_digest in pHash.org
+const SIGMA: f32 = 1.0;
+const PROJECTIONS: usize = 180;
+const COEFFICIENTS: usize = 40;
+
+/// Distance of digests without any positive correlation.
+pub const MAX_DISTANCE: u32 = 100;
+
+/// Peak cross-correlation above which pHash.org considers two digests a match.
+pub const MATCH_THRESHOLD: f64 = 0.9;
+
+/// Radial variance digest: the first DCT coefficients of the pixel variance along
+/// lines through the image centre, scaled to 0-255.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RadialDigest {
+    coeffs: Vec<u8>,
+}
+
+impl RadialDigest {
+    pub fn coeffs(&self) -> &[u8] {
+        &self.coeffs
+    }
+
+    pub fn from_coeffs(coeffs: Vec<u8>) -> Self {
+        RadialDigest { coeffs }
+    }
+
+    pub fn to_hex(&self) -> String {
+        self.coeffs.iter().map(|c| format!("{c:02x}")).collect()
+    }
+
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        if hex.len() != COEFFICIENTS * 2 || !hex.is_ascii() {
+            return None;
+        }
+        let coeffs = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+            .collect::<Option<Vec<u8>>>()?;
+        Some(RadialDigest { coeffs })
+    }
+
+    /// Highest Pearson correlation over all circular shifts of `other`, between 0 and 1; like in
+    /// pHash.org, negative correlations count as 0. Shifting the digest compensates for the image being rotated.
+    /// Flat digests, whose coefficients are all equal, correlate fully with each other and not at all with others.
+    pub fn peak_cross_correlation(&self, other: &RadialDigest) -> f64 {
+        assert_eq!(self.coeffs.len(), other.coeffs.len(), "Cannot compare digests of different lengths");
+        let n = self.coeffs.len();
+        let mean = |coeffs: &[u8]| coeffs.iter().map(|&c| c as f64).sum::<f64>() / n as f64;
+        let (mean_x, mean_y) = (mean(&self.coeffs), mean(&other.coeffs));
+        // the sums of squares do not depend on the shift
+        let squares = |coeffs: &[u8], mean: f64| coeffs.iter().map(|&c| (c as f64 - mean).powi(2)).sum::<f64>();
+        let (den_x, den_y) = (squares(&self.coeffs, mean_x), squares(&other.coeffs, mean_y));
+        if den_x == 0.0 || den_y == 0.0 {
+            return if den_x == den_y { 1.0 } else { 0.0 };
+        }
+
+        let mut peak = 0.0;
+        for shift in 0..n {
+            let num: f64 = (0..n)
+                .map(|i| (self.coeffs[i] as f64 - mean_x) * (other.coeffs[(n + i - shift) % n] as f64 - mean_y))
+                .sum();
+            let r = num / (den_x * den_y).sqrt();
+            if r > peak {
+                peak = r;
+            }
+        }
+        peak
+    }
+
+    /// Distance between 0 (perfect correlation) and 100 (no positive correlation).
+    pub fn distance(&self, other: &RadialDigest) -> u32 {
+        ((1.0 - self.peak_cross_correlation(other)) * MAX_DISTANCE as f64).round().clamp(0.0, MAX_DISTANCE as f64) as u32
+    }
+}
+
+/// Radial variance hash of pHash.org (`ph_image_digest`).
+pub fn radial_hash(img: &DynamicImage) -> RadialDigest {
+    let luma = imageops::blur(&luma(img), SIGMA);
+    let (lines, pixels_per_line) = radon_projections(&luma);
+    let features = feature_vector(&lines, &pixels_per_line);
+    RadialDigest { coeffs: dct(&features) }
+}
+
+// Y channel of YCbCr, as CImg computes it for colour images
+pub(crate) fn luma(img: &DynamicImage) -> GrayImage {
+    if !img.color().has_color() {
+        return img.to_luma8();
+    }
+    let rgb = img.to_rgb8();
+    GrayImage::from_fn(rgb.width(), rgb.height(), |x, y| {
+        let [r, g, b] = rgb.get_pixel(x, y).0.map(|v| v as u32);
+        image::Luma([(((66 * r + 129 * g + 25 * b + 128) >> 8) + 16).min(255) as u8])
+    })
+}
+
+fn round_half_away(value: f64) -> i64 {
+    (value + if value >= 0.0 { 0.5 } else { -0.5 }).floor() as i64
+}
+
+// pixels along PROJECTIONS lines through the centre, one angle step being pi / PROJECTIONS;
+// lines are stored with zero padding up to the longest image side
+fn radon_projections(img: &GrayImage) -> (Vec<Vec<u8>>, Vec<usize>) {
+    let (width, height) = (img.width() as i64, img.height() as i64);
+    let d = width.max(height);
+    let x_off = round_half_away(width as f64 / 2.0);
+    let y_off = round_half_away(height as f64 / 2.0);
+    let n = PROJECTIONS;
+
+    let mut lines = vec![vec![0u8; d as usize]; n];
+    let mut pixels_per_line = vec![0usize; n];
+    let pixel = |x: i64, y: i64| img.get_pixel(x as u32, y as u32)[0];
+
+    // angles up to 45 degrees, mirrored to angles between 45 and 90 degrees
+    for k in 0..=n / 4 {
+        let alpha = (k as f64 * std::f64::consts::PI / n as f64).tan();
+        for x in 0..d {
+            let yd = round_half_away(alpha * (x - x_off) as f64);
+            if yd + y_off >= 0 && yd + y_off < height && x < width {
+                lines[k][x as usize] = pixel(x, yd + y_off);
+                pixels_per_line[k] += 1;
+            }
+            if yd + x_off >= 0 && yd + x_off < width && k != n / 4 && x < height {
+                lines[n / 2 - k][x as usize] = pixel(yd + x_off, x);
+                pixels_per_line[n / 2 - k] += 1;
+            }
+        }
+    }
+
+    // angles between 135 and 180 degrees, mirrored to angles between 90 and 135 degrees
+    for (j, k) in (3 * n / 4..n).enumerate() {
+        let alpha = (k as f64 * std::f64::consts::PI / n as f64).tan();
+        for x in 0..d {
+            let yd = round_half_away(alpha * (x - x_off) as f64);
+            if yd + y_off >= 0 && yd + y_off < height && x < width {
+                lines[k][x as usize] = pixel(x, yd + y_off);
+                pixels_per_line[k] += 1;
+            }
+            // the reference checks the column against the width here as well
+            if y_off - yd >= 0 && y_off - yd < width && x < height {
+                lines[n / 4 + j][x as usize] = pixel(y_off - yd, x);
+                pixels_per_line[n / 4 + j] += 1;
+            }
+        }
+    }
+
+    (lines, pixels_per_line)
+}
+
+// variance of every line, normalized to zero mean and unit variance across lines
+fn feature_vector(lines: &[Vec<u8>], pixels_per_line: &[usize]) -> Vec<f64> {
+    let mut features: Vec<f64> = lines
+        .iter()
+        .zip(pixels_per_line)
+        .map(|(line, &count)| {
+            if count == 0 {
+                return 0.0;
+            }
+            let count = count as f64;
+            let sum: f64 = line.iter().map(|&v| v as f64).sum();
+            let sum_sqd: f64 = line.iter().map(|&v| (v as f64).powi(2)).sum();
+            sum_sqd / count - sum * sum / (count * count)
+        })
+        .collect();
+
+    let n = features.len() as f64;
+    let sum: f64 = features.iter().sum();
+    let sum_sqd: f64 = features.iter().map(|f| f * f).sum();
+    let mean = sum / n;
+    let deviation = (sum_sqd / n - sum * sum / (n * n)).sqrt();
+    if deviation > 0.0 {
+        features.iter_mut().for_each(|f| *f = (*f - mean) / deviation);
+    }
+    features
+}
+
+// first COEFFICIENTS coefficients of the orthonormal DCT-II, stretched to 0-255
+fn dct(features: &[f64]) -> Vec<u8> {
+    let n = features.len();
+    let coeffs: Vec<f64> = (0..COEFFICIENTS)
+        .map(|k| {
+            let sum: f64 = features
+                .iter()
+                .enumerate()
+                .map(|(i, f)| f * (std::f64::consts::PI * (2 * i + 1) as f64 * k as f64 / (2 * n) as f64).cos())
+                .sum();
+            let scale = if k == 0 { (1.0 / n as f64).sqrt() } else { (2.0 / n as f64).sqrt() };
+            scale * sum
+        })
+        .collect();
+
+    // the reference starts both bounds at 0
+    let max = coeffs.iter().copied().fold(0.0, f64::max);
+    let min = coeffs.iter().copied().fold(0.0, f64::min);
+    coeffs
+        .iter()
+        .map(|c| if max > min { (255.0 * (c - min) / (max - min)) as u8 } else { 0 })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use crate::preprocessing::preprocessor::{PreprocessOptions, load};
+    use super::*;
+
+    fn load_image(path: &str) -> DynamicImage {
+        load(Path::new(path), 256, &PreprocessOptions::default()).expect("load failed")
+    }
+
+    #[test]
+    fn digest_has_40_coefficients_and_round_trips_through_hex() {
+        let digest = radial_hash(&load_image("pictures/cats/not_robust/cats.jpg"));
+        assert_eq!(digest.coeffs().len(), COEFFICIENTS);
+        assert_eq!(RadialDigest::from_hex(&digest.to_hex()), Some(digest.clone()));
+        assert!((digest.peak_cross_correlation(&digest) - 1.0).abs() < 1e-9);
+        assert_eq!(digest.distance(&digest), 0);
+    }
+
+    #[test]
+    fn correlation_finds_shifted_digests() {
+        let digest = radial_hash(&load_image("pictures/dogs/not_robust/dogs.jpg"));
+        let mut shifted = digest.clone();
+        shifted.coeffs.rotate_right(7);
+        assert!((digest.peak_cross_correlation(&shifted) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn rotated_images_still_match() {
+        for name in ["cats", "dogs"] {
+            let original = radial_hash(&load_image(&format!("pictures/{name}/not_robust/{name}.jpg")));
+            let rotated = radial_hash(&load_image(&format!("pictures/{name}/not_robust/{name}_rotate_180.jpg")));
+            let pcc = original.peak_cross_correlation(&rotated);
+            assert!(pcc > MATCH_THRESHOLD, "{name} rotated by 180 degrees has correlation {pcc}");
+        }
+    }
+
+    #[test]
+    fn flat_digests_only_match_each_other() {
+        let (black, white) = (RadialDigest::from_coeffs(vec![0; COEFFICIENTS]), RadialDigest::from_coeffs(vec![255; COEFFICIENTS]));
+        let cats = radial_hash(&load_image("pictures/cats/not_robust/cats.jpg"));
+        assert_eq!(black.peak_cross_correlation(&white), 1.0);
+        assert_eq!(black.distance(&black), 0);
+        assert_eq!(black.peak_cross_correlation(&cats), 0.0);
+        assert_eq!(cats.distance(&white), MAX_DISTANCE);
+    }
+
+    #[test]
+    fn different_images_do_not_match() {
+        let cats = radial_hash(&load_image("pictures/cats/not_robust/cats.jpg"));
+        let dogs = radial_hash(&load_image("pictures/dogs/not_robust/dogs.jpg"));
+        assert!(cats.peak_cross_correlation(&dogs) < MATCH_THRESHOLD);
+    }
+}
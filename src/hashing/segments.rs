@@ -0,0 +1,168 @@
+use image::{DynamicImage, imageops};
+use super::d_hash::d_hash_image;
+use super::hash::BitHash;
+
+// the image is scaled so that its shorter side is IMG_SIZE, and its longer side at most MAX_ASPECT times that
+pub const IMG_SIZE: u32 = 256;
+const MAX_ASPECT: u32 = 4;
+/// Two segments agree when their dHashes differ in at most this many bits.
+pub const SEGMENT_MATCH_DISTANCE: u32 = 10;
+/// Share of the segments of either image, in percent, that has to agree for the images to match;
+/// as a share it scales with the number of segments the grid gives. Unrelated photos share next to
+/// none, copies cropped by 5% still 3 to 8 percent (`pictures/*/partially_robust/*_crop_5.jpg`).
+pub const MIN_MATCHING_PERCENT: u32 = 3;
+/// Distance up to which two images match, see `MIN_MATCHING_PERCENT`.
+pub const MATCH_DISTANCE: u32 = MAX_DISTANCE - MIN_MATCHING_PERCENT;
+/// Distance of images without a single agreeing segment.
+pub const MAX_DISTANCE: u32 = 100;
+
+/// Parameters of the crop-resistant segment hash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentOptions {
+    /// Tiles are square and 1 / `grid` of the shorter side of the image wide, a new one starting every quarter tile.
+    pub grid: u32,
+}
+
+impl Default for SegmentOptions {
+    fn default() -> Self {
+        SegmentOptions { grid: 4 }
+    }
+}
+
+impl SegmentOptions {
+    fn tile_size(&self) -> u32 {
+        IMG_SIZE / self.grid
+    }
+
+    /// Number of tiles along the shorter side, 13 for a grid of 4; the longer side has at least as many.
+    pub fn tiles_per_side(&self) -> usize {
+        positions(IMG_SIZE, self.tile_size()).len()
+    }
+}
+
+// tile offsets along a side of `len` pixels, a quarter tile apart, the last one ending with the side
+fn positions(len: u32, tile: u32) -> Vec<u32> {
+    let step = (tile / 4).max(1);
+    let count = (len - tile).div_ceil(step) + 1;
+    (0..count).map(|i| (i * step).min(len - tile)).collect()
+}
+
+/// dHashes of overlapping tiles of an image, row by row.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentHashes {
+    segments: Vec<BitHash>,
+}
+
+impl SegmentHashes {
+    pub fn segments(&self) -> &[BitHash] {
+        &self.segments
+    }
+
+    pub fn from_segments(segments: Vec<BitHash>) -> Self {
+        SegmentHashes { segments }
+    }
+
+    /// Number of own segments that agree with any segment of `other`. Comparing every
+    /// pair lets a tile match its counterpart even when cropping has shifted it.
+    pub fn matching_segments(&self, other: &SegmentHashes) -> usize {
+        self.segments
+            .iter()
+            .filter(|segment| other.segments.iter().any(|o| segment.hamming(o) <= SEGMENT_MATCH_DISTANCE))
+            .count()
+    }
+
+    /// Whether at least `MIN_MATCHING_PERCENT` of the segments of both images agree.
+    pub fn is_match(&self, other: &SegmentHashes) -> bool {
+        self.distance(other) <= MATCH_DISTANCE
+    }
+
+    /// 100 less the share of segments, in whole percent, that have a counterpart in the other image;
+    /// of both images the one with fewer, so that the distance is the same both ways.
+    pub fn distance(&self, other: &SegmentHashes) -> u32 {
+        let matching_percent = |a: &SegmentHashes, b: &SegmentHashes| {
+            (a.matching_segments(b) * MAX_DISTANCE as usize).checked_div(a.segments.len()).unwrap_or(0) as u32
+        };
+        MAX_DISTANCE - matching_percent(self, other).min(matching_percent(other, self))
+    }
+
+    pub fn to_hex(&self) -> String {
+        self.segments.iter().map(BitHash::to_hex).collect()
+    }
+
+    /// Inverse of `to_hex` for segments of 64 bits, the size of a dHash.
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        if hex.is_empty() || !hex.len().is_multiple_of(16) || !hex.is_ascii() {
+            return None;
+        }
+        let segments = (0..hex.len())
+            .step_by(16)
+            .map(|i| BitHash::from_hex(&hex[i..i + 16], 64))
+            .collect::<Option<Vec<BitHash>>>()?;
+        Some(SegmentHashes { segments })
+    }
+}
+
+/// Crop-resistant hash: the image is split into overlapping square tiles and each is hashed on its own.
+/// The aspect ratio is kept, so wide images have more tiles along their width.
+pub fn segment_hash(img: &DynamicImage, options: &SegmentOptions) -> SegmentHashes {
+    assert!(options.grid >= 1 && options.grid <= IMG_SIZE / 4, "Segment grid must have between 1 and 64 tiles");
+    let (width, height) = (img.width().max(1) as f64, img.height().max(1) as f64);
+    let scale = IMG_SIZE as f64 / width.min(height);
+    let side = |len: f64| ((len * scale).round() as u32).clamp(IMG_SIZE, MAX_ASPECT * IMG_SIZE);
+    let img = img.resize_exact(side(width), side(height), imageops::Triangle);
+
+    let tile = options.tile_size();
+    let columns = positions(img.width(), tile);
+    let segments = positions(img.height(), tile)
+        .iter()
+        .flat_map(|&y| columns.iter().map(move |&x| (x, y)))
+        .map(|(x, y)| BitHash::from_u64(d_hash_image(&img.crop_imm(x, y, tile, tile))))
+        .collect();
+    SegmentHashes { segments }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use crate::preprocessing::preprocessor::{PreprocessOptions, load};
+    use super::*;
+
+    fn hash_file(path: &str) -> SegmentHashes {
+        let img = load(Path::new(path), IMG_SIZE, &PreprocessOptions::default()).expect("load failed");
+        segment_hash(&img, &SegmentOptions::default())
+    }
+
+    #[test]
+    fn tiles_follow_the_aspect_ratio() {
+        // 4:3, scaled to 341x256: 13 tiles down and 19 across
+        let segments = hash_file("pictures/cats/partially_robust/cats.jpg");
+        assert_eq!(SegmentOptions::default().tiles_per_side(), 13);
+        assert_eq!(segments.segments().len(), 13 * 19);
+        assert_eq!(segments.distance(&segments), 0);
+
+        let square = segment_hash(&DynamicImage::new_rgb8(500, 500), &SegmentOptions::default());
+        assert_eq!(square.segments().len(), 13 * 13);
+        // a panorama is squashed to 4:1 at most
+        let panorama = segment_hash(&DynamicImage::new_rgb8(5000, 500), &SegmentOptions::default());
+        assert_eq!(panorama.segments().len(), 13 * 61);
+        assert_eq!(SegmentOptions { grid: 3 }.tiles_per_side(), 10);
+    }
+
+    #[test]
+    fn cropped_images_keep_matching_segments() {
+        for (name, other_name) in [("cats", "dogs"), ("dogs", "cats")] {
+            let original = hash_file(&format!("pictures/{name}/partially_robust/{name}.jpg"));
+            let other = hash_file(&format!("pictures/{other_name}/partially_robust/{other_name}.jpg"));
+            let unrelated = original.distance(&other);
+            assert!(!original.is_match(&other), "{name} is at {unrelated} from an unrelated image");
+
+            for crop in [1, 3, 5] {
+                let cropped = hash_file(&format!("pictures/{name}/partially_robust/{name}_crop_{crop}.jpg"));
+                let distance = original.distance(&cropped);
+                assert!(original.is_match(&cropped), "{name} cropped by {crop} is at {distance}");
+                assert_eq!(cropped.distance(&original), distance);
+                assert!(distance < unrelated);
+            }
+        }
+    }
+}
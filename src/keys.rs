@@ -0,0 +1,166 @@
+use std::collections::BTreeMap;
+use crossterm::event::KeyCode;
+use color_eyre::{Result, eyre::eyre};
+
+// what the keys of the TUI do; a key can do different things on different screens
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Action {
+    Quit,
+    Back,
+    Up,
+    Down,
+    Left,
+    Right,
+    SelectFolder,
+    Choose,
+    Alpha,
+    Decode,
+    Color,
+    Quality,
+    Sampling,
+    FrameMatch,
+    Rule,
+    Display,
+    Filter,
+    Loosen,
+    Tighten,
+    Order,
+    Export,
+    SaveIndex,
+    Watch,
+    Log,
+}
+
+// name in the [keys] section of the configuration and the default key
+const ACTIONS: [(Action, &str, &str); 24] = [
+    (Action::Quit, "quit", "q"),
+    (Action::Back, "back", "esc"),
+    (Action::Up, "up", "k"),
+    (Action::Down, "down", "j"),
+    (Action::Left, "left", "h"),
+    (Action::Right, "right", "l"),
+    (Action::SelectFolder, "select-folder", "c"),
+    (Action::Choose, "choose", "enter"),
+    (Action::Alpha, "alpha", "a"),
+    (Action::Decode, "decode", "f"),
+    (Action::Color, "color", "c"),
+    (Action::Quality, "quality", "u"),
+    (Action::Sampling, "sampling", "n"),
+    (Action::FrameMatch, "frame-match", "b"),
+    (Action::Rule, "rule", "m"),
+    (Action::Display, "display", "d"),
+    (Action::Filter, "filter", "f"),
+    (Action::Loosen, "loosen", "+"),
+    (Action::Tighten, "tighten", "-"),
+    (Action::Order, "order", "o"),
+    (Action::Export, "export", "e"),
+    (Action::SaveIndex, "save-index", "w"),
+    (Action::Watch, "watch", "v"),
+    (Action::Log, "log", "g"),
+];
+
+// the actions of every screen, which need keys of their own
+pub const EXPLORER: [Action; 3] = [Action::Quit, Action::SelectFolder, Action::Log];
+pub const CHOOSER: [Action; 14] = [
+    Action::Quit, Action::Back, Action::Up, Action::Down, Action::Left, Action::Right, Action::Choose,
+    Action::Alpha, Action::Decode, Action::Color, Action::Quality, Action::Sampling, Action::FrameMatch, Action::Log,
+];
+pub const CALCULATING: [Action; 3] = [Action::Quit, Action::Back, Action::Log];
+pub const RESULTS: [Action; 16] = [
+    Action::Quit, Action::Back, Action::Up, Action::Down, Action::Left, Action::Right, Action::Rule, Action::Display,
+    Action::Filter, Action::Loosen, Action::Tighten, Action::Order, Action::Export, Action::SaveIndex, Action::Watch, Action::Log,
+];
+// filter picks the lowest level shown, log goes back like back
+pub const LOG: [Action; 6] = [Action::Quit, Action::Back, Action::Up, Action::Down, Action::Filter, Action::Log];
+const SCREENS: [(&str, &[Action]); 5] = [
+    ("explorer", &EXPLORER), ("algorithm", &CHOOSER), ("calculating", &CALCULATING), ("results", &RESULTS), ("log", &LOG),
+];
+
+const NAMED_KEYS: [(&str, KeyCode); 14] = [
+    ("esc", KeyCode::Esc),
+    ("enter", KeyCode::Enter),
+    ("tab", KeyCode::Tab),
+    ("space", KeyCode::Char(' ')),
+    ("backspace", KeyCode::Backspace),
+    ("delete", KeyCode::Delete),
+    ("home", KeyCode::Home),
+    ("end", KeyCode::End),
+    ("pageup", KeyCode::PageUp),
+    ("pagedown", KeyCode::PageDown),
+    ("up", KeyCode::Up),
+    ("down", KeyCode::Down),
+    ("left", KeyCode::Left),
+    ("right", KeyCode::Right),
+];
+
+/// The `[keys]` section with every action at its default key.
+pub fn defaults() -> BTreeMap<String, String> {
+    ACTIONS.iter().map(|(_, name, key)| (name.to_string(), key.to_string())).collect()
+}
+
+fn parse_key(key: &str) -> Option<KeyCode> {
+    let mut chars = key.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Some(KeyCode::Char(c));
+    }
+    let key = key.to_lowercase();
+    NAMED_KEYS.iter().find(|(name, _)| *name == key).map(|(_, code)| *code)
+        .or_else(|| key.strip_prefix('f').and_then(|n| n.parse().ok()).filter(|n| (1..=12).contains(n)).map(KeyCode::F))
+}
+
+/// The keys of the actions, from the `[keys]` section of the configuration.
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    keys: BTreeMap<Action, KeyCode>,
+}
+
+impl Keymap {
+    /// Actions missing from `bindings` keep their default key; two actions of a screen cannot share one.
+    pub fn new(bindings: &BTreeMap<String, String>) -> Result<Self> {
+        let mut keys = BTreeMap::new();
+        for (action, name, default) in ACTIONS {
+            let key = bindings.get(name).map_or(default, String::as_str);
+            let code = parse_key(key).ok_or_else(|| eyre!("keys.{name}: unknown key {key:?}"))?;
+            keys.insert(action, code);
+        }
+        if let Some(name) = bindings.keys().find(|name| !ACTIONS.iter().any(|(_, known, _)| known == name)) {
+            return Err(eyre!("keys.{name}: unknown action"));
+        }
+
+        let keymap = Keymap { keys };
+        for (screen, actions) in SCREENS {
+            for (i, a) in actions.iter().enumerate() {
+                if let Some(b) = actions[i + 1..].iter().find(|b| keymap.keys[a] == keymap.keys[*b]) {
+                    return Err(eyre!("keys: {} and {} share the key {} on the {screen} screen", name(*a), name(*b), keymap.label(*a)));
+                }
+            }
+        }
+        Ok(keymap)
+    }
+
+    /// The action of `actions` that `code` is bound to; arrow keys always move.
+    pub fn action(&self, code: KeyCode, actions: &[Action]) -> Option<Action> {
+        actions.iter().copied().find(|action| self.keys[action] == code).or_else(|| {
+            let arrow = match code {
+                KeyCode::Up => Action::Up,
+                KeyCode::Down => Action::Down,
+                KeyCode::Left => Action::Left,
+                KeyCode::Right => Action::Right,
+                _ => return None,
+            };
+            actions.contains(&arrow).then_some(arrow)
+        })
+    }
+
+    /// The key of `action` for the hints, e.g. `<Q>` or `<Esc>`.
+    pub fn label(&self, action: Action) -> String {
+        match self.keys[&action] {
+            KeyCode::Char(c) if c != ' ' => format!("<{}>", c.to_uppercase()),
+            code => format!("<{code}>"),
+        }
+    }
+}
+
+fn name(action: Action) -> &'static str {
+    ACTIONS.iter().find(|(known, _, _)| *known == action).map_or("", |(_, name, _)| name)
+}
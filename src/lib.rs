@@ -0,0 +1,6 @@
+pub mod hashing;
+pub mod preprocessing;
+pub mod handler;
+pub mod ffi;
+#[cfg(test)]
+mod test_support;
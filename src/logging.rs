@@ -0,0 +1,135 @@
+use std::collections::VecDeque;
+use std::fmt::Debug;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{Event, Level, Subscriber, field::{Field, Visit}, warn};
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::{Layer, filter::LevelFilter, layer::{Context, SubscriberExt}, util::SubscriberInitExt};
+
+// lines kept for the log screen of the TUI
+const BUFFER_LINES: usize = 1000;
+const FILE_PREFIX: &str = "perceptual-hashing";
+
+/// Where and how much is logged, from the `[log]` section of the configuration.
+#[derive(Debug, Clone)]
+pub struct LogSettings {
+    pub level: LevelFilter,
+    /// `None` when there is no folder for it, e.g. without a home folder.
+    pub dir: Option<PathBuf>,
+    pub rotation: Rotation,
+    /// Rotated files beyond this many are deleted.
+    pub max_files: usize,
+}
+
+/// An event as the log screen shows it.
+#[derive(Debug, Clone)]
+pub struct LogLine {
+    /// Since the start of the application.
+    pub elapsed: Duration,
+    pub level: Level,
+    pub target: String,
+    /// The message followed by the other fields as `key=value`.
+    pub text: String,
+}
+
+/// The latest events, shared between the logging layer and the TUI.
+#[derive(Debug, Clone, Default)]
+pub struct LogBuffer(Arc<Mutex<VecDeque<LogLine>>>);
+
+impl LogBuffer {
+    pub fn lines(&self) -> Vec<LogLine> {
+        self.0.lock().map(|lines| lines.iter().cloned().collect()).unwrap_or_default()
+    }
+
+    fn push(&self, line: LogLine) {
+        if let Ok(mut lines) = self.0.lock() {
+            if lines.len() == BUFFER_LINES {
+                lines.pop_front();
+            }
+            lines.push_back(line);
+        }
+    }
+}
+
+#[derive(Default)]
+struct Fields {
+    message: String,
+    rest: String,
+}
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "message" => self.message = value.to_string(),
+            name => self.rest.push_str(&format!(" {name}={value}")),
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        match field.name() {
+            "message" => self.message = format!("{value:?}"),
+            name => self.rest.push_str(&format!(" {name}={value:?}")),
+        }
+    }
+}
+
+struct BufferLayer {
+    buffer: LogBuffer,
+    started: Instant,
+}
+
+impl<S: Subscriber> Layer<S> for BufferLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let metadata = event.metadata();
+        self.buffer.push(LogLine {
+            elapsed: self.started.elapsed(),
+            level: *metadata.level(),
+            target: metadata.target().to_string(),
+            text: fields.message + &fields.rest,
+        });
+    }
+}
+
+/// Logs to a file rotated as configured and to the returned buffer; with `verbose` (the number of `-v`),
+/// also to stderr: 1 for info, 2 for debug including the time every file took, 3 for trace.
+pub fn init(settings: &LogSettings, verbose: u8) -> LogBuffer {
+    let buffer = LogBuffer::default();
+    let file = settings.dir.as_ref().map(|dir| {
+        // the appender looks for old files to delete before it creates the folder
+        fs::create_dir_all(dir)?;
+        RollingFileAppender::builder()
+            .rotation(settings.rotation.clone())
+            .filename_prefix(FILE_PREFIX)
+            .filename_suffix("log")
+            .max_log_files(settings.max_files)
+            .build(dir)
+            .map_err(io::Error::other)
+    });
+    let (file, file_error) = match file {
+        Some(Ok(appender)) => (Some(appender), None),
+        Some(Err(err)) => (None, Some(err)),
+        None => (None, None),
+    };
+    let stderr_level = match verbose {
+        0 => LevelFilter::OFF,
+        1 => LevelFilter::INFO,
+        2 => LevelFilter::DEBUG,
+        _ => LevelFilter::TRACE,
+    };
+
+    tracing_subscriber::registry()
+        .with(file.map(|appender| tracing_subscriber::fmt::layer().with_ansi(false).with_writer(appender).with_filter(settings.level)))
+        .with(BufferLayer { buffer: buffer.clone(), started: Instant::now() }.with_filter(settings.level))
+        .with((verbose > 0).then(|| tracing_subscriber::fmt::layer().with_writer(std::io::stderr).with_filter(stderr_level)))
+        .init();
+    // the application works the same without a log file
+    if let Some(err) = file_error {
+        warn!(dir = ?settings.dir, error = %err, "the log file cannot be written");
+    }
+    buffer
+}
@@ -1,17 +1,50 @@
-mod hashing;
-mod preprocessing;
-mod handler;
 mod app;
+mod cli;
+mod config;
+mod keys;
+mod logging;
+mod theme;
 mod ui;
 mod widgets;
 
 use app::App;
+use clap::Parser;
+use cli::{Cli, Command};
 use color_eyre::Result;
+use perceptual_hashing::preprocessing::formats;
+use tracing::{debug, info};
 
 fn main() -> Result<()> {
     color_eyre::install()?;
+    let cli = Cli::parse();
+    if cli.formats {
+        println!("{}", formats::describe());
+        return Ok(());
+    }
+    let loaded = config::load(cli.config.as_deref(), &cli.overrides)?;
+    if let Some(Command::Config) = cli.command {
+        print!("{}", loaded.describe()?);
+        // mistakes are reported after the configuration they are in
+        return loaded.config.settings().map(|_| ());
+    }
+    let mut settings = loaded.config.settings()?;
+    if cli.tags.is_some() {
+        settings.tags = cli.tags;
+    }
+    // stderr belongs to the TUI when there is no command
+    let verbose = if cli.command.is_some() { cli.verbose } else { 0 };
+    let log = logging::init(&settings.log, verbose);
+    info!(version = env!("CARGO_PKG_VERSION"), "started");
+    for source in loaded.sources.iter().filter(|source| source.found) {
+        debug!(layer = source.name, path = ?source.path, "configuration read");
+    }
+    if let Some(command) = cli.command {
+        return cli::run(command, &settings);
+    }
+
+    let mut app = App::new(settings, log);
     let mut terminal = ratatui::init();
-    let app_result = App::new().run(&mut terminal);
+    let app_result = app.run(&mut terminal);
     ratatui::restore();
     app_result
-}
\ No newline at end of file
+}
@@ -0,0 +1,154 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Seek};
+use std::path::Path;
+use image::{AnimationDecoder, DynamicImage, Frame, GrayImage, ImageError, ImageFormat, ImageReader, imageops};
+use image::codecs::gif::GifDecoder;
+use image::codecs::png::PngDecoder;
+use image::codecs::webp::WebPDecoder;
+use tracing::{field, warn};
+
+/// Upper bound on the frames kept from one animation, so long GIFs cannot stall a scan; the
+/// frames after it are left out with a warning.
+pub const MAX_FRAMES: usize = 64;
+// frames are compared for scene changes on a SCENE_SIZE x SCENE_SIZE grayscale thumbnail
+const SCENE_SIZE: u32 = 16;
+// mean absolute difference of the thumbnails, out of 255, from which on a frame starts a new scene
+const SCENE_THRESHOLD: f64 = 12.0;
+
+/// Which frames of an animated GIF, APNG or WebP are hashed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FrameSampling {
+    /// Only the first frame, like a still image.
+    #[default]
+    First,
+    /// Every n-th frame, starting with the first one.
+    EveryNth(u32),
+    /// The first frame and every frame that differs clearly from the last kept one.
+    SceneChange,
+}
+
+impl FrameSampling {
+    pub fn next(self) -> Self {
+        match self {
+            FrameSampling::First => FrameSampling::EveryNth(5),
+            FrameSampling::EveryNth(_) => FrameSampling::SceneChange,
+            FrameSampling::SceneChange => FrameSampling::First,
+        }
+    }
+}
+
+impl std::fmt::Display for FrameSampling {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FrameSampling::First => write!(f, "first"),
+            FrameSampling::EveryNth(n) => write!(f, "every {n}."),
+            FrameSampling::SceneChange => write!(f, "scene changes"),
+        }
+    }
+}
+
+/// Sampled frames of the animation at `path`; `None` when the file is not an animation.
+pub fn decode_frames(path: &Path, sampling: FrameSampling) -> Result<Option<Vec<DynamicImage>>, ImageError> {
+    sample_frames(BufReader::new(File::open(path)?), sampling, Some(path))
+}
+
+/// Like `decode_frames`, for an animation that is streamed or already in memory.
+pub fn decode_frames_reader<R: BufRead + Seek>(reader: R, sampling: FrameSampling) -> Result<Option<Vec<DynamicImage>>, ImageError> {
+    sample_frames(reader, sampling, None)
+}
+
+// `path` only names the file in the log
+fn sample_frames<R: BufRead + Seek>(mut reader: R, sampling: FrameSampling, path: Option<&Path>) -> Result<Option<Vec<DynamicImage>>, ImageError> {
+    let frames = match ImageReader::new(&mut reader).with_guessed_format()?.format() {
+        Some(ImageFormat::Gif) => GifDecoder::new(reader)?.into_frames(),
+        Some(ImageFormat::Png) => {
+            let decoder = PngDecoder::new(reader)?;
+            if !decoder.is_apng()? {
+                return Ok(None);
+            }
+            decoder.apng()?.into_frames()
+        }
+        Some(ImageFormat::WebP) => {
+            let decoder = WebPDecoder::new(reader)?;
+            if !decoder.has_animation() {
+                return Ok(None);
+            }
+            decoder.into_frames()
+        }
+        _ => return Ok(None),
+    };
+
+    let mut sampled = Vec::new();
+    let mut last_scene: Option<GrayImage> = None;
+    for (i, frame) in frames.enumerate() {
+        let frame: Frame = frame?;
+        let keep = match sampling {
+            FrameSampling::First => i == 0,
+            FrameSampling::EveryNth(n) => (i as u32).is_multiple_of(n.max(1)),
+            FrameSampling::SceneChange => {
+                let thumbnail = imageops::resize(&DynamicImage::ImageRgba8(frame.buffer().clone()).to_luma8(), SCENE_SIZE, SCENE_SIZE, imageops::Triangle);
+                let changed = last_scene.as_ref().is_none_or(|last| mean_difference(last, &thumbnail) > SCENE_THRESHOLD);
+                if changed {
+                    last_scene = Some(thumbnail);
+                }
+                changed
+            }
+        };
+        if keep {
+            if sampled.len() == MAX_FRAMES {
+                warn!(path = path.map(|path| field::display(path.display())), max_frames = MAX_FRAMES, "only the first sampled frames of the animation are hashed");
+                break;
+            }
+            sampled.push(DynamicImage::ImageRgba8(frame.into_buffer()));
+        }
+        if sampling == FrameSampling::First && !sampled.is_empty() {
+            break;
+        }
+    }
+
+    // a single frame animation is no different from a still image
+    Ok((sampled.len() > 1).then_some(sampled))
+}
+
+fn mean_difference(a: &GrayImage, b: &GrayImage) -> f64 {
+    let sum: u64 = a.pixels().zip(b.pixels()).map(|(p, q)| p[0].abs_diff(q[0]) as u64).sum();
+    sum as f64 / (a.width() * a.height()) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::write_gif;
+    use super::*;
+
+    #[test]
+    fn every_nth_frame_is_sampled() {
+        let path = write_gif("nth", &[0, 10, 20, 30, 40, 50, 60]);
+        let frames = decode_frames(&path, FrameSampling::EveryNth(3)).unwrap().unwrap();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(decode_frames(&path, FrameSampling::First).unwrap(), None);
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn scene_changes_skip_repeated_frames() {
+        let path = write_gif("scenes", &[0, 0, 0, 120, 120, 0]);
+        let frames = decode_frames(&path, FrameSampling::SceneChange).unwrap().unwrap();
+        assert_eq!(frames.len(), 3);
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn long_animations_are_cut_off() {
+        let levels: Vec<u8> = (0..MAX_FRAMES as u8 + 6).collect();
+        let path = write_gif("long", &levels);
+        let frames = decode_frames(&path, FrameSampling::EveryNth(1)).unwrap().unwrap();
+        assert_eq!(frames.len(), MAX_FRAMES);
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn still_images_are_not_animations() {
+        let path = Path::new("test_images/test1.png");
+        assert_eq!(decode_frames(path, FrameSampling::EveryNth(1)).unwrap(), None);
+    }
+}
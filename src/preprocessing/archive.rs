@@ -0,0 +1,210 @@
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use flate2::read::GzDecoder;
+use image::ImageFormat;
+
+/// Separates the archive from the entry in a locator, as in `photos.zip!/2023/beach.jpg`.
+pub const SEPARATOR: &str = "!/";
+// sizes come from the archive and may be made up, so larger entries grow their buffer as they are read
+const MAX_PREALLOCATED: u64 = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+impl ArchiveKind {
+    /// The kind of archive `path` is, judged by its name.
+    pub fn of(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?.to_ascii_lowercase();
+        if name.ends_with(".zip") {
+            Some(ArchiveKind::Zip)
+        } else if name.ends_with(".tar") {
+            Some(ArchiveKind::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(ArchiveKind::TarGz)
+        } else {
+            None
+        }
+    }
+}
+
+/// Locator of the entry `entry` of `archive`, used wherever a path of a file is.
+pub fn locator(archive: &Path, entry: &str) -> PathBuf {
+    let mut locator = OsString::from(archive.as_os_str());
+    locator.push(SEPARATOR);
+    locator.push(entry);
+    PathBuf::from(locator)
+}
+
+/// The archive and the entry a locator points at; `None` for paths of plain files.
+pub fn split_locator(path: &Path) -> Option<(PathBuf, String)> {
+    let text = path.to_str()?;
+    text.match_indices(SEPARATOR)
+        .map(|(i, _)| (Path::new(&text[..i]), &text[i + SEPARATOR.len()..]))
+        .find(|(archive, _)| ArchiveKind::of(archive).is_some())
+        .map(|(archive, entry)| (archive.to_path_buf(), entry.to_string()))
+}
+
+/// Whether the entry `name` of an archive is read at all: its extension has to be one of an image
+/// format of the `image` crate, as the formats of the optional features need a file on disk.
+pub fn is_image_entry(name: &str) -> bool {
+    Path::new(name).extension().and_then(|extension| extension.to_str()).is_some_and(|extension| ImageFormat::from_extension(extension).is_some())
+}
+
+// an empty buffer for an entry of `size` bytes
+fn entry_buffer(size: u64) -> Vec<u8> {
+    Vec::with_capacity(size.min(MAX_PREALLOCATED) as usize)
+}
+
+/// Calls `visit` with the name and the content of every image in `archive` (see `is_image_entry`),
+/// in the order they are stored, until it breaks. Entries are read one at a time, nothing is
+/// extracted to disk, and other entries are skipped without being read.
+pub fn visit_entries<F>(archive: &Path, mut visit: F) -> io::Result<()>
+where
+    F: FnMut(String, Vec<u8>) -> ControlFlow<()>,
+{
+    match ArchiveKind::of(archive) {
+        Some(ArchiveKind::Zip) => {
+            let mut zip = zip::ZipArchive::new(BufReader::new(File::open(archive)?)).map_err(io::Error::other)?;
+            for i in 0..zip.len() {
+                let mut entry = zip.by_index(i).map_err(io::Error::other)?;
+                if entry.is_dir() || !is_image_entry(entry.name()) {
+                    continue;
+                }
+                let mut bytes = entry_buffer(entry.size());
+                entry.read_to_end(&mut bytes)?;
+                if visit(entry.name().to_string(), bytes).is_break() {
+                    break;
+                }
+            }
+            Ok(())
+        }
+        Some(ArchiveKind::Tar) => visit_tar(BufReader::new(File::open(archive)?), visit),
+        Some(ArchiveKind::TarGz) => visit_tar(GzDecoder::new(BufReader::new(File::open(archive)?)), visit),
+        None => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not an archive", archive.display()))),
+    }
+}
+
+fn visit_tar<R: Read, F>(reader: R, mut visit: F) -> io::Result<()>
+where
+    F: FnMut(String, Vec<u8>) -> ControlFlow<()>,
+{
+    let mut tar = tar::Archive::new(reader);
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = entry.path()?.to_string_lossy().into_owned();
+        if !is_image_entry(&name) {
+            continue;
+        }
+        let mut bytes = entry_buffer(entry.size());
+        entry.read_to_end(&mut bytes)?;
+        if visit(name, bytes).is_break() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Number of images in `archive`, as `visit_entries` sees them; compressed TAR archives are
+/// decompressed to count them.
+pub fn count_entries(archive: &Path) -> io::Result<usize> {
+    match ArchiveKind::of(archive) {
+        Some(ArchiveKind::Zip) => {
+            let zip = zip::ZipArchive::new(BufReader::new(File::open(archive)?)).map_err(io::Error::other)?;
+            Ok(zip.file_names().filter(|name| !name.ends_with('/') && is_image_entry(name)).count())
+        }
+        Some(ArchiveKind::Tar) => count_tar(BufReader::new(File::open(archive)?)),
+        Some(ArchiveKind::TarGz) => count_tar(GzDecoder::new(BufReader::new(File::open(archive)?))),
+        None => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not an archive", archive.display()))),
+    }
+}
+
+fn count_tar<R: Read>(reader: R) -> io::Result<usize> {
+    let mut count = 0;
+    for entry in tar::Archive::new(reader).entries()? {
+        let entry = entry?;
+        if entry.header().entry_type().is_file() && is_image_entry(&entry.path()?.to_string_lossy()) {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+/// Content of the entry a locator points at. Compressed TAR archives are read up to the entry,
+/// so prefer `visit_entries` for reading many of them.
+pub fn read_entry(locator: &Path) -> io::Result<Vec<u8>> {
+    let not_found = || io::Error::new(io::ErrorKind::NotFound, format!("{} not found", locator.display()));
+    let (archive, name) = split_locator(locator).ok_or_else(not_found)?;
+
+    if ArchiveKind::of(&archive) == Some(ArchiveKind::Zip) {
+        let mut zip = zip::ZipArchive::new(BufReader::new(File::open(&archive)?)).map_err(io::Error::other)?;
+        let mut entry = zip.by_name(&name).map_err(|_| not_found())?;
+        let mut bytes = entry_buffer(entry.size());
+        entry.read_to_end(&mut bytes)?;
+        return Ok(bytes);
+    }
+
+    let mut found = None;
+    visit_entries(&archive, |entry, bytes| {
+        if entry == name {
+            found = Some(bytes);
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    })?;
+    found.ok_or_else(not_found)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::write_archive;
+    use super::*;
+
+    #[test]
+    fn locators_round_trip() {
+        let locator = locator(Path::new("backup/photos.tar.gz"), "2023/beach!/sunset.jpg");
+        assert_eq!(locator, PathBuf::from("backup/photos.tar.gz!/2023/beach!/sunset.jpg"));
+        assert_eq!(split_locator(&locator), Some((PathBuf::from("backup/photos.tar.gz"), "2023/beach!/sunset.jpg".to_string())));
+        assert_eq!(locator.file_name().unwrap(), "sunset.jpg");
+        assert_eq!(split_locator(Path::new("photos/wow!/cat.jpg")), None);
+    }
+
+    #[test]
+    fn entries_are_read_from_every_kind() {
+        for name in ["entries.zip", "entries.tar", "entries.tar.gz"] {
+            let archive = std::env::temp_dir().join(format!("perceptual_hashing_{}_{name}", std::process::id()));
+            write_archive(&archive, &[("a.png", b"first"), ("notes.txt", b"skipped"), ("inner/b.JPG", b"second")]);
+
+            let mut seen = Vec::new();
+            visit_entries(&archive, |entry, bytes| {
+                seen.push((entry, bytes));
+                ControlFlow::Continue(())
+            }).unwrap();
+            assert_eq!(seen, vec![("a.png".to_string(), b"first".to_vec()), ("inner/b.JPG".to_string(), b"second".to_vec())], "{name}");
+            assert_eq!(count_entries(&archive).unwrap(), 2);
+
+            assert_eq!(read_entry(&locator(&archive, "inner/b.JPG")).unwrap(), b"second");
+            assert_eq!(read_entry(&locator(&archive, "missing.png")).unwrap_err().kind(), io::ErrorKind::NotFound);
+            std::fs::remove_file(archive).ok();
+        }
+    }
+
+    #[test]
+    fn only_image_extensions_are_read() {
+        assert!(is_image_entry("2023/beach.jpeg"));
+        assert!(is_image_entry("scan.TIFF"));
+        assert!(!is_image_entry("readme.txt"));
+        assert!(!is_image_entry("photo"));
+        assert!(!is_image_entry("clip.mp4"));
+    }
+}
@@ -0,0 +1,189 @@
+use std::io::{BufRead, Cursor, Seek, SeekFrom};
+use std::path::Path;
+use image::{DynamicImage, GrayImage, ImageError, ImageFormat, ImageReader, RgbImage};
+use jpeg_decoder::PixelFormat;
+use exif::{In, Tag};
+use super::formats::decode_extra;
+
+// the decoded image has to be at least this many times bigger than the hash input
+// so that the final Lanczos resize still has enough detail to work with
+const MIN_OVERSAMPLING: u32 = 4;
+// maximal relative difference between the thumbnail and the primary image aspect ratios
+const ASPECT_TOLERANCE: f64 = 0.02;
+
+/// How much of the source image is actually decoded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DecodeMode {
+    /// Always decode the image at full resolution.
+    #[default]
+    Full,
+    /// For JPEGs use the embedded EXIF thumbnail when it is big enough,
+    /// otherwise decode at 1/2, 1/4 or 1/8 scale in the DCT domain.
+    /// Faster, but the hashes of JPEGs differ slightly from those of a full decode.
+    Fast,
+}
+
+impl DecodeMode {
+    pub fn next(self) -> Self {
+        match self {
+            DecodeMode::Full => DecodeMode::Fast,
+            DecodeMode::Fast => DecodeMode::Full,
+        }
+    }
+}
+
+impl std::fmt::Display for DecodeMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeMode::Full => write!(f, "full"),
+            DecodeMode::Fast => write!(f, "fast"),
+        }
+    }
+}
+
+/// Decodes the image at `path`, possibly at a reduced resolution that is still
+/// comfortably bigger than `width` x `height`.
+pub fn decode(path: &Path, width: u32, height: u32, mode: DecodeMode) -> Result<DynamicImage, ImageError> {
+    if let Some(result) = decode_extra(path) {
+        return result;
+    }
+    // the extension decides the format when the content does not, e.g. for TGA
+    decode_image(ImageReader::open(path)?, width, height, mode)
+}
+
+/// Like `decode`, for an image that is streamed or already in memory. The format is
+/// guessed from the content, so formats enabled by cargo features are not recognised.
+pub fn decode_reader<R: BufRead + Seek>(reader: R, width: u32, height: u32, mode: DecodeMode) -> Result<DynamicImage, ImageError> {
+    decode_image(ImageReader::new(reader).with_guessed_format()?, width, height, mode)
+}
+
+/// `decode_reader` over a byte buffer, e.g. an upload.
+pub fn decode_bytes(bytes: &[u8], width: u32, height: u32, mode: DecodeMode) -> Result<DynamicImage, ImageError> {
+    decode_reader(Cursor::new(bytes), width, height, mode)
+}
+
+/// Whether decoding an image of `format` in `mode` may give less than its full resolution;
+/// only a fast decode of a JPEG does.
+pub fn may_reduce(format: Option<ImageFormat>, mode: DecodeMode) -> bool {
+    mode == DecodeMode::Fast && format == Some(ImageFormat::Jpeg)
+}
+
+fn decode_image<R: BufRead + Seek>(reader: ImageReader<R>, width: u32, height: u32, mode: DecodeMode) -> Result<DynamicImage, ImageError> {
+    if !may_reduce(reader.format(), mode) {
+        return reader.decode();
+    }
+
+    let mut inner = reader.into_inner();
+    let start = inner.stream_position()?;
+    let min_side = width.max(height) * MIN_OVERSAMPLING;
+    // any failure here just means falling back to the regular decoder
+    if let Some(img) = exif_thumbnail(&mut inner, min_side) {
+        return Ok(img);
+    }
+    inner.seek(SeekFrom::Start(start))?;
+    if let Some(img) = scaled_jpeg(&mut inner, min_side) {
+        return Ok(img);
+    }
+    inner.seek(SeekFrom::Start(start))?;
+    ImageReader::with_format(inner, ImageFormat::Jpeg).decode()
+}
+
+fn exif_thumbnail<R: BufRead + Seek>(reader: &mut R, min_side: u32) -> Option<DynamicImage> {
+    let exif = exif::Reader::new().read_from_container(reader).ok()?;
+
+    let uint = |tag, ifd| exif.get_field(tag, ifd).and_then(|f| f.value.get_uint(0));
+    let offset = uint(Tag::JPEGInterchangeFormat, In::THUMBNAIL)? as usize;
+    let length = uint(Tag::JPEGInterchangeFormatLength, In::THUMBNAIL)? as usize;
+    let bytes = exif.buf().get(offset..offset.checked_add(length)?)?;
+
+    let thumbnail = image::load_from_memory_with_format(bytes, ImageFormat::Jpeg).ok()?;
+    if thumbnail.width().min(thumbnail.height()) < min_side {
+        return None;
+    }
+
+    // thumbnails are sometimes letterboxed to 160x120, which would shift every hash
+    if let (Some(primary_width), Some(primary_height)) = (
+        uint(Tag::PixelXDimension, In::PRIMARY),
+        uint(Tag::PixelYDimension, In::PRIMARY),
+    ) && primary_height > 0 {
+        let primary = primary_width as f64 / primary_height as f64;
+        let thumb = thumbnail.width() as f64 / thumbnail.height() as f64;
+        if (primary - thumb).abs() / primary > ASPECT_TOLERANCE {
+            return None;
+        }
+    }
+
+    Some(thumbnail)
+}
+
+fn scaled_jpeg<R: BufRead>(reader: &mut R, min_side: u32) -> Option<DynamicImage> {
+    let mut decoder = jpeg_decoder::Decoder::new(reader);
+
+    let requested = min_side.min(u16::MAX as u32) as u16;
+    let (width, height) = decoder.scale(requested, requested).ok()?;
+    let pixels = decoder.decode().ok()?;
+    let (width, height) = (width as u32, height as u32);
+
+    match decoder.info()?.pixel_format {
+        PixelFormat::L8 => GrayImage::from_raw(width, height, pixels).map(DynamicImage::ImageLuma8),
+        PixelFormat::RGB24 => RgbImage::from_raw(width, height, pixels).map(DynamicImage::ImageRgb8),
+        // rare in practice, leave the colour conversion to the full decoder
+        PixelFormat::L16 | PixelFormat::CMYK32 => None,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::time::{Duration, Instant};
+    use image::imageops;
+    use super::*;
+
+    fn jpegs(dir: &Path, out: &mut Vec<PathBuf>) {
+        for entry in fs::read_dir(dir).expect("Could not read pictures directory").flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                jpegs(&path, out);
+            } else if path.extension().map(|e| e.to_ascii_lowercase()) == Some("jpg".into()) {
+                out.push(path);
+            }
+        }
+    }
+
+    #[test]
+    fn fast_decode_is_smaller_but_close_to_full() {
+        let path = Path::new("pictures/cats/robust/cats_scale_300.jpg");
+
+        let full = decode(path, 32, 32, DecodeMode::Full).expect("full decode failed");
+        let fast = decode(path, 32, 32, DecodeMode::Fast).expect("fast decode failed");
+        assert!(fast.width() < full.width(), "fast decode did not reduce the resolution");
+        assert!(fast.width().min(fast.height()) >= 32 * MIN_OVERSAMPLING);
+
+        let small = |img: DynamicImage| img.grayscale().resize_exact(32, 32, imageops::Lanczos3).into_luma8();
+        let (full, fast) = (small(full), small(fast));
+        let mean_diff = full.pixels().zip(fast.pixels())
+            .map(|(a, b)| (a[0] as f64 - b[0] as f64).abs())
+            .sum::<f64>() / (32.0 * 32.0);
+        assert!(mean_diff < 2.0, "thumbnails differ too much: {mean_diff}");
+    }
+
+    // cargo test --release -- --ignored --nocapture benchmark_decode_modes
+    #[test]
+    #[ignore]
+    fn benchmark_decode_modes() {
+        let mut paths = Vec::new();
+        jpegs(Path::new("pictures"), &mut paths);
+
+        for mode in [DecodeMode::Full, DecodeMode::Fast] {
+            let mut total = Duration::ZERO;
+            for path in &paths {
+                let start = Instant::now();
+                decode(path, 32, 32, mode).expect("decode failed");
+                total += start.elapsed();
+            }
+            println!("{mode:>4}: {} files in {:.3}s", paths.len(), total.as_secs_f64());
+        }
+    }
+}
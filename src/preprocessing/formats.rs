@@ -0,0 +1,107 @@
+use std::path::Path;
+use image::{DynamicImage, ImageError};
+use super::raw::RAW_EXTENSIONS;
+use super::video::VIDEO_EXTENSIONS;
+
+/// Formats the `image` crate decodes in every build.
+pub const BUILTIN_FORMATS: [&str; 14] = [
+    "JPEG", "PNG", "GIF", "WebP", "BMP", "TIFF", "ICO", "TGA", "PNM", "QOI", "OpenEXR", "HDR", "DDS", "farbfeld",
+];
+
+type Decoder = fn(&Path) -> Result<DynamicImage, ImageError>;
+
+/// A format that needs a cargo feature, and possibly a system library or tool.
+pub struct ExtraFormat {
+    pub name: &'static str,
+    pub extensions: &'static [&'static str],
+    pub feature: &'static str,
+    pub enabled: bool,
+    /// What has to be installed when the program runs, beyond the libraries linked at build time.
+    pub runtime: Option<&'static str>,
+    // None for formats that are not decoded into a single image
+    decoder: Option<Decoder>,
+}
+
+#[cfg(feature = "heif")]
+const HEIF_DECODER: Option<Decoder> = Some(super::heif::decode);
+#[cfg(not(feature = "heif"))]
+const HEIF_DECODER: Option<Decoder> = None;
+#[cfg(feature = "jxl")]
+const JXL_DECODER: Option<Decoder> = Some(super::jxl::decode);
+#[cfg(not(feature = "jxl"))]
+const JXL_DECODER: Option<Decoder> = None;
+#[cfg(feature = "raw")]
+const RAW_DECODER: Option<Decoder> = Some(super::raw::decode);
+#[cfg(not(feature = "raw"))]
+const RAW_DECODER: Option<Decoder> = None;
+
+pub const EXTRA_FORMATS: [ExtraFormat; 5] = [
+    ExtraFormat { name: "HEIC/HEIF", extensions: &["heic", "heif", "hif"], feature: "heif", enabled: cfg!(feature = "heif"), runtime: None, decoder: HEIF_DECODER },
+    ExtraFormat { name: "AVIF", extensions: &["avif"], feature: "heif", enabled: cfg!(feature = "heif"), runtime: Some("a libheif with an AV1 decoder"), decoder: HEIF_DECODER },
+    ExtraFormat { name: "JPEG XL", extensions: &["jxl"], feature: "jxl", enabled: cfg!(feature = "jxl"), runtime: Some("djxl of libjxl on the PATH"), decoder: JXL_DECODER },
+    ExtraFormat { name: "camera RAW", extensions: &RAW_EXTENSIONS, feature: "raw", enabled: cfg!(feature = "raw"), runtime: None, decoder: RAW_DECODER },
+    ExtraFormat { name: "video", extensions: &VIDEO_EXTENSIONS, feature: "video", enabled: cfg!(feature = "video"), runtime: Some("ffmpeg on the PATH"), decoder: None },
+];
+
+/// Names of every format this build can read.
+pub fn supported_formats() -> Vec<&'static str> {
+    let extra = EXTRA_FORMATS.iter().filter(|format| format.enabled).map(|format| format.name);
+    BUILTIN_FORMATS.into_iter().chain(extra).collect()
+}
+
+/// Supported and optional formats, as shown in the help of the command line, with the tools some of
+/// them need when the program runs.
+pub fn describe() -> String {
+    let supported: Vec<String> = BUILTIN_FORMATS
+        .iter()
+        .map(|name| name.to_string())
+        .chain(EXTRA_FORMATS.iter().filter(|format| format.enabled).map(|format| match format.runtime {
+            Some(runtime) => format!("{} (needs {runtime})", format.name),
+            None => format.name.to_string(),
+        }))
+        .collect();
+    let mut text = format!("Supported formats: {}", supported.join(", "));
+    let disabled: Vec<String> = EXTRA_FORMATS
+        .iter()
+        .filter(|format| !format.enabled)
+        .map(|format| match format.runtime {
+            Some(runtime) => format!("{} (--features {}, needs {runtime})", format.name, format.feature),
+            None => format!("{} (--features {})", format.name, format.feature),
+        })
+        .collect();
+    if !disabled.is_empty() {
+        text.push_str(&format!("\nAvailable when built with: {}", disabled.join(", ")));
+    }
+    text
+}
+
+/// Decodes `path` with the decoder of an enabled extra format; `None` when the
+/// extension belongs to none of them, so the `image` crate should try.
+pub fn decode_extra(path: &Path) -> Option<Result<DynamicImage, ImageError>> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    let format = EXTRA_FORMATS
+        .iter()
+        .find(|format| format.enabled && format.extensions.contains(&extension.as_str()))?;
+    format.decoder.map(|decoder| decoder(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disabled_formats_are_left_to_the_image_crate() {
+        let listed = supported_formats();
+        for format in &EXTRA_FORMATS {
+            assert_eq!(listed.contains(&format.name), format.enabled);
+            if let Some(runtime) = format.runtime {
+                assert!(describe().contains(&format!("needs {runtime}")));
+            }
+            if !format.enabled {
+                assert!(describe().contains(&format!("--features {}", format.feature)));
+                assert!(decode_extra(&Path::new("photo").with_extension(format.extensions[0])).is_none());
+            }
+        }
+        assert!(decode_extra(Path::new("test_images/test1.png")).is_none());
+    }
+}
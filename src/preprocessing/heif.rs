@@ -0,0 +1,27 @@
+use std::path::Path;
+use image::{DynamicImage, ImageError, RgbImage};
+use image::error::{DecodingError, ImageFormatHint};
+use libheif_rs::{ColorSpace, HeifContext, HeifError, LibHeif, RgbChroma};
+
+/// Decodes the primary image of the HEIF or AVIF file at `path` as 8-bit RGB; AVIF
+/// needs a libheif built with an AV1 decoder.
+pub fn decode(path: &Path) -> Result<DynamicImage, ImageError> {
+    let decoding_error = |err: HeifError| ImageError::Decoding(DecodingError::new(ImageFormatHint::Name("HEIF".into()), err));
+    let invalid = |message: &str| ImageError::Decoding(DecodingError::new(ImageFormatHint::Name("HEIF".into()), message));
+
+    let name = path.to_str().ok_or_else(|| invalid("path is not valid UTF-8"))?;
+    let context = HeifContext::read_from_file(name).map_err(decoding_error)?;
+    let handle = context.primary_image_handle().map_err(decoding_error)?;
+    let image = LibHeif::new()
+        .decode(&handle, ColorSpace::Rgb(RgbChroma::Rgb), None)
+        .map_err(decoding_error)?;
+
+    // rows of the interleaved plane may be padded up to `stride` bytes
+    let plane = image.planes().interleaved.ok_or_else(|| invalid("no interleaved RGB plane"))?;
+    let row = plane.width as usize * 3;
+    if plane.stride == 0 || plane.stride < row || plane.data.len() < plane.stride * (plane.height as usize).saturating_sub(1) + row {
+        return Err(invalid("the RGB plane is smaller than its size says"));
+    }
+    let pixels: Vec<u8> = plane.data.chunks(plane.stride).take(plane.height as usize).flat_map(|line| &line[..row]).copied().collect();
+    RgbImage::from_raw(plane.width, plane.height, pixels).map(DynamicImage::ImageRgb8).ok_or_else(|| invalid("the RGB plane is smaller than its size says"))
+}
@@ -0,0 +1,35 @@
+use std::path::Path;
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use image::{DynamicImage, ImageError};
+
+// the reference decoder of libjxl; it writes its output to a file
+const DJXL: &str = "djxl";
+
+// files are decoded in parallel, every call needs its own output file
+static NEXT_OUTPUT: AtomicUsize = AtomicUsize::new(0);
+
+/// Decodes the JPEG XL file at `path` with the system `djxl` through a temporary PNG.
+pub fn decode(path: &Path) -> Result<DynamicImage, ImageError> {
+    let output = std::env::temp_dir().join(format!(
+        "perceptual_hashing_{}_{}.png",
+        std::process::id(),
+        NEXT_OUTPUT.fetch_add(1, Ordering::Relaxed)
+    ));
+    let result = Command::new(DJXL)
+        .arg(path)
+        .arg(&output)
+        .arg("--quiet")
+        .output();
+
+    let img = match result {
+        Ok(result) if result.status.success() => image::open(&output),
+        Ok(result) => Err(ImageError::IoError(std::io::Error::other(format!(
+            "djxl failed: {}",
+            String::from_utf8_lossy(&result.stderr).trim()
+        )))),
+        Err(err) => Err(ImageError::IoError(err)),
+    };
+    std::fs::remove_file(&output).ok();
+    img
+}
@@ -1 +1,11 @@
-pub mod preprocessor;
\ No newline at end of file
+pub mod preprocessor;
+pub mod decoder;
+pub mod animation;
+pub mod video;
+pub mod formats;
+pub mod archive;
+pub mod raw;
+#[cfg(feature = "heif")]
+pub mod heif;
+#[cfg(feature = "jxl")]
+pub mod jxl;
\ No newline at end of file
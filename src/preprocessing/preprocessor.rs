@@ -1,11 +1,255 @@
-use image::{DynamicImage, GrayImage, ImageError, ImageReader, imageops};
+use image::{DynamicImage, GrayImage, ImageError, ImageReader, Luma, Rgb, RgbImage, imageops};
+use std::io::{BufRead, Cursor, Seek, SeekFrom};
 use std::path::Path;
+use super::decoder::{DecodeMode, decode, decode_bytes, decode_reader, may_reduce};
+use super::archive::{read_entry, split_locator};
+use super::animation::{FrameSampling, decode_frames, decode_frames_reader};
+#[cfg(feature = "video")]
+use super::video::{decode_video, is_video};
+
+/// How transparent pixels are treated before the image is converted to grayscale.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlphaPolicy {
+    /// Drop the alpha channel and keep whatever RGB is stored under it.
+    Ignore,
+    /// Composite the image over a solid background colour.
+    Background(Rgb<u8>),
+    /// Fill transparent areas with the mean luminance of the visible ones,
+    /// so only the opaque shape contributes to the hash.
+    Mask,
+}
+
+pub const WHITE: Rgb<u8> = Rgb([255, 255, 255]);
+pub const BLACK: Rgb<u8> = Rgb([0, 0, 0]);
+
+impl AlphaPolicy {
+    // order used when cycling through the policies in the TUI
+    pub fn next(self) -> Self {
+        match self {
+            AlphaPolicy::Background(WHITE) => AlphaPolicy::Background(BLACK),
+            AlphaPolicy::Background(BLACK) => AlphaPolicy::Mask,
+            AlphaPolicy::Mask => AlphaPolicy::Ignore,
+            AlphaPolicy::Ignore | AlphaPolicy::Background(_) => AlphaPolicy::Background(WHITE),
+        }
+    }
+}
+
+impl Default for AlphaPolicy {
+    fn default() -> Self {
+        AlphaPolicy::Background(WHITE)
+    }
+}
+
+impl std::fmt::Display for AlphaPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AlphaPolicy::Ignore => write!(f, "ignore"),
+            AlphaPolicy::Background(WHITE) => write!(f, "white"),
+            AlphaPolicy::Background(BLACK) => write!(f, "black"),
+            AlphaPolicy::Background(Rgb([r, g, b])) => write!(f, "#{r:02x}{g:02x}{b:02x}"),
+            AlphaPolicy::Mask => write!(f, "mask"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PreprocessOptions {
+    pub alpha: AlphaPolicy,
+    pub decode: DecodeMode,
+    pub frames: FrameSampling,
+}
+
+/// Decodes the image and flattens its alpha channel; `min_size` is the largest
+/// hash input that will later be produced from it.
+pub fn load(path: &Path, min_size: u32, options: &PreprocessOptions) -> Result<DynamicImage, ImageError> {
+    if split_locator(path).is_some() {
+        return load_bytes(&read_entry(path)?, min_size, options);
+    }
+    let img: DynamicImage = decode(path, min_size, min_size, options.decode)?;
+    Ok(flatten_alpha(img, options.alpha))
+}
+
+/// Like `load`, for an image that is streamed or already in memory.
+pub fn load_reader<R: BufRead + Seek>(reader: R, min_size: u32, options: &PreprocessOptions) -> Result<DynamicImage, ImageError> {
+    let img: DynamicImage = decode_reader(reader, min_size, min_size, options.decode)?;
+    Ok(flatten_alpha(img, options.alpha))
+}
+
+/// `load_reader` over a byte buffer.
+pub fn load_bytes(bytes: &[u8], min_size: u32, options: &PreprocessOptions) -> Result<DynamicImage, ImageError> {
+    load_reader(Cursor::new(bytes), min_size, options)
+}
+
+/// Like `load`, but returns the sampled frames of animations and, with the `video` feature,
+/// of videos; still images give a single frame.
+pub fn load_frames(path: &Path, min_size: u32, options: &PreprocessOptions) -> Result<Vec<DynamicImage>, ImageError> {
+    if split_locator(path).is_some() {
+        return load_frames_reader(Cursor::new(read_entry(path)?), min_size, options);
+    }
+    // videos are always sampled, their first frame is too often a black one
+    #[cfg(feature = "video")]
+    if is_video(path) {
+        return decode_video(path);
+    }
+
+    if options.frames != FrameSampling::First
+        && let Some(frames) = decode_frames(path, options.frames)? {
+            return Ok(frames.into_iter().map(|frame| flatten_alpha(frame, options.alpha)).collect());
+        }
+    Ok(vec![load(path, min_size, options)?])
+}
+
+/// Like `load_frames`, for an image that is streamed or already in memory; videos need a path.
+pub fn load_frames_reader<R: BufRead + Seek>(mut reader: R, min_size: u32, options: &PreprocessOptions) -> Result<Vec<DynamicImage>, ImageError> {
+    let start = reader.stream_position()?;
+    if options.frames != FrameSampling::First
+        && let Some(frames) = decode_frames_reader(&mut reader, options.frames)? {
+            return Ok(frames.into_iter().map(|frame| flatten_alpha(frame, options.alpha)).collect());
+        }
+    reader.seek(SeekFrom::Start(start))?;
+    Ok(vec![load_reader(reader, min_size, options)?])
+}
+
+/// Like `load_frames`, but also returns the frames at full resolution when the decode mode reduced them,
+/// for the hashes that are defined on the original pixels; `None` when the frames are the originals.
+pub fn load_frames_and_originals(path: &Path, min_size: u32, options: &PreprocessOptions) -> Result<(Vec<DynamicImage>, Option<Vec<DynamicImage>>), ImageError> {
+    if split_locator(path).is_some() {
+        return load_frames_and_originals_reader(Cursor::new(read_entry(path)?), min_size, options);
+    }
+    let frames = load_frames(path, min_size, options)?;
+    // animations and videos are always decoded in full, only a still image can be reduced
+    if frames.len() > 1 || !may_reduce(ImageReader::open(path)?.format(), options.decode) {
+        return Ok((frames, None));
+    }
+    let original = load(path, 0, &PreprocessOptions { decode: DecodeMode::Full, ..*options })?;
+    Ok((frames, Some(vec![original])))
+}
+
+/// Like `load_frames_and_originals`, for an image that is streamed or already in memory.
+pub fn load_frames_and_originals_reader<R: BufRead + Seek>(mut reader: R, min_size: u32, options: &PreprocessOptions) -> Result<(Vec<DynamicImage>, Option<Vec<DynamicImage>>), ImageError> {
+    let start = reader.stream_position()?;
+    let frames = load_frames_reader(&mut reader, min_size, options)?;
+    reader.seek(SeekFrom::Start(start))?;
+    let format = ImageReader::new(&mut reader).with_guessed_format()?.format();
+    if frames.len() > 1 || !may_reduce(format, options.decode) {
+        return Ok((frames, None));
+    }
+    reader.seek(SeekFrom::Start(start))?;
+    let original = load_reader(reader, 0, &PreprocessOptions { decode: DecodeMode::Full, ..*options })?;
+    Ok((frames, Some(vec![original])))
+}
+
+/// The image shown for `path` in the TUI: the file as stored, or the first sampled frame of a video.
+pub fn load_preview(path: &Path) -> Result<DynamicImage, ImageError> {
+    if split_locator(path).is_some() {
+        return decode_bytes(&read_entry(path)?, 0, 0, DecodeMode::Full);
+    }
+    #[cfg(feature = "video")]
+    if is_video(path) {
+        return decode_video(path).map(|frames| frames.into_iter().next().expect("at least one frame"));
+    }
+    decode(path, 0, 0, DecodeMode::Full)
+}
 
 pub fn preprocess(path: &Path, height: u32, width: u32) -> Result<GrayImage, ImageError> {
-    let img: DynamicImage = ImageReader::open(path)?.decode()?;
+    let img = load(path, height.max(width), &PreprocessOptions::default())?;
+    Ok(preprocess_image(&img, height, width))
+}
 
-    Ok(img
-        .grayscale()
+pub fn preprocess_image(img: &DynamicImage, height: u32, width: u32) -> GrayImage {
+    img.grayscale()
         .resize_exact(width, height, imageops::Lanczos3)
-        .into_luma8())
-}
\ No newline at end of file
+        .into_luma8()
+}
+
+/// Removes the alpha channel according to `policy`; opaque images are returned untouched.
+pub fn flatten_alpha(img: DynamicImage, policy: AlphaPolicy) -> DynamicImage {
+    if !img.color().has_alpha() {
+        return img;
+    }
+
+    match policy {
+        AlphaPolicy::Ignore => img,
+        AlphaPolicy::Background(background) => {
+            let rgba = img.into_rgba8();
+            let flattened = RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+                let [r, g, b, a] = rgba.get_pixel(x, y).0;
+                Rgb([
+                    blend(r, background[0], a),
+                    blend(g, background[1], a),
+                    blend(b, background[2], a),
+                ])
+            });
+            DynamicImage::ImageRgb8(flattened)
+        }
+        AlphaPolicy::Mask => {
+            let luma_alpha = img.into_luma_alpha8();
+
+            // alpha-weighted mean of the visible pixels
+            let (weighted_sum, alpha_sum) = luma_alpha
+                .pixels()
+                .fold((0u64, 0u64), |(sum, total), p| {
+                    (sum + p[0] as u64 * p[1] as u64, total + p[1] as u64)
+                });
+            let mean = weighted_sum.checked_div(alpha_sum).unwrap_or(0) as u8;
+
+            let flattened = GrayImage::from_fn(luma_alpha.width(), luma_alpha.height(), |x, y| {
+                let [l, a] = luma_alpha.get_pixel(x, y).0;
+                Luma([blend(l, mean, a)])
+            });
+            DynamicImage::ImageLuma8(flattened)
+        }
+    }
+}
+
+// `foreground` over `background` with 8-bit alpha
+fn blend(foreground: u8, background: u8, alpha: u8) -> u8 {
+    let alpha = alpha as u32;
+    ((foreground as u32 * alpha + background as u32 * (255 - alpha) + 127) / 255) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, Rgba, RgbaImage};
+    use super::*;
+
+    // left half opaque grey, right half fully transparent with a random-looking colour underneath
+    fn half_transparent(hidden: [u8; 3]) -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(4, 2, |x, _| {
+            if x < 2 {
+                Rgba([100, 100, 100, 255])
+            } else {
+                Rgba([hidden[0], hidden[1], hidden[2], 0])
+            }
+        }))
+    }
+
+    #[test]
+    fn hidden_rgb_does_not_leak_through_flattening() {
+        for policy in [AlphaPolicy::Background(WHITE), AlphaPolicy::Background(BLACK), AlphaPolicy::Mask] {
+            let a = flatten_alpha(half_transparent([255, 0, 0]), policy).into_luma8();
+            let b = flatten_alpha(half_transparent([0, 0, 255]), policy).into_luma8();
+            assert_eq!(a, b, "hidden colour influenced the result for policy {policy}");
+        }
+    }
+
+    #[test]
+    fn transparent_pixels_take_the_policy_value() {
+        let white = flatten_alpha(half_transparent([1, 2, 3]), AlphaPolicy::Background(WHITE)).into_rgb8();
+        assert_eq!(white.get_pixel(3, 0), &WHITE);
+        assert_eq!(white.get_pixel(0, 0), &Rgb([100, 100, 100]));
+
+        let black = flatten_alpha(half_transparent([1, 2, 3]), AlphaPolicy::Background(BLACK)).into_rgb8();
+        assert_eq!(black.get_pixel(3, 1), &BLACK);
+
+        // mask fills the transparent half with the mean of the opaque half
+        let mask = flatten_alpha(half_transparent([1, 2, 3]), AlphaPolicy::Mask).into_luma8();
+        assert_eq!(mask.get_pixel(3, 0), mask.get_pixel(0, 0));
+    }
+
+    #[test]
+    fn ignore_keeps_alpha_channel() {
+        let img = flatten_alpha(half_transparent([1, 2, 3]), AlphaPolicy::Ignore);
+        assert!(img.color().has_alpha());
+    }
+}
@@ -0,0 +1,210 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use image::{DynamicImage, ImageError, ImageFormat};
+use image::error::{DecodingError, ImageFormatHint};
+
+/// Extensions of the camera RAW files whose embedded preview is decoded.
+pub const RAW_EXTENSIONS: [&str; 12] = ["cr2", "nef", "nrw", "dng", "arw", "srf", "sr2", "orf", "rw2", "pef", "raf", "srw"];
+
+// TIFF tags that point at embedded images
+const COMPRESSION: u16 = 0x103;
+const PHOTOMETRIC: u16 = 0x106;
+const STRIP_OFFSETS: u16 = 0x111;
+const STRIP_BYTE_COUNTS: u16 = 0x117;
+const SUB_IFDS: u16 = 0x14a;
+const JPEG_OFFSET: u16 = 0x201;
+const JPEG_LENGTH: u16 = 0x202;
+// old-style and new-style JPEG compression
+const JPEG_COMPRESSIONS: [u32; 2] = [6, 7];
+// photometric interpretations of sensor data, which is never a usable preview
+const CFA: u32 = 32803;
+const LINEAR_RAW: u32 = 34892;
+// guards against malformed files whose IFDs point at each other
+const MAX_IFDS: usize = 64;
+
+/// Decodes the largest JPEG preview the camera embedded in the RAW file at `path`;
+/// the sensor data itself is not demosaiced.
+pub fn decode(path: &Path) -> Result<DynamicImage, ImageError> {
+    decode_preview(&std::fs::read(path)?)
+}
+
+fn decode_preview(data: &[u8]) -> Result<DynamicImage, ImageError> {
+    let mut candidates = preview_candidates(data);
+    candidates.sort_by_key(|&(_, length)| std::cmp::Reverse(length));
+
+    // the biggest candidate can still be lossless JPEG sensor data, which fails to decode
+    candidates
+        .into_iter()
+        .find_map(|(offset, length)| image::load_from_memory_with_format(&data[offset..offset + length], ImageFormat::Jpeg).ok())
+        .ok_or_else(|| ImageError::Decoding(DecodingError::new(ImageFormatHint::Name("RAW".into()), "no embedded JPEG preview")))
+}
+
+// (offset, length) of every embedded JPEG
+fn preview_candidates(data: &[u8]) -> Vec<(usize, usize)> {
+    let candidates = if data.starts_with(b"FUJIFILMCCD-RAW") {
+        // RAF keeps the offset and length of its preview at fixed places in the header
+        let reader = Reader { data, little_endian: false };
+        reader.u32(84).zip(reader.u32(88)).into_iter().collect()
+    } else {
+        tiff_candidates(data)
+    };
+
+    candidates
+        .into_iter()
+        .map(|(offset, length)| (offset as usize, length as usize))
+        .filter(|&(offset, length)| {
+            offset.checked_add(length).is_some_and(|end| end <= data.len()) && data[offset..].starts_with(&[0xff, 0xd8])
+        })
+        .collect()
+}
+
+fn tiff_candidates(data: &[u8]) -> Vec<(u32, u32)> {
+    let little_endian = match data.get(..2) {
+        Some(b"II") => true,
+        Some(b"MM") => false,
+        _ => return Vec::new(),
+    };
+    let reader = Reader { data, little_endian };
+
+    let mut candidates = Vec::new();
+    let mut queue: Vec<u32> = reader.u32(4).into_iter().collect();
+    let mut visited = HashSet::new();
+    while let Some(ifd) = queue.pop() {
+        if ifd == 0 || visited.len() == MAX_IFDS || !visited.insert(ifd) {
+            continue;
+        }
+        let Some(count) = reader.u16(ifd as usize) else {
+            continue;
+        };
+
+        let mut tags = HashMap::new();
+        for i in 0..count as usize {
+            let entry = ifd as usize + 2 + i * 12;
+            let Some(tag) = reader.u16(entry) else {
+                break;
+            };
+            if tag == SUB_IFDS {
+                queue.extend(reader.values(entry));
+            } else if let Some(&value) = reader.values(entry).first() {
+                tags.insert(tag, value);
+            }
+        }
+        if let Some(next) = reader.u32(ifd as usize + 2 + count as usize * 12) {
+            queue.push(next);
+        }
+
+        if let (Some(&offset), Some(&length)) = (tags.get(&JPEG_OFFSET), tags.get(&JPEG_LENGTH)) {
+            candidates.push((offset, length));
+        }
+        let is_jpeg = tags.get(&COMPRESSION).is_some_and(|compression| JPEG_COMPRESSIONS.contains(compression));
+        let is_sensor_data = tags.get(&PHOTOMETRIC).is_some_and(|&photometric| photometric == CFA || photometric == LINEAR_RAW);
+        if is_jpeg && !is_sensor_data
+            && let (Some(&offset), Some(&length)) = (tags.get(&STRIP_OFFSETS), tags.get(&STRIP_BYTE_COUNTS)) {
+                candidates.push((offset, length));
+            }
+    }
+    candidates
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    little_endian: bool,
+}
+
+impl Reader<'_> {
+    fn u16(&self, offset: usize) -> Option<u16> {
+        let bytes: [u8; 2] = self.data.get(offset..offset + 2)?.try_into().ok()?;
+        Some(if self.little_endian { u16::from_le_bytes(bytes) } else { u16::from_be_bytes(bytes) })
+    }
+
+    fn u32(&self, offset: usize) -> Option<u32> {
+        let bytes: [u8; 4] = self.data.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if self.little_endian { u32::from_le_bytes(bytes) } else { u32::from_be_bytes(bytes) })
+    }
+
+    // the SHORT, LONG or IFD values of the IFD entry at `entry`; other types are of no use here
+    fn values(&self, entry: usize) -> Vec<u32> {
+        let (Some(kind), Some(count)) = (self.u16(entry + 2), self.u32(entry + 4)) else {
+            return Vec::new();
+        };
+        let size = match kind {
+            3 => 2,
+            4 | 13 => 4,
+            _ => return Vec::new(),
+        };
+        // values that do not fit into the entry are stored elsewhere
+        let start = if count as usize * size <= 4 {
+            entry + 8
+        } else {
+            match self.u32(entry + 8) {
+                Some(offset) => offset as usize,
+                None => return Vec::new(),
+            }
+        };
+        (0..count.min(MAX_IFDS as u32) as usize)
+            .filter_map(|i| match size {
+                2 => self.u16(start + i * 2).map(u32::from),
+                _ => self.u32(start + i * 4),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use image::{Rgb, RgbImage};
+    use super::*;
+
+    fn jpeg(width: u32, height: u32) -> Vec<u8> {
+        let img = RgbImage::from_fn(width, height, |x, y| Rgb([(x * 8) as u8, (y * 8) as u8, 128]));
+        let mut bytes = Cursor::new(Vec::new());
+        img.write_to(&mut bytes, ImageFormat::Jpeg).unwrap();
+        bytes.into_inner()
+    }
+
+    // little endian TIFF: IFD0 with a small JPEG thumbnail, and a SubIFD with a bigger preview strip
+    fn tiff(thumbnail: &[u8], preview: &[u8]) -> Vec<u8> {
+        let ifd0 = 8u32;
+        let sub_ifd = ifd0 + 2 + 3 * 12 + 4;
+        let thumbnail_offset = sub_ifd + 2 + 3 * 12 + 4;
+        let preview_offset = thumbnail_offset + thumbnail.len() as u32;
+
+        let mut data = b"II*\0".to_vec();
+        data.extend(ifd0.to_le_bytes());
+        let entry = |data: &mut Vec<u8>, tag: u16, kind: u16, value: u32| {
+            data.extend(tag.to_le_bytes());
+            data.extend(kind.to_le_bytes());
+            data.extend(1u32.to_le_bytes());
+            data.extend(value.to_le_bytes());
+        };
+        data.extend(3u16.to_le_bytes());
+        entry(&mut data, SUB_IFDS, 13, sub_ifd);
+        entry(&mut data, JPEG_OFFSET, 4, thumbnail_offset);
+        entry(&mut data, JPEG_LENGTH, 4, thumbnail.len() as u32);
+        data.extend(0u32.to_le_bytes());
+        data.extend(3u16.to_le_bytes());
+        entry(&mut data, COMPRESSION, 3, 6);
+        entry(&mut data, STRIP_OFFSETS, 4, preview_offset);
+        entry(&mut data, STRIP_BYTE_COUNTS, 4, preview.len() as u32);
+        data.extend(0u32.to_le_bytes());
+        data.extend(thumbnail);
+        data.extend(preview);
+        data
+    }
+
+    #[test]
+    fn the_biggest_preview_is_decoded() {
+        let data = tiff(&jpeg(16, 8), &jpeg(64, 32));
+        assert_eq!(preview_candidates(&data).len(), 2);
+
+        let preview = decode_preview(&data).unwrap();
+        assert_eq!((preview.width(), preview.height()), (64, 32));
+    }
+
+    #[test]
+    fn files_without_previews_are_errors() {
+        assert!(decode_preview(b"II*\0\x08\0\0\0\0\0").is_err());
+        assert!(decode_preview(b"not a raw file").is_err());
+    }
+}
@@ -0,0 +1,98 @@
+use std::path::Path;
+#[cfg(feature = "video")]
+use std::process::Command;
+#[cfg(feature = "video")]
+use image::{DynamicImage, ImageError, RgbImage};
+#[cfg(feature = "video")]
+use tracing::warn;
+
+/// Extensions of the containers handed to the video decoder.
+pub const VIDEO_EXTENSIONS: [&str; 12] = ["mp4", "m4v", "mov", "mkv", "webm", "avi", "wmv", "flv", "mpg", "mpeg", "3gp", "ts"];
+/// Seconds between two sampled frames. Sampling at a fixed rate instead of at keyframes keeps
+/// re-encodes alignable, as encoders are free to place their keyframes anywhere.
+pub const SAMPLE_INTERVAL: u32 = 1;
+// frames are scaled to fit FRAME_SIZE x FRAME_SIZE by the decoder, keeping their aspect ratio, and padded
+// with black to the square; big enough for every hash input but the Marr-Hildreth one, which is upscaled
+// like any small image
+#[cfg(feature = "video")]
+const FRAME_SIZE: u32 = 256;
+/// Upper bound on the frames kept from one video, i.e. its first 5 minutes.
+pub const MAX_VIDEO_FRAMES: usize = 300;
+// the system decoder; any ffmpeg build with its default decoders will do
+#[cfg(feature = "video")]
+const FFMPEG: &str = "ffmpeg";
+
+/// Whether `path` looks like a video container, judged by its extension.
+pub fn is_video(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| VIDEO_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str()))
+}
+
+/// One frame every `SAMPLE_INTERVAL` seconds of the video at `path`, decoded by the system ffmpeg.
+#[cfg(feature = "video")]
+pub fn decode_video(path: &Path) -> Result<Vec<DynamicImage>, ImageError> {
+    let output = Command::new(FFMPEG)
+        .args(["-nostdin", "-v", "error", "-i"])
+        .arg(path)
+        .args(["-vf", &format!(
+            "fps=1/{SAMPLE_INTERVAL},scale={FRAME_SIZE}:{FRAME_SIZE}:force_original_aspect_ratio=decrease,pad={FRAME_SIZE}:{FRAME_SIZE}:(ow-iw)/2:(oh-ih)/2"
+        )])
+        // one frame more than is kept tells whether the video is longer
+        .args(["-frames:v", &(MAX_VIDEO_FRAMES + 1).to_string()])
+        .args(["-f", "rawvideo", "-pix_fmt", "rgb24", "pipe:1"])
+        .output()?;
+    if !output.status.success() {
+        let message = String::from_utf8_lossy(&output.stderr);
+        return Err(ImageError::IoError(std::io::Error::other(format!("ffmpeg failed: {}", message.trim()))));
+    }
+
+    let frame_len = (FRAME_SIZE * FRAME_SIZE * 3) as usize;
+    let chunks = output.stdout.chunks_exact(frame_len);
+    if chunks.len() > MAX_VIDEO_FRAMES {
+        warn!(path = %path.display(), max_frames = MAX_VIDEO_FRAMES, "only the first sampled frames of the video are hashed");
+    }
+    let frames: Vec<DynamicImage> = chunks
+        .take(MAX_VIDEO_FRAMES)
+        .map(|frame| DynamicImage::ImageRgb8(RgbImage::from_raw(FRAME_SIZE, FRAME_SIZE, frame.to_vec()).expect("chunk has the frame size")))
+        .collect();
+    if frames.is_empty() {
+        return Err(ImageError::IoError(std::io::Error::other("ffmpeg decoded no frames")));
+    }
+    Ok(frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn videos_are_recognised_by_extension() {
+        assert!(is_video(Path::new("clips/holiday.MP4")));
+        assert!(is_video(Path::new("clip.webm")));
+        assert!(!is_video(Path::new("test_images/test1.png")));
+        assert!(!is_video(Path::new("mp4")));
+    }
+
+    // needs ffmpeg on the PATH: cargo test --features video -- --ignored
+    #[cfg(feature = "video")]
+    #[test]
+    #[ignore]
+    fn frames_are_sampled_every_interval() {
+        let path = std::env::temp_dir().join(format!("perceptual_hashing_{}_sampled.mp4", std::process::id()));
+        let status = Command::new(FFMPEG)
+            .args(["-nostdin", "-v", "error", "-y", "-f", "lavfi", "-i", "testsrc=duration=5:size=320x240:rate=25"])
+            .arg(&path)
+            .status()
+            .expect("ffmpeg is installed");
+        assert!(status.success());
+
+        let frames = decode_video(&path).unwrap();
+        // the fps filter may round the last second up
+        assert!((5..=6).contains(&frames.len()), "{} frames", frames.len());
+        assert_eq!(frames[0].width(), FRAME_SIZE);
+        // 320x240 fits as 256x192, with black bars above and below
+        assert!(frames[0].to_rgb8().get_pixel(128, 10).0.iter().all(|&c| c < 8));
+        std::fs::remove_file(path).ok();
+    }
+}
@@ -0,0 +1,56 @@
+//! Fixtures shared by the tests of several modules.
+
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use flate2::write::GzEncoder;
+use image::{Delay, Frame, Rgba, RgbaImage};
+use image::codecs::gif::GifEncoder;
+use crate::preprocessing::archive::ArchiveKind;
+
+// writes a GIF whose frames are flat images of the given grey levels
+pub(crate) fn write_gif(name: &str, levels: &[u8]) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("perceptual_hashing_{}_{name}.gif", std::process::id()));
+    let mut encoder = GifEncoder::new(File::create(&path).unwrap());
+    let frames = levels.iter().map(|&level| {
+        // a gradient, so that frames also differ in structure and not just in brightness
+        let img = RgbaImage::from_fn(32, 32, |x, y| {
+            let v = level.saturating_add(((x + y) * 2) as u8);
+            Rgba([v, v, v, 255])
+        });
+        Frame::from_parts(img, 0, 0, Delay::from_numer_denom_ms(100, 1))
+    });
+    encoder.encode_frames(frames).unwrap();
+    path
+}
+
+// writes `entries` into a new archive of the kind its name says
+pub(crate) fn write_archive(path: &Path, entries: &[(&str, &[u8])]) {
+    let file = File::create(path).unwrap();
+    match ArchiveKind::of(path).unwrap() {
+        ArchiveKind::Zip => {
+            let mut zip = zip::ZipWriter::new(file);
+            zip.add_directory("empty/", zip::write::SimpleFileOptions::default()).unwrap();
+            for (entry, bytes) in entries {
+                zip.start_file(*entry, zip::write::SimpleFileOptions::default()).unwrap();
+                zip.write_all(bytes).unwrap();
+            }
+            zip.finish().unwrap();
+        }
+        kind => {
+            let writer: Box<dyn Write> = match kind {
+                ArchiveKind::TarGz => Box::new(GzEncoder::new(file, flate2::Compression::default())),
+                _ => Box::new(file),
+            };
+            let mut tar = tar::Builder::new(writer);
+            for (entry, bytes) in entries {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(bytes.len() as u64);
+                header.set_mode(0o644);
+                header.set_cksum();
+                tar.append_data(&mut header, entry, *bytes).unwrap();
+            }
+            tar.into_inner().unwrap().flush().unwrap();
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use ratatui::style::Color;
+use color_eyre::{Result, eyre::eyre};
+
+// name in the [theme] section of the configuration and the default colour
+const ROLES: [(&str, &str); 7] = [
+    ("keys", "blue"),
+    ("title", "cyan"),
+    ("highlight", "yellow"),
+    ("directory", "red"),
+    ("muted", "darkgray"),
+    ("selection", "blue"),
+    ("similar", "green"),
+];
+
+/// The `[theme]` section with every colour at its default.
+pub fn defaults() -> BTreeMap<String, String> {
+    ROLES.iter().map(|(role, color)| (role.to_string(), color.to_string())).collect()
+}
+
+/// Colours of the TUI, from the `[theme]` section of the configuration.
+#[derive(Debug, Clone, Copy)]
+pub struct Theme {
+    /// Keys in the hints.
+    pub keys: Color,
+    pub title: Color,
+    /// Selected entry of the folder explorer, timings and status messages.
+    pub highlight: Color,
+    /// Selected folder of the folder explorer and the hint how to choose it.
+    pub directory: Color,
+    /// Secondary text, like the readable formats or the progress details.
+    pub muted: Color,
+    /// Background of the selected file.
+    pub selection: Color,
+    /// Background of the selected similar file, the algorithm and the progress bar.
+    pub similar: Color,
+}
+
+impl Theme {
+    /// Roles missing from `colors` keep their default; colours are names, `#rrggbb` or indexes of the 256 colour palette.
+    pub fn new(colors: &BTreeMap<String, String>) -> Result<Self> {
+        if let Some(role) = colors.keys().find(|role| !ROLES.iter().any(|(known, _)| known == role)) {
+            return Err(eyre!("theme.{role}: unknown colour role"));
+        }
+        let color = |role: &str| {
+            let default = ROLES.iter().find(|(known, _)| *known == role).map_or("", |(_, color)| color);
+            let name = colors.get(role).map_or(default, String::as_str);
+            Color::from_str(name).map_err(|_| eyre!("theme.{role}: unknown colour {name:?}"))
+        };
+        Ok(Theme {
+            keys: color("keys")?,
+            title: color("title")?,
+            highlight: color("highlight")?,
+            directory: color("directory")?,
+            muted: color("muted")?,
+            selection: color("selection")?,
+            similar: color("similar")?,
+        })
+    }
+}
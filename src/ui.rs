@@ -1,17 +1,27 @@
 use std::path::PathBuf;
 use std::time::{Duration, Instant};
-use std::sync::mpsc::{Receiver, channel};
+use std::sync::mpsc::{Receiver, TryRecvError, channel};
 use std::thread::spawn;
 use std::cmp::max;
+use std::fs::File;
+use std::io::{BufWriter, Write};
 
 use ratatui::{prelude::*, widgets::*, Frame, symbols::{border, Marker}, widgets::canvas::Canvas};
 use ratatui_explorer::{FileExplorer, Theme};
 use ratatui_image::{picker::Picker, protocol::StatefulProtocol};
 use color_eyre::{eyre::Ok, Result, Report};
 use crossterm::event::{poll, read, Event, KeyCode, KeyEventKind};
-use image::ImageReader;
-use crate::{app::{App, CurrentScreen}, handler::similarity_analyzer::SimilarityAnalyzer};
-use crate::widgets::{algorithm_chooser::*,letters::*, list::draw_list};
+use tracing::{Level, error, info, warn};
+use perceptual_hashing::preprocessing::{formats, preprocessor::load_preview};
+use crate::app::{App, CurrentScreen};
+use crate::keys::{self, Action};
+use perceptual_hashing::handler::handle::{HashingType, ScanOptions};
+use perceptual_hashing::handler::similarity_analyzer::SimilarityAnalyzer;
+use perceptual_hashing::handler::progress::{CancellationToken, Progress, ProgressEvent};
+use perceptual_hashing::handler::score::ScoreFilter;
+use perceptual_hashing::handler::index;
+use perceptual_hashing::handler::watch::{self, Change, LiveIndex, WatchEvent};
+use crate::widgets::{algorithm_chooser::*,letters::*, list::draw_list, log_viewer::draw_log};
 
 const POLL_DURATION: Duration = Duration::from_millis(50);
 
@@ -20,8 +30,16 @@ pub enum ImageTarget {Mid, Right}
 pub struct UI {
     file_explorer: FileExplorer,
     pub selected_button: usize,
-    button_states: [State; 2],
+    button_states: [State; BUTTONS],
     similarity_analyzer_rx: Option<Receiver<Result<SimilarityAnalyzer, Report>>>,
+    // for the progress of the hashing thread
+    progress_rx: Option<Receiver<ProgressEvent>>,
+    progress: Progress,
+    cancel_token: Option<CancellationToken>,
+    // watch mode on the results screen: changes of the folder and the index they are applied to
+    watch_rx: Option<Receiver<Change>>,
+    watch_cancel: Option<CancellationToken>,
+    live_index: Option<LiveIndex>,
     pub selected_button_2: usize,
     pub selected_column: usize,
     pub files_num_column_1: usize,
@@ -30,66 +48,103 @@ pub struct UI {
     pub image_mid_rx: Option<Receiver<StatefulProtocol>>,
     // for the next image
     pub image_right: Option<StatefulProtocol>,
-    pub image_right_rx: Option<Receiver<StatefulProtocol>>
+    pub image_right_rx: Option<Receiver<StatefulProtocol>>,
+    // log screen: the screen it was opened from, the top line when scrolled back, the most verbose level shown
+    log_return: CurrentScreen,
+    pub log_scroll: Option<usize>,
+    pub log_level: Level,
+    // written by draw_log for scrolling
+    pub log_height: usize,
+    pub log_lines: usize
 }
 
 impl UI {
-    pub fn new() -> Result<Self> {
+    pub fn new(app: &App) -> Result<Self> {
+        let colors = app.settings.theme;
+        let keys = &app.settings.keys;
+        let (select_folder, log, quit) = (keys.label(Action::SelectFolder), keys.label(Action::Log), keys.label(Action::Quit));
         let theme = Theme::default()
             .add_default_title()
-            .with_title_top(|_fe| {
+            .with_title_top(move |_fe| {
                 // Centred title
                 Line::from(
                     Span::styled(
                         "Select a folder with images",
-                        Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD)
+                        Style::default().fg(colors.title).add_modifier(Modifier::BOLD)
                     )
                 ).alignment(Alignment::Center)
             })
-            .with_title_bottom(|fe| {
+            .with_title_top(move |_fe| {
+                // Readable formats aligned to the right
+                Line::from(
+                    Span::styled(
+                        format!("Formats: {}", formats::supported_formats().join(", ")),
+                        Style::default().fg(colors.muted)
+                    )
+                ).alignment(Alignment::Right)
+            })
+            .with_title_bottom(move |fe| {
                 // Number of files aligned to the left
                 Line::from(
                     Span::styled(
                         format!("[{} files]", fe.files().len()),
-                        Style::default().fg(Color::Green)
+                        Style::default().fg(colors.similar)
                     )
                 )
             })
-            .with_title_bottom(|_fe|{
+            .with_title_bottom(move |_fe|{
                 // Info how to pick a folder
                 Line::from(
                     Span::styled(
-                        "Press 'c' to select a folder",
-                        Style::default().fg(Color::Red)
+                        format!("Press {select_folder} to select a folder"),
+                        Style::default().fg(colors.directory)
                     )
                 ).alignment(Alignment::Center)
             })
-            .with_title_bottom(|_fe| {
+            .with_title_bottom(move |_fe| {
                 // Info aligned to the right
                 Line::from(
                     Span::styled(
-                        "Press 'q' to exit",
-                        Style::default().fg(Color::Yellow)
+                        format!("Press {log} for the log, {quit} to exit"),
+                        Style::default().fg(colors.highlight)
                     )
                 ).alignment(Alignment::Right)
             })
             .with_block(Block::default().borders(Borders::ALL).border_type(BorderType::Rounded))
-            .with_highlight_item_style(Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))
-            .with_highlight_dir_style(Style::default().fg(Color::Red).add_modifier(Modifier::BOLD))
+            .with_highlight_item_style(Style::default().fg(colors.highlight).add_modifier(Modifier::BOLD))
+            .with_highlight_dir_style(Style::default().fg(colors.directory).add_modifier(Modifier::BOLD))
             .with_highlight_symbol("> ");
-    
+
+        let mut file_explorer = FileExplorer::with_theme(theme)?;
+        if let Some(dir) = &app.settings.start_dir {
+            file_explorer.set_cwd(dir).map_err(|err| color_eyre::eyre::eyre!("general.start-dir {}: {err}", dir.display()))?;
+        }
+        // the configured algorithm is preselected
+        let selected_button = app.settings.algorithm.and_then(|hashing_type| HashingType::ALL.iter().position(|t| *t == hashing_type)).unwrap_or(0);
+
         Ok(Self {
-            file_explorer: FileExplorer::with_theme(theme)?,
-            selected_button: 0,
-            button_states: [State::Selected, State::Normal],
+            file_explorer,
+            selected_button,
+            button_states: std::array::from_fn(|i| if i == selected_button { State::Selected } else { State::Normal }),
             similarity_analyzer_rx: None,
+            progress_rx: None,
+            progress: Progress::new(),
+            cancel_token: None,
+            watch_rx: None,
+            watch_cancel: None,
+            live_index: None,
             selected_button_2: 0,
             selected_column: 0,
             files_num_column_1: 0,
             image_mid: None,
             image_mid_rx: None,
             image_right: None,
-            image_right_rx: None
+            image_right_rx: None,
+            log_return: CurrentScreen::FolderChoose,
+            log_scroll: None,
+            log_level: Level::TRACE,
+            log_height: 0,
+            log_lines: 0
         })
     }
 
@@ -101,25 +156,262 @@ impl UI {
             ImageTarget::Right => self.image_right_rx = Some(rx),
         }
         spawn(move || {
-            if let std::result::Result::Ok(image_reader) = ImageReader::open(&path)
-                && let std::result::Result::Ok(image_source) = image_reader.decode() {
+            match load_preview(&path) {
+                std::result::Result::Ok(image_source) => {
                     let protocol = picker.new_resize_protocol(image_source);
                     tx.send(protocol).ok();
                 }
+                Err(err) => warn!(path = %path.display(), error = %err, "loading the preview failed"),
+            }
         });
         Ok(())
     }
 
     pub fn load_second_img(&mut self, app: &mut App) -> Result<()> {
-        self.start_async_image_load(app.similarity_analyzer
+        let similar = app.similarity_analyzer
+            .as_ref()
+            .unwrap()
+            .similar_files(&app.items_list.as_ref().unwrap()[self.selected_button], &app.score_options);
+        // the filter may leave nothing to preview
+        match similar.into_iter().nth(self.selected_button_2) {
+            Some(scored) => self.start_async_image_load(scored.path, ImageTarget::Right)?,
+            None => {
+                self.image_right_rx = None;
+                self.image_right = None;
+            }
+        }
+        Ok(())
+    }
+
+    // the similar files changed, start again from the most similar one
+    fn reset_second_column(&mut self, app: &mut App) -> Result<()> {
+        self.selected_button_2 = 0;
+        self.files_num_column_1 = app.similarity_analyzer
+            .as_ref()
+            .unwrap()
+            .similar_files(&app.items_list.as_ref().unwrap()[self.selected_button], &app.score_options)
+            .len();
+        if self.image_mid.is_some() {
+            self.load_second_img(app)?;
+        }
+        Ok(())
+    }
+
+    // writes the distances of the current algorithm next to the working directory
+    fn export(&self, app: &mut App) {
+        let hashing_type = app.hashing_type.unwrap().to_string().to_lowercase().replace(' ', "_");
+        let path = PathBuf::from(format!("similarity_{hashing_type}.csv"));
+        let result = File::create(&path).and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            app.similarity_analyzer.as_ref().unwrap().export_csv(&mut writer, &app.score_options)?;
+            writer.flush()
+        });
+        app.status = Some(match result {
+            std::result::Result::Ok(()) => {
+                info!(path = %path.display(), "exported");
+                format!("Exported to {}", path.display())
+            }
+            Err(err) => {
+                warn!(path = %path.display(), error = %err, "export failed");
+                format!("Export failed: {err}")
+            }
+        });
+    }
+
+    // saves the hashes of the folder next to the working directory, for the command line to query
+    fn save_index(&self, app: &mut App) {
+        let name = app.dir_path.as_ref().and_then(|dir| dir.file_name()).map_or("index".into(), |name| name.to_string_lossy());
+        let path = PathBuf::from(format!("{name}.{}", index::EXTENSION));
+        app.status = Some(match app.similarity_analyzer.as_ref().unwrap().index().save(&path) {
+            std::result::Result::Ok(()) => {
+                info!(path = %path.display(), "index written");
+                format!("Index written to {}", path.display())
+            }
+            Err(err) => {
+                warn!(path = %path.display(), error = %err, "writing the index failed");
+                format!("Writing the index failed: {err}")
+            }
+        });
+    }
+
+    pub fn is_watching(&self) -> bool {
+        self.watch_rx.is_some()
+    }
+
+    // hashes files of the folder as they change; near-duplicates are reported within the filter's
+    // distance, or within a tenth of the largest distance when the filter is not a distance
+    fn toggle_watch(&mut self, app: &mut App) {
+        if self.is_watching() {
+            self.stop_watching();
+            app.status = Some("Stopped watching".to_string());
+            return;
+        }
+        let (dir, hashing_type) = (app.dir_path.clone().unwrap(), app.hashing_type.unwrap());
+        let max_distance = match app.score_options.filter {
+            Some(ScoreFilter::MaxDistance(max_distance)) => max_distance,
+            _ => app.settings.near_duplicate_distance(hashing_type, &app.hashing_options),
+        };
+        let index = app.similarity_analyzer.as_ref().unwrap().index();
+        let (options, hashing_options, filter) = (index.options, index.hashing_options, app.settings.scan_filter.clone());
+        self.live_index = Some(LiveIndex::new(index, hashing_type, app.match_options, max_distance));
+
+        let (tx, rx) = channel();
+        let token = CancellationToken::new();
+        let thread_token = token.clone();
+        spawn(move || {
+            if let Err(err) = watch::watch(&dir, &options, &hashing_options, &filter, &tx, &thread_token) {
+                error!(dir = %dir.display(), error = %err, "watching failed");
+            }
+        });
+        self.watch_rx = Some(rx);
+        self.watch_cancel = Some(token);
+        app.status = Some("Watching the folder".to_string());
+    }
+
+    fn stop_watching(&mut self) {
+        if let Some(token) = self.watch_cancel.take() {
+            token.cancel();
+        }
+        self.watch_rx = None;
+        self.live_index = None;
+    }
+
+    // applies the changes the watcher sent since the last frame to the analyzer and the lists
+    fn apply_watch_changes(&mut self, app: &mut App) -> Result<()> {
+        let Some(rx) = &self.watch_rx else { return Ok(()) };
+        let mut changes = Vec::new();
+        let stopped = loop {
+            match rx.try_recv() {
+                std::result::Result::Ok(change) => changes.push(change),
+                Err(TryRecvError::Empty) => break false,
+                // the watcher could not start or failed
+                Err(TryRecvError::Disconnected) => break true,
+            }
+        };
+
+        let live = self.live_index.as_mut().unwrap();
+        let analyzer = app.similarity_analyzer.as_mut().unwrap();
+        // the latest hashes are looked up once every change is applied
+        let events: Vec<WatchEvent> = changes.into_iter().flat_map(|change| live.apply(change)).collect();
+        let changed = !events.is_empty();
+        for event in events {
+            let record = |path: &PathBuf| live.index.get(path).map(|entry| entry.record.clone());
+            match event {
+                WatchEvent::Added { path } | WatchEvent::Updated { path } => analyzer.update(&path, record(&path)),
+                WatchEvent::Removed { path } => analyzer.update(&path, None),
+                WatchEvent::Renamed { from, to } => {
+                    analyzer.update(&from, None);
+                    analyzer.update(&to, record(&to));
+                }
+                WatchEvent::Duplicate { path, similar, similarity, .. } => {
+                    app.status = Some(format!("{} is {similarity:.1}% similar to {}", path.display(), similar.display()));
+                }
+                WatchEvent::Failed { path, error } => {
+                    app.status = Some(format!("Hashing {} failed: {error}", path.display()));
+                }
+            }
+        }
+        if stopped {
+            self.stop_watching();
+            app.status = Some("Watching stopped".to_string());
+        }
+        if changed {
+            self.refresh_list(app)?;
+        }
+        Ok(())
+    }
+
+    // lists the files of the analyzer again, keeping the selected file selected while it exists
+    fn refresh_list(&mut self, app: &mut App) -> Result<()> {
+        let mut items: Vec<PathBuf> = app.similarity_analyzer.as_ref().unwrap().similarity_map.keys().cloned().collect();
+        if items.is_empty() {
+            self.stop_watching();
+            self.leave_results(app);
+            return Ok(())
+        }
+        items.sort();
+
+        let selected = app.items_list.as_ref().unwrap()[self.selected_button].clone();
+        let position = items.binary_search(&selected).unwrap_or_else(|position| position.min(items.len() - 1));
+        let moved = items[position] != selected;
+        self.selected_button = position;
+        app.items_list = Some(items);
+        if moved && self.image_mid.is_some() {
+            self.start_async_image_load(app.items_list.as_ref().unwrap()[self.selected_button].clone(), ImageTarget::Mid)?;
+        }
+        self.reset_second_column(app)
+    }
+
+    // back to choosing an algorithm; similarity_analyzer is kept, so choosing another one is instant
+    fn leave_results(&mut self, app: &mut App) {
+        self.image_right_rx = None;
+        self.image_right = None;
+        self.image_mid_rx = None;
+        self.image_mid = None;
+        self.files_num_column_1 = 0;
+        self.selected_column = 0;
+        self.selected_button_2 = 0;
+        self.selected_button = 0;
+        app.hashing_type = None;
+        app.time_start = None;
+        app.items_list = None;
+        app.status = None;
+
+        app.current_screen = CurrentScreen::ChooseAnAlgorithm;
+    }
+
+    // switches to the results screen once similarity_analyzer holds distances for app.hashing_type
+    fn show_results(&mut self, app: &mut App) -> Result<()> {
+        self.selected_button = 0; // re-use
+
+        let mut items: Vec<PathBuf> = app.similarity_analyzer
             .as_ref()
             .unwrap()
-            .get_one_file_similarity(&app.items_list.as_ref().unwrap()[self.selected_button])
-            .iter()
-            .filter(|(path, _)| path != &app.items_list.as_ref().unwrap()[self.selected_button])
-            .collect::<Vec<_>>()
-            [self.selected_button_2].0.clone(),
-            ImageTarget::Right)?;
+            .similarity_map
+            .keys()
+            .cloned()
+            .collect();
+
+        if items.len() < 2 { // not enough files
+            self.selected_button = 0;
+            app.hashing_type = None;
+            app.similarity_analyzer = None;
+            app.time_start = None;
+            app.items_list = None;
+
+            app.current_screen = CurrentScreen::FolderChoose;
+            return Ok(())
+        }
+
+        items.sort();
+        app.items_list = Some(items); // to keep consistent list in every iteration
+
+        // initalize structures for photo preview
+        // disabled on windows, won't fail on other unsupported terminals
+        #[cfg(not(target_os = "windows"))]
+        {
+            if let std::result::Result::Ok(picker) = Picker::from_query_stdio() {
+                let image_source_mid = load_preview(
+                    app.items_list.as_ref().unwrap().first().unwrap()
+                )?;
+                self.image_mid = Some(picker.new_resize_protocol(image_source_mid));
+                // and the next
+                let image_source_right = load_preview(
+                    &app.similarity_analyzer
+                            .as_ref()
+                            .unwrap()
+                            .get_one_file_similarity(&app.items_list.as_ref().unwrap()[self.selected_button])
+                            .iter()
+                            .filter(|(path, _)| path != &app.items_list.as_ref().unwrap()[self.selected_button])
+                            .collect::<Vec<_>>()
+                            [self.selected_button_2].0 // read from the tuple
+                    )?;
+                self.image_right = Some(picker.new_resize_protocol(image_source_right));
+            }
+        }
+
+        app.current_screen = CurrentScreen::Main; // change screen
+
         Ok(())
     }
 
@@ -133,11 +425,15 @@ impl UI {
                 if poll(POLL_DURATION)? {
                     let event = read()?;
                     if let Event::Key(key) = event {
-                        match key.code {
-                            KeyCode::Char('q') => {
+                        match app.settings.keys.action(key.code, &keys::EXPLORER) {
+                            Some(Action::Quit) => {
                                 app.stop();
                             }
-                            KeyCode::Char('c') => {
+                            Some(Action::Log) => {
+                                self.open_log(app);
+                                return Ok(())
+                            }
+                            Some(Action::SelectFolder) => {
                                 // get selected element
                                 let selected = self.file_explorer.current();
                                 if selected.is_dir() {
@@ -157,36 +453,72 @@ impl UI {
             },
 
             CurrentScreen::ChooseAnAlgorithm => {
-                draw(f, self.button_states);
+                draw(f, self.button_states, app);
                 if poll(POLL_DURATION)? {
                     if let Event::Key(key) = read()? {
                         if handle_key_event(key, &mut self.button_states, &mut self.selected_button, app).is_break() {
                             app.stop();
                         }
-                        if key.kind == KeyEventKind::Press && key.code == KeyCode::Esc {
-                            app.current_screen = CurrentScreen::FolderChoose;
-                            return Ok(())
+                        match app.settings.keys.action(key.code, &keys::CHOOSER) {
+                            Some(Action::Back) if key.kind == KeyEventKind::Press => {
+                                app.current_screen = CurrentScreen::FolderChoose;
+                                return Ok(())
+                            }
+                            Some(Action::Log) if key.kind == KeyEventKind::Press => {
+                                self.open_log(app);
+                                return Ok(())
+                            }
+                            _ => {}
                         }
                     }
 
-                    if app.hashing_type.is_some() {                        
+                    if let Some(hashing_type) = app.hashing_type {
+                        let dir_path = app.dir_path.as_ref().unwrap().clone();
+                        let preprocess_options = app.preprocess_options;
+                        let hashing_options = app.hashing_options;
+                        let match_options = app.match_options;
+
+                        // hashes for this folder are already known - only distances change
+                        if let Some(analyzer) = app.similarity_analyzer.as_mut()
+                            && analyzer.is_valid_for(&dir_path, &preprocess_options, &hashing_options) {
+                                let time_start = Instant::now();
+                                analyzer.analyze(hashing_type, &match_options);
+                                app.time_elapsed = format!(" Time: {:.2}s ", time_start.elapsed().as_secs_f32());
+                                return self.show_results(app);
+                            }
+
+                        let scan = ScanOptions {
+                            options: preprocess_options,
+                            hashing_options,
+                            tags: app.settings.tags,
+                            filter: app.settings.scan_filter.clone(),
+                        };
+
                         // initialize similarity_analyzer in a different thread - nonblocking
-                        // create a channel
+                        // create channels for the result and for the progress
                         let (tx, rx) = channel();
-                        let dir_path = app.dir_path.as_ref().unwrap().clone();
-                        let hashing_type = app.hashing_type.unwrap();
+                        let (progress_tx, progress_rx) = channel();
+                        let cancel_token = CancellationToken::new();
+                        let thread_token = cancel_token.clone();
 
                         // save time stamp
                         app.time_start = Some(Instant::now());
                         
                         // spawn the thread (with error propagation)
                         spawn(move || {
-                            let result = SimilarityAnalyzer::new(dir_path, hashing_type);
+                            let result = SimilarityAnalyzer::new(dir_path, &scan, Some(progress_tx), &thread_token)
+                                .map(|mut analyzer| {
+                                    analyzer.analyze(hashing_type, &match_options);
+                                    analyzer
+                                });
                             tx.send(result).ok();
                         });
 
-                        // store the receiver in app
+                        // store the receivers in app
                         self.similarity_analyzer_rx = Some(rx);
+                        self.progress_rx = Some(progress_rx);
+                        self.progress = Progress::new();
+                        self.cancel_token = Some(cancel_token);
                         
                         // change screen
                         app.current_screen = CurrentScreen::Calculating;
@@ -197,12 +529,25 @@ impl UI {
 
             CurrentScreen::Calculating => {
                 let area = f.area();
-                let time = Line::from(format!(" Time: {:.2}s ", app.time_start.unwrap().elapsed().as_secs_f32())).yellow();
-                let title = Line::from(format!(" Selected algorithm: {} ", app.hashing_type.unwrap()).bold()).green();
+                let colors = app.settings.theme;
+                let time = Line::from(format!(" Time: {:.2}s ", app.time_start.unwrap().elapsed().as_secs_f32())).fg(colors.highlight);
+                let title = Line::from(format!(" Selected algorithm: {} ", app.hashing_type.unwrap()).bold()).fg(colors.similar);
                 let instructions = Line::from(vec![
+                    " Log ".into(),
+                    app.settings.hint(Action::Log),
+                    " Cancel ".into(),
+                    app.settings.hint(Action::Back),
                     " Quit ".into(),
-                    "<Q> ".blue().bold(),
+                    app.settings.hint(Action::Quit),
+                    " ".into(),
                 ]);
+
+                // collect everything the hashing thread reported since the last frame
+                if let Some(rx) = &self.progress_rx {
+                    for event in rx.try_iter() {
+                        self.progress.apply(event);
+                    }
+                }
                 
                 // Frame
                 let block = Block::bordered()
@@ -217,10 +562,17 @@ impl UI {
                     Constraint::Percentage(50), // top padding
                     Constraint::Length(7),      // height of the large text
                     Constraint::Length(2),      // spacing
+                    Constraint::Length(1),      // progress gauge
+                    Constraint::Length(1),      // spacing
                     Constraint::Length(2),      // height of the small text
                     Constraint::Percentage(35), // bottom padding
                 ]);
-                let [_, word_area, _, info_area, _] = vertical.areas(inner);
+                let [_, word_area, _, gauge_area, _, info_area, _] = vertical.areas(inner);
+                let [_, gauge_area, _] = Layout::horizontal([
+                    Constraint::Percentage(20),
+                    Constraint::Percentage(60),
+                    Constraint::Percentage(20),
+                ]).areas(gauge_area);
 
                 let word_str = "calculating";
                 let letter_spacing = 7.0;
@@ -249,79 +601,72 @@ impl UI {
                     word_area,
                 );
 
-                let info = Paragraph::new("Please wait...")
+                let progress = &self.progress;
+                let gauge = Gauge::default()
+                    .gauge_style(Style::default().fg(colors.similar))
+                    .ratio(progress.ratio())
+                    .label(format!("{}/{}", progress.done(), progress.discovered));
+                f.render_widget(gauge, gauge_area);
+
+                let eta = progress.eta()
+                    .map(|eta| format!("{:.0}s", eta.as_secs_f32()))
+                    .unwrap_or_else(|| "-".to_string());
+                let current = progress.current
+                    .as_ref()
+                    .and_then(|path| path.file_name())
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| "Please wait...".to_string());
+                let info = Paragraph::new(vec![
+                    Line::from(format!("Hashed: {}  Failed: {}  ETA: {}", progress.hashed, progress.failed, eta)),
+                    Line::from(current),
+                ])
                     .alignment(Alignment::Center)
-                    .style(Style::default().fg(Color::Gray));
+                    .style(Style::default().fg(colors.muted));
                 f.render_widget(info, info_area);
 
                 // Frame render
                 f.render_widget(block, area);
 
                 if poll(POLL_DURATION)?
-                    && let Event::Key(key) = read()?
-                        && key.code == KeyCode::Char('q') {
-                            app.stop();
+                    && let Event::Key(key) = read()? {
+                        match app.settings.keys.action(key.code, &keys::CALCULATING) {
+                            Some(Action::Quit) => app.stop(),
+                            Some(Action::Log) if key.kind == KeyEventKind::Press => {
+                                // hashing goes on, its progress is picked up on the way back
+                                self.open_log(app);
+                                return Ok(())
+                            }
+                            Some(Action::Back) if key.kind == KeyEventKind::Press => {
+                                // the thread stops on its own, its result is dropped with the receiver
+                                if let Some(token) = self.cancel_token.take() {
+                                    token.cancel();
+                                }
+                                self.similarity_analyzer_rx = None;
+                                self.progress_rx = None;
+                                app.hashing_type = None;
+                                app.time_start = None;
+
+                                app.current_screen = CurrentScreen::ChooseAnAlgorithm;
+                                return Ok(())
+                            }
+                            _ => {}
                         }
+                    }
 
                 // check whether an analyzer is initializated
                 if let Some(rx) = &self.similarity_analyzer_rx
                     && let std::result::Result::Ok(analyzer) = rx.try_recv() {
                         app.time_elapsed = format!(" Time: {:.2}s ", app.time_start.unwrap().elapsed().as_secs_f32());
+                        if let Err(err) = &analyzer {
+                            error!(error = %err, "hashing the folder failed");
+                        }
                         app.similarity_analyzer = Some(analyzer?);
-
-                        self.similarity_analyzer_rx = None; // free
-                        self.selected_button = 0; // re-use
                         app.time_start = None;
 
-                        let mut items: Vec<PathBuf> = app.similarity_analyzer
-                            .as_ref()
-                            .unwrap()
-                            .similarity_map
-                            .keys()
-                            .cloned()
-                            .collect();
-
-                        if items.len() < 2 { // not enough files
-                            self.selected_button = 0;
-                            app.hashing_type = None;
-                            app.similarity_analyzer = None;
-                            app.time_start = None;
-                            app.items_list = None;
-
-                            app.current_screen = CurrentScreen::FolderChoose;
-                            return Ok(())
-                        }
-
-                        items.sort();
-                        app.items_list = Some(items); // to keep consistent list in every iteration
-
-                        // initalize structures for photo preview
-                        // disabled on windows, won't fail on other unsupported terminals
-                        #[cfg(not(target_os = "windows"))]
-                        {
-                            if let std::result::Result::Ok(picker) = Picker::from_query_stdio() {
-                                let image_source_mid = ImageReader::open(
-                                    app.items_list.as_ref().unwrap().first().unwrap()
-                                )?.decode()?;
-                                self.image_mid = Some(picker.new_resize_protocol(image_source_mid));
-                                // and the next
-                                let image_source_right = ImageReader::open(
-                                    &app.similarity_analyzer
-                                            .as_ref()
-                                            .unwrap()
-                                            .get_one_file_similarity(&app.items_list.as_ref().unwrap()[self.selected_button])
-                                            .iter()
-                                            .filter(|(path, _)| path != &app.items_list.as_ref().unwrap()[self.selected_button])
-                                            .collect::<Vec<_>>()
-                                            [self.selected_button_2].0 // read from the tuple
-                                    )?.decode()?;
-                                self.image_right = Some(picker.new_resize_protocol(image_source_right));
-                            }
-                        }
-
-                        app.current_screen = CurrentScreen::Main; // change screen
-
-                        return Ok(())
+                        self.similarity_analyzer_rx = None; // free
+                        self.progress_rx = None;
+                        self.cancel_token = None;
+                        return self.show_results(app);
                     }
             },
             
@@ -338,15 +683,19 @@ impl UI {
                         self.image_right = Some(protocol);
                         self.image_right_rx = None;
                     }
-                
+                self.apply_watch_changes(app)?;
+                if !matches!(app.current_screen, CurrentScreen::Main) {
+                    return Ok(())
+                }
+
                 draw_list(f, app, self);
 
                 if poll(POLL_DURATION)?
                     && let Event::Key(key) = read()?
                         && key.kind == KeyEventKind::Press {
-                            match key.code {
-                                KeyCode::Char('q') => app.stop(),
-                                KeyCode::Char('j') | KeyCode::Down => {
+                            match app.settings.keys.action(key.code, &keys::RESULTS) {
+                                Some(Action::Quit) => app.stop(),
+                                Some(Action::Down) => {
                                     if self.selected_column == 0 {
                                         self.selected_button = (self.selected_button + 1) % app.similarity_analyzer.as_ref().unwrap().similarity_map.len();
                                         self.selected_button_2 = 0;
@@ -355,7 +704,7 @@ impl UI {
                                             self.start_async_image_load(app.items_list.as_ref().unwrap()[self.selected_button].clone(), ImageTarget::Mid)?;
                                         }
                                     }
-                                    else {
+                                    else if self.files_num_column_1 > 0 {
                                         self.selected_button_2 = (self.selected_button_2 + 1) % self.files_num_column_1;
                                         // set a new image
                                         if self.image_mid.is_some() {
@@ -363,7 +712,7 @@ impl UI {
                                         }
                                     }
                                 },
-                                KeyCode::Char('k') | KeyCode::Up => {
+                                Some(Action::Up) => {
                                     if self.selected_column == 0 {
                                         let max = app.similarity_analyzer.as_ref().unwrap().similarity_map.len();
                                         self.selected_button = (self.selected_button + max - 1) % max;
@@ -373,7 +722,7 @@ impl UI {
                                             self.start_async_image_load(app.items_list.as_ref().unwrap()[self.selected_button].clone(), ImageTarget::Mid)?;
                                         }
                                     }
-                                    else {
+                                    else if self.files_num_column_1 > 0 {
                                         self.selected_button_2 = (self.selected_button_2 + self.files_num_column_1 - 1) % self.files_num_column_1;
                                         // set a new image
                                         if self.image_mid.is_some() {
@@ -381,28 +730,82 @@ impl UI {
                                         }
                                     }
                                 },
-                                KeyCode::Char('h') | KeyCode::Left => {
+                                Some(Action::Rule) => {
+                                    app.composite_rule.combinator = app.composite_rule.combinator.next();
+                                }
+                                Some(Action::Display) => {
+                                    app.score_options.display = app.score_options.display.next();
+                                }
+                                Some(Action::Filter) => {
+                                    // none -> distance -> percent -> none
+                                    app.score_options.filter = match app.score_options.filter {
+                                        None => Some(ScoreFilter::MaxDistance(app.settings.max_distance)),
+                                        Some(ScoreFilter::MaxDistance(_)) => Some(ScoreFilter::MinSimilarity(app.settings.min_similarity)),
+                                        Some(ScoreFilter::MinSimilarity(_)) => None,
+                                    };
+                                    self.reset_second_column(app)?;
+                                }
+                                Some(Action::Loosen) => {
+                                    app.score_options.filter = app.score_options.filter.map(ScoreFilter::loosen);
+                                    self.reset_second_column(app)?;
+                                }
+                                Some(Action::Tighten) => {
+                                    app.score_options.filter = app.score_options.filter.map(ScoreFilter::tighten);
+                                    self.reset_second_column(app)?;
+                                }
+                                Some(Action::Order) => {
+                                    app.score_options.order = app.score_options.order.next();
+                                    self.reset_second_column(app)?;
+                                }
+                                Some(Action::Export) => self.export(app),
+                                Some(Action::SaveIndex) => self.save_index(app),
+                                Some(Action::Watch) => self.toggle_watch(app),
+                                Some(Action::Log) => self.open_log(app),
+                                Some(Action::Left) => {
                                     self.selected_column = self.selected_column.saturating_sub(1);
                                 }
-                                KeyCode::Char('l') | KeyCode::Right => {
+                                Some(Action::Right) => {
                                     self.selected_column = max(self.selected_column + 1, 1);
                                 }
-                                KeyCode::Esc => {
-                                    self.image_right_rx = None;
-                                    self.image_right = None;
-                                    self.image_mid_rx = None;
-                                    self.image_mid = None;
-                                    self.files_num_column_1 = 0;
-                                    self.selected_column = 0;
-                                    self.selected_button_2 = 0;
-                                    self.selected_button = 0;
-                                    app.hashing_type = None;
-                                    app.similarity_analyzer = None;
-                                    app.time_start = None;
-                                    app.items_list = None;
+                                Some(Action::Back) => {
+                                    self.stop_watching();
+                                    self.leave_results(app);
+                                }
+                                _ => {}
+                            }
+                        }
+            }
 
-                                    app.current_screen = CurrentScreen::ChooseAnAlgorithm;
+            CurrentScreen::Log => {
+                draw_log(f, app, self);
+
+                if poll(POLL_DURATION)?
+                    && let Event::Key(key) = read()?
+                        && key.kind == KeyEventKind::Press {
+                            let last_top = self.log_lines.saturating_sub(self.log_height);
+                            let top = self.log_scroll.unwrap_or(last_top);
+                            // back at the end, the newest lines are followed again
+                            let scroll_to = |top: usize| (top < last_top).then_some(top);
+                            match (app.settings.keys.action(key.code, &keys::LOG), key.code) {
+                                (Some(Action::Quit), _) => app.stop(),
+                                (Some(Action::Back | Action::Log), _) => app.current_screen = self.log_return,
+                                (Some(Action::Up), _) => self.log_scroll = scroll_to(top.saturating_sub(1)),
+                                (Some(Action::Down), _) => self.log_scroll = scroll_to(top + 1),
+                                (Some(Action::Filter), _) => {
+                                    // everything -> debug -> info -> warn -> error -> everything
+                                    self.log_level = match self.log_level {
+                                        Level::TRACE => Level::DEBUG,
+                                        Level::DEBUG => Level::INFO,
+                                        Level::INFO => Level::WARN,
+                                        Level::WARN => Level::ERROR,
+                                        _ => Level::TRACE,
+                                    };
+                                    self.log_scroll = None;
                                 }
+                                (_, KeyCode::PageUp) => self.log_scroll = scroll_to(top.saturating_sub(self.log_height)),
+                                (_, KeyCode::PageDown) => self.log_scroll = scroll_to(top + self.log_height),
+                                (_, KeyCode::Home) => self.log_scroll = scroll_to(0),
+                                (_, KeyCode::End) => self.log_scroll = None,
                                 _ => {}
                             }
                         }
@@ -411,4 +814,11 @@ impl UI {
 
         Ok(())
     }
+
+    // the log screen starts at the newest lines and goes back to the current screen
+    fn open_log(&mut self, app: &mut App) {
+        self.log_return = app.current_screen;
+        self.log_scroll = None;
+        app.current_screen = CurrentScreen::Log;
+    }
 }
\ No newline at end of file
@@ -1,11 +1,13 @@
 use std::ops::ControlFlow;
 use super::letters::*;
 use crate::app::App;
-use crate::handler::handle::HashingType;
+use crate::keys::{self, Action};
+use perceptual_hashing::handler::handle::HashingType;
+use perceptual_hashing::hashing::pdq_hash::MIN_QUALITY;
 
 use ratatui::{
     buffer::Buffer,
-    crossterm::{event::{self, KeyCode}},
+    crossterm::event,
     layout::{Constraint, Layout, Rect},
     style::{Color, Style, Stylize},
     text::{Line},
@@ -44,6 +46,11 @@ const BLUE: ButtonTheme = ButtonTheme {
     shadow: Color::Rgb(32, 48, 96),
 };
 
+// number of buttons, one per algorithm
+pub const BUTTONS: usize = HashingType::ALL.len();
+// buttons in a single row
+const BUTTONS_PER_ROW: usize = 4;
+
 const RED: ButtonTheme = ButtonTheme {
     text: Color::Rgb(48, 16, 16),
     background: Color::Rgb(144, 48, 48),
@@ -51,6 +58,15 @@ const RED: ButtonTheme = ButtonTheme {
     shadow: Color::Rgb(96, 32, 32),
 };
 
+const GREEN: ButtonTheme = ButtonTheme {
+    text: Color::Rgb(16, 48, 16),
+    background: Color::Rgb(48, 144, 48),
+    highlight: Color::Rgb(64, 192, 64),
+    shadow: Color::Rgb(32, 96, 32),
+};
+
+const THEMES: [ButtonTheme; 3] = [RED, BLUE, GREEN];
+
 /// A button with a label that can be button_themed.
 impl<'a> Button<'a> {
     pub fn new<T: Into<Line<'a>>>(label: T) -> Self {
@@ -119,23 +135,57 @@ impl Button<'_> {
 
 pub fn handle_key_event(
     key: event::KeyEvent,
-    button_states: &mut [State; 2],
+    button_states: &mut [State; BUTTONS],
     selected_button: &mut usize,
     app: &mut App
 ) -> ControlFlow<()> {
-    match key.code {
-        KeyCode::Char('q') => return ControlFlow::Break(()),
-        KeyCode::Left | KeyCode::Char('h') => {
+    let Some(action) = app.settings.keys.action(key.code, &keys::CHOOSER) else {
+        return ControlFlow::Continue(());
+    };
+    match action {
+        Action::Quit => return ControlFlow::Break(()),
+        Action::Left => {
             button_states[*selected_button] = State::Normal;
             *selected_button = selected_button.saturating_sub(1);
             button_states[*selected_button] = State::Selected;
         }
-        KeyCode::Right | KeyCode::Char('l') => {
+        Action::Right => {
+            button_states[*selected_button] = State::Normal;
+            *selected_button = selected_button.saturating_add(1).min(BUTTONS - 1);
+            button_states[*selected_button] = State::Selected;
+        }
+        Action::Up => {
             button_states[*selected_button] = State::Normal;
-            *selected_button = selected_button.saturating_add(1).min(1);
+            *selected_button = selected_button.saturating_sub(BUTTONS_PER_ROW);
             button_states[*selected_button] = State::Selected;
         }
-        KeyCode::Enter => {
+        Action::Down => {
+            button_states[*selected_button] = State::Normal;
+            *selected_button = selected_button.saturating_add(BUTTONS_PER_ROW).min(BUTTONS - 1);
+            button_states[*selected_button] = State::Selected;
+        }
+        Action::Alpha => {
+            app.preprocess_options.alpha = app.preprocess_options.alpha.next();
+        }
+        Action::Decode => {
+            app.preprocess_options.decode = app.preprocess_options.decode.next();
+        }
+        Action::Color => {
+            app.hashing_options.color = app.hashing_options.color.next();
+        }
+        Action::Quality => {
+            app.match_options.min_quality = match app.match_options.min_quality {
+                Some(_) => None,
+                None => Some(app.settings.match_options.min_quality.unwrap_or(MIN_QUALITY)),
+            };
+        }
+        Action::Sampling => {
+            app.preprocess_options.frames = app.preprocess_options.frames.next();
+        }
+        Action::FrameMatch => {
+            app.match_options.frames = app.match_options.frames.next();
+        }
+        Action::Choose => {
             if button_states[*selected_button] == State::Active {
                 button_states[*selected_button] = State::Normal;
             } else {
@@ -143,24 +193,44 @@ pub fn handle_key_event(
                 app.hashing_type = HashingType::from_index(*selected_button);
             }
         }
+        // handled by the screen
         _ => (),
     }
     ControlFlow::Continue(())
 }
 
 
-pub fn draw(frame: &mut Frame, states: [State; 2]) {
+pub fn draw(frame: &mut Frame, states: [State; BUTTONS], app: &App) {
     let area = frame.area();
     let title = Line::from(" Select hashing algorithm ".bold());
+    let hint = |action| app.settings.hint(action);
     let instructions = Line::from(vec![
         " Select ".into(),
-        "←/→".blue().bold(),
+        "←/→/↑/↓".fg(app.settings.theme.keys).bold(),
         " Toggle ".into(),
-        "<Enter>".blue().bold(),
+        hint(Action::Choose),
+        format!(" Alpha: {} ", app.preprocess_options.alpha).into(),
+        hint(Action::Alpha),
+        format!(" Decode: {} ", app.preprocess_options.decode).into(),
+        hint(Action::Decode),
+        format!(" Color: {} ", app.hashing_options.color).into(),
+        hint(Action::Color),
+        match app.match_options.min_quality {
+            Some(min_quality) => format!(" PDQ quality: ≥{min_quality} "),
+            None => " PDQ quality: any ".to_string(),
+        }.into(),
+        hint(Action::Quality),
+        format!(" Frames: {} ", app.preprocess_options.frames).into(),
+        hint(Action::Sampling),
+        format!(" Match: {} ", app.match_options.frames).into(),
+        hint(Action::FrameMatch),
+        " Log ".into(),
+        hint(Action::Log),
         " Back ".into(),
-        "<Esc>".blue().bold(),
+        hint(Action::Back),
         " Quit ".into(),
-        "<Q> ".blue().bold(),
+        hint(Action::Quit),
+        " ".into(),
     ]);
     
     // Frame with title
@@ -170,24 +240,26 @@ pub fn draw(frame: &mut Frame, states: [State; 2]) {
         .border_set(border::THICK);
     let inner = block.inner(area);
     
+    let rows = BUTTONS.div_ceil(BUTTONS_PER_ROW);
+
     // Layout: [top_padding][text][middle_padding][buttons][bottom_padding]
     let vertical = Layout::vertical([
         Constraint::Percentage(25), // top padding - centers the text vertically
         Constraint::Length(7),      // height for the text made of letters
         Constraint::Percentage(25), // middle padding between text and buttons
-        Constraint::Length(5),      // height for the buttons
+        Constraint::Length(5 * rows as u16), // height for the buttons
         Constraint::Percentage(25), // bottom padding
     ]);
     let [_, word_area, _, buttons_area, _] = vertical.areas(inner);
     
     // Centered and scalable buttons according to window width
     let horizontal = Layout::horizontal([
-        Constraint::Percentage(25),
-        Constraint::Percentage(25),
-        Constraint::Percentage(25),
-        Constraint::Percentage(25),
+        Constraint::Percentage(10),
+        Constraint::Percentage(80),
+        Constraint::Percentage(10),
     ]);
-    let [_, left_btn_area, right_btn_area, _] = horizontal.areas(buttons_area);
+    let [_, buttons_area, _] = horizontal.areas(buttons_area);
+    let row_areas = Layout::vertical(vec![Constraint::Length(5); rows]).split(buttons_area);
     
     // Frame render
     frame.render_widget(block, area);
@@ -222,12 +294,18 @@ pub fn draw(frame: &mut Frame, states: [State; 2]) {
     );
     
     // Button render
-    frame.render_widget(
-        Button::new("dHash").button_theme(RED).state(states[0]),
-        left_btn_area,
-    );
-    frame.render_widget(
-        Button::new("pHash").button_theme(BLUE).state(states[1]),
-        right_btn_area,
-    );
-}
\ No newline at end of file
+    for (row, row_area) in row_areas.iter().enumerate() {
+        let btn_areas = Layout::horizontal(vec![Constraint::Ratio(1, BUTTONS_PER_ROW as u32); BUTTONS_PER_ROW])
+            .spacing(1)
+            .split(*row_area);
+        for (column, btn_area) in btn_areas.iter().enumerate() {
+            let i = row * BUTTONS_PER_ROW + column;
+            if let Some(hashing_type) = HashingType::from_index(i) {
+                frame.render_widget(
+                    Button::new(hashing_type.to_string()).button_theme(THEMES[i % THEMES.len()]).state(states[i]),
+                    *btn_area,
+                );
+            }
+        }
+    }
+}
@@ -8,7 +8,18 @@ use ratatui::{
 };
 use ratatui_image::StatefulImage;
 
-use crate::{app::App, ui::UI};
+use std::path::Path;
+
+use crate::{app::App, keys::Action, ui::UI};
+use perceptual_hashing::handler::handle::HashingType;
+use perceptual_hashing::handler::score::ScoreDisplay;
+use perceptual_hashing::handler::frames::FrameMatch;
+use perceptual_hashing::hashing::segments;
+
+// relative to the chosen folder, so that archive entries show as `photos.zip!/inner/a.jpg`
+fn display_name<'a>(path: &'a Path, app: &App) -> std::borrow::Cow<'a, str> {
+    path.strip_prefix(app.dir_path.as_ref().unwrap()).unwrap_or(path).to_string_lossy()
+}
 
 pub fn draw_list(frame: &mut Frame, app: &mut App, ui: &mut UI) {
     use ratatui::layout::{Layout, Constraint, Direction};
@@ -21,20 +32,45 @@ pub fn draw_list(frame: &mut Frame, app: &mut App, ui: &mut UI) {
         .constraints([Constraint::Percentage(33), Constraint::Percentage(34), Constraint::Percentage(33)])
         .split(area);
 
-    let title0 = Line::from(format!(" {} ", app.dir_path.as_ref().unwrap().to_str().unwrap().bold())).blue();
-    let title1 = Line::from(format!(" {} ", app.hashing_type.as_ref().unwrap()).bold()).green();
-    let title2 = Line::from(app.time_elapsed.as_str().bold()).yellow();
+    let (colors, hint) = (app.settings.theme, |action| app.settings.hint(action));
+    let title0 = Line::from(format!(" {} ", app.dir_path.as_ref().unwrap().to_str().unwrap().bold())).fg(colors.selection);
+    let title1 = Line::from(format!(" {} ", app.hashing_type.as_ref().unwrap()).bold()).fg(colors.similar);
+    let title2 = Line::from(app.time_elapsed.as_str().bold()).fg(colors.highlight);
 
     let instructions0 = Line::from(vec![
         " Select ".into(),
-        "↑/↓ ".blue().bold(),
-        "←/→ ".blue().bold(),
+        "↑/↓ ".fg(colors.keys).bold(),
+        "←/→ ".fg(colors.keys).bold(),
+        format!(" Rule: {} ", app.composite_rule.combinator).into(),
+        hint(Action::Rule),
+        " ".into(),
+    ]);
+    let instructions1 = Line::from(vec![
+        format!(" Show: {} ", app.score_options.display).into(),
+        hint(Action::Display),
+        " Filter ".into(),
+        hint(Action::Filter),
+        " ".into(),
+        hint(Action::Loosen),
+        hint(Action::Tighten),
+        " Order ".into(),
+        hint(Action::Order),
+        " Export ".into(),
+        hint(Action::Export),
+        " Index ".into(),
+        hint(Action::SaveIndex),
+        " ".into(),
     ]);
     let instructions2 = Line::from(vec![
+        format!(" Watch: {} ", if ui.is_watching() { "on" } else { "off" }).into(),
+        hint(Action::Watch),
+        " Log ".into(),
+        hint(Action::Log),
         " Back ".into(),
-        "<Esc>".blue().bold(),
+        hint(Action::Back),
         " Quit ".into(),
-        "<Q> ".blue().bold()
+        hint(Action::Quit),
+        " ".into(),
     ]);
 
     // list in block0
@@ -43,14 +79,14 @@ pub fn draw_list(frame: &mut Frame, app: &mut App, ui: &mut UI) {
     state0.select(Some(ui.selected_button));
 
     let list0 = List::new(
-        app.items_list.as_ref().unwrap().iter().map(|x| x.file_name().unwrap().to_str().unwrap())
+        app.items_list.as_ref().unwrap().iter().map(|x| display_name(x, app))
         ).block(
             Block::bordered()
                 .title(title0.left_aligned())
                 .title_bottom(instructions0.left_aligned())
                 .border_set(border::THICK)
         )
-        .highlight_style(Style::default().bg(ratatui::style::Color::Blue).fg(ratatui::style::Color::White))
+        .highlight_style(Style::default().bg(colors.selection).fg(ratatui::style::Color::White))
         .highlight_symbol(">> ");
 
     frame.render_stateful_widget(list0, chunks[0], &mut state0);
@@ -58,20 +94,44 @@ pub fn draw_list(frame: &mut Frame, app: &mut App, ui: &mut UI) {
 
     // list in block1
     
-    let items1 = app.similarity_analyzer
-        .as_ref()
-        .unwrap()
-        .get_one_file_similarity(&app.items_list.as_ref().unwrap()[ui.selected_button]);
+    let analyzer = app.similarity_analyzer.as_ref().unwrap();
+    let selected = &app.items_list.as_ref().unwrap()[ui.selected_button];
+    let items1 = analyzer.similar_files(selected, &app.score_options);
 
     let mut state1 = ListState::default();
     state1.select(Some(ui.selected_button_2));
 
-    let desc = Line::from(" Distance -> Filename ".bold());
+    // segment hashes report the share of their segments that found a counterpart instead of a distance
+    let segments = app.hashing_type == Some(HashingType::Segments);
+    let unit = match (segments, app.score_options.display) {
+        (_, ScoreDisplay::Percent) => "Similarity",
+        (true, _) => "Matching segments",
+        (false, _) => "Distance",
+    };
+    let filter = app.score_options.filter.map_or(String::new(), |filter| format!(", {filter}"));
+    let desc = Line::from(format!(" {unit} -> Filename ({}{filter}) ", app.score_options.order).bold());
 
     let list1 = List::new(
         items1.iter()
-        .filter(|(path, _)| path != &app.items_list.as_ref().unwrap()[ui.selected_button]) // not ot show duplicated file
-        .map(|(path, dist)| format!("{} -> {}", dist, path.file_name().unwrap().to_str().unwrap()))
+        .map(|scored| {
+            let name = display_name(&scored.path, app);
+            let distance = if segments {
+                format!("{}%", segments::MAX_DISTANCE.saturating_sub(scored.distance))
+            } else {
+                scored.distance.to_string()
+            };
+            let mut score = app.score_options.display.format(&distance, scored.similarity);
+            // where the similar clip starts relative to the selected one, in sampled frames
+            if let FrameMatch::Aligned { min_similarity } = app.match_options.frames
+                && let Some(alignment) = analyzer.alignment(selected, &scored.path, min_similarity) {
+                    score = format!("{score} @{:+}", alignment.offset);
+                }
+            // decision of the composite rule, e.g. "dHash 3 · pHash 5 ✓"
+            match analyzer.evaluate(&app.composite_rule, selected, &scored.path) {
+                Some(composite) => format!("{score} [{composite}] -> {name}"),
+                None => format!("{score} -> {name}"),
+            }
+        })
         )
         .block(
             Block::bordered()
@@ -79,10 +139,10 @@ pub fn draw_list(frame: &mut Frame, app: &mut App, ui: &mut UI) {
                 .title_bottom(desc.centered())
                 .border_set(border::THICK)
         )
-        .highlight_style(Style::default().bg(ratatui::style::Color::Green).fg(ratatui::style::Color::White))
+        .highlight_style(Style::default().bg(colors.similar).fg(ratatui::style::Color::White))
         .highlight_symbol(">> ");
 
-    ui.files_num_column_1 = items1.len() - 1; // without duplicated file
+    ui.files_num_column_1 = items1.len(); // the selected file itself is not listed
 
     frame.render_stateful_widget(list1, chunks[1], &mut state1);
     // end of list1
@@ -108,8 +168,11 @@ pub fn draw_list(frame: &mut Frame, app: &mut App, ui: &mut UI) {
     }
 
     // lower half
+    let status = Line::from(app.status.as_deref().map_or(String::new(), |status| format!(" {status} "))).fg(colors.highlight);
     let image_block = Block::bordered()
         .title(" Similar ")
+        .title(status.right_aligned())
+        .title_bottom(instructions1.left_aligned())
         .title_bottom(instructions2.right_aligned())
         .border_set(border::THICK);
     let image_area = image_block.inner(right_chunks[1]);
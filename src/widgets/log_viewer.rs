@@ -0,0 +1,60 @@
+use ratatui::{
+    style::{Color, Stylize},
+    text::{Line, Span},
+    widgets::{Block, Paragraph},
+    symbols::{border},
+    Frame
+};
+use tracing::Level;
+
+use crate::{app::App, keys::Action, ui::UI};
+
+fn level_color(level: Level, muted: Color) -> Color {
+    match level {
+        Level::ERROR => Color::Red,
+        Level::WARN => Color::Yellow,
+        Level::INFO => Color::Green,
+        _ => muted,
+    }
+}
+
+pub fn draw_log(frame: &mut Frame, app: &App, ui: &mut UI) {
+    let area = frame.area();
+    let (colors, hint) = (app.settings.theme, |action| app.settings.hint(action));
+
+    // more verbose levels compare greater
+    let lines: Vec<_> = app.log.lines().into_iter().filter(|line| line.level <= ui.log_level).collect();
+    let file = app.settings.log.dir.as_ref().map_or(" no log file ".to_string(), |dir| format!(" {} ", dir.display()));
+    let title = Line::from(" Log ".bold()).fg(colors.title);
+    let instructions = Line::from(vec![
+        " Scroll ".into(),
+        "↑/↓ PgUp/PgDn Home/End ".fg(colors.keys).bold(),
+        format!(" Level: {} ", ui.log_level.as_str().to_lowercase()).into(),
+        hint(Action::Filter),
+        " Back ".into(),
+        hint(Action::Back),
+        " Quit ".into(),
+        hint(Action::Quit),
+        " ".into(),
+    ]);
+    let block = Block::bordered()
+        .title(title.centered())
+        .title(Line::from(file).fg(colors.muted).right_aligned())
+        .title_bottom(instructions.centered())
+        .border_set(border::THICK);
+
+    // without a scroll position the newest lines are followed
+    let height = block.inner(area).height as usize;
+    let last_top = lines.len().saturating_sub(height);
+    let top = ui.log_scroll.map_or(last_top, |top| top.min(last_top));
+    ui.log_height = height;
+    ui.log_lines = lines.len();
+
+    let text: Vec<Line> = lines[top..].iter().take(height).map(|line| Line::from(vec![
+        Span::from(format!("{:>9.3}s ", line.elapsed.as_secs_f64())).fg(colors.muted),
+        Span::from(format!("{:<5} ", line.level)).fg(level_color(line.level, colors.muted)).bold(),
+        Span::from(format!("{}: ", line.target)).fg(colors.muted),
+        Span::from(line.text.as_str()),
+    ])).collect();
+    frame.render_widget(Paragraph::new(text).block(block), area);
+}
@@ -1,3 +1,4 @@
 pub mod algorithm_chooser;
 pub mod letters;
-pub mod list;
\ No newline at end of file
+pub mod list;
+pub mod log_viewer;
\ No newline at end of file
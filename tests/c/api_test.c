@@ -0,0 +1,125 @@
+/* Exercises the C API: run by tests/c_api.rs with two different images and a path to write an index to. */
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "perceptual_hashing.h"
+
+#define CHECK(condition) \
+    do { \
+        if (!(condition)) { \
+            const char *error = ph_last_error(); \
+            fprintf(stderr, "%s:%d: %s failed (%s)\n", __FILE__, __LINE__, #condition, error ? error : "no error"); \
+            return 1; \
+        } \
+    } while (0)
+
+static unsigned char *read_file(const char *path, size_t *len) {
+    FILE *file = fopen(path, "rb");
+    if (!file) {
+        return NULL;
+    }
+    fseek(file, 0, SEEK_END);
+    *len = (size_t)ftell(file);
+    fseek(file, 0, SEEK_SET);
+    unsigned char *data = malloc(*len);
+    if (data && fread(data, 1, *len, file) != *len) {
+        free(data);
+        data = NULL;
+    }
+    fclose(file);
+    return data;
+}
+
+static int hashes(const char *image, const char *other) {
+    PhHash *hash = NULL, *parsed = NULL, *from_buffer = NULL, *different = NULL, *phash = NULL;
+    uint32_t distance = 0;
+
+    CHECK(ph_hash_file(image, PH_ALGORITHM_DHASH, &hash) == PH_STATUS_OK);
+    CHECK(ph_hash_algorithm(hash) == PH_ALGORITHM_DHASH);
+    CHECK(ph_hash_algorithm(NULL) == PH_NO_ALGORITHM && ph_last_error() != NULL);
+    char *hex = ph_hash_to_hex(hash);
+    CHECK(hex != NULL && strlen(hex) == 16);
+    CHECK(ph_hash_from_hex(PH_ALGORITHM_DHASH, hex, &parsed) == PH_STATUS_OK);
+    CHECK(ph_distance(hash, parsed, &distance) == PH_STATUS_OK && distance == 0);
+    ph_string_free(hex);
+
+    size_t len = 0;
+    unsigned char *data = read_file(image, &len);
+    CHECK(data != NULL);
+    CHECK(ph_hash_buffer(data, len, PH_ALGORITHM_DHASH, &from_buffer) == PH_STATUS_OK);
+    free(data);
+    CHECK(ph_distance(hash, from_buffer, &distance) == PH_STATUS_OK && distance == 0);
+
+    CHECK(ph_hash_file(other, PH_ALGORITHM_DHASH, &different) == PH_STATUS_OK);
+    CHECK(ph_distance(hash, different, &distance) == PH_STATUS_OK);
+    CHECK(distance > 0 && distance <= ph_max_distance(PH_ALGORITHM_DHASH));
+
+    CHECK(ph_hash_file(image, PH_ALGORITHM_PHASH, &phash) == PH_STATUS_OK);
+    CHECK(ph_distance(hash, phash, &distance) == PH_STATUS_INCOMPATIBLE);
+    CHECK(ph_last_error() != NULL);
+    CHECK(ph_hash_file(image, 42, &parsed) == PH_STATUS_INVALID_ARGUMENT);
+    CHECK(ph_hash_buffer((const uint8_t *)"no image", 8, PH_ALGORITHM_PDQ, &parsed) == PH_STATUS_DECODE);
+
+    ph_hash_free(hash);
+    ph_hash_free(parsed);
+    ph_hash_free(from_buffer);
+    ph_hash_free(different);
+    ph_hash_free(phash);
+    return 0;
+}
+
+static int indexes(const char *image, const char *other, const char *index_path) {
+    PhIndex *index = ph_index_new(), *loaded = NULL;
+    PhHash *query = NULL, *from_index = NULL;
+    PhMatch *matches = NULL;
+    size_t count = 0;
+
+    CHECK(ph_index_insert_file(index, image) == PH_STATUS_OK);
+    CHECK(ph_index_insert_file(index, other) == PH_STATUS_OK);
+    size_t len = 0;
+    unsigned char *data = read_file(image, &len);
+    CHECK(data != NULL);
+    CHECK(ph_index_insert_buffer(index, "uploads/copy.png", data, len) == PH_STATUS_OK);
+    free(data);
+    CHECK(ph_index_len(index) == 3);
+    CHECK(ph_index_len(NULL) == 0);
+
+    CHECK(ph_hash_file(image, PH_ALGORITHM_PHASH, &query) == PH_STATUS_OK);
+    CHECK(ph_index_query(index, query, 0, &matches, &count) == PH_STATUS_OK);
+    CHECK(count == 2);
+    CHECK(matches[0].distance == 0 && matches[1].distance == 0);
+    CHECK(strcmp(matches[0].path, image) == 0 || strcmp(matches[1].path, image) == 0);
+    ph_matches_free(matches, count);
+    CHECK(ph_index_hash_file(index, other, PH_ALGORITHM_PDQ, &from_index) == PH_STATUS_OK);
+    CHECK(ph_index_query(index, from_index, 0, &matches, &count) == PH_STATUS_OK);
+    CHECK(count == 1 && strcmp(matches[0].path, other) == 0);
+    ph_matches_free(matches, count);
+
+    CHECK(ph_index_remove(index, "uploads/copy.png") == PH_STATUS_OK);
+    CHECK(ph_index_remove(index, "uploads/copy.png") == PH_STATUS_NOT_FOUND);
+    CHECK(ph_index_save(index, index_path) == PH_STATUS_OK);
+    CHECK(ph_index_load(index_path, &loaded) == PH_STATUS_OK);
+    CHECK(ph_index_len(loaded) == 2);
+    CHECK(ph_index_query(loaded, query, ph_max_distance(PH_ALGORITHM_PHASH), &matches, &count) == PH_STATUS_OK);
+    CHECK(count == 2 && strcmp(matches[0].path, image) == 0);
+    ph_matches_free(matches, count);
+
+    ph_hash_free(query);
+    ph_hash_free(from_index);
+    ph_index_free(index);
+    ph_index_free(loaded);
+    return 0;
+}
+
+int main(int argc, char **argv) {
+    if (argc != 4) {
+        fprintf(stderr, "usage: %s IMAGE OTHER_IMAGE INDEX\n", argv[0]);
+        return 2;
+    }
+    if (hashes(argv[1], argv[2]) != 0 || indexes(argv[1], argv[2], argv[3]) != 0) {
+        return 1;
+    }
+    puts("ok");
+    return 0;
+}
@@ -0,0 +1,49 @@
+// Builds tests/c/api_test.c against include/perceptual_hashing.h and the cdylib of this crate,
+// then runs it. Needs a C compiler, `cc` or the one in $CC.
+#![cfg(unix)]
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// the cdylib is built next to the test executable, or one level up
+fn library_dir() -> PathBuf {
+    let exe = env::current_exe().unwrap();
+    let deps = exe.parent().unwrap();
+    let name = format!("{}perceptual_hashing{}", env::consts::DLL_PREFIX, env::consts::DLL_SUFFIX);
+    [deps, deps.parent().unwrap()]
+        .into_iter()
+        .find(|dir| dir.join(&name).exists())
+        .unwrap_or_else(|| panic!("{name} was not built"))
+        .to_path_buf()
+}
+
+#[test]
+fn c_program_uses_the_api() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let out = env::temp_dir().join(format!("perceptual_hashing_c_api_{}", std::process::id()));
+    std::fs::create_dir_all(&out).unwrap();
+    let (program, lib) = (out.join("api_test"), library_dir());
+
+    let compiler = env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let status = Command::new(&compiler)
+        .arg(root.join("tests/c/api_test.c"))
+        .arg("-I").arg(root.join("include"))
+        .arg("-L").arg(&lib)
+        .arg(format!("-Wl,-rpath,{}", lib.display()))
+        .args(["-Wall", "-Werror", "-o"]).arg(&program)
+        .arg("-lperceptual_hashing")
+        .status()
+        .unwrap_or_else(|err| panic!("running {compiler} failed: {err}"));
+    assert!(status.success(), "compiling the C test failed");
+
+    let output = Command::new(&program)
+        .current_dir(root)
+        .args(["test_images/test1.png", "test_images/test3.png"])
+        .arg(out.join("index.phix"))
+        .output()
+        .unwrap();
+    std::fs::remove_dir_all(&out).ok();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "ok\n");
+}